@@ -51,13 +51,197 @@ in
           description = "Whether to hide the mouse cursor when locked";
         };
 
+        transparentCursor = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Hide the mouse cursor with a transparent image, for compositors which ignore hiding it";
+        };
+
         backgroundType = mkOption {
           type = types.enum [
             "color"
             "image"
+            "none"
           ];
           default = "color";
-          description = "Either color or image background type";
+          description = "Background type, a color, an image, or none for plain black";
+        };
+
+        backgroundFormat = mkOption {
+          type = types.enum [
+            "argb"
+            "xrgb"
+            "auto"
+          ];
+          default = "argb";
+          description = "Preferred background buffer format, xrgb is only used for opaque backgrounds, auto picks xrgb whenever the background is opaque";
+        };
+
+        pamSession = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Whether to open a PAM session on unlock, e.g. to unlock keyrings";
+        };
+
+        maxAttempts = mkOption {
+          type = types.ints.unsigned;
+          default = 0;
+          description = "Failed attempts before the session can never be unlocked, 0 is unlimited";
+        };
+
+        lockoutMessage = mkOption {
+          type = types.str;
+          default = "Too many failed attempts";
+          description = "Message displayed when locked out";
+        };
+
+        authRestartLimit = mkOption {
+          type = types.ints.unsigned;
+          default = 3;
+          description = "Number of times to restart the authenticator if it stops";
+        };
+
+        waitForSurfaces = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Whether to render lock surfaces right after locking, avoiding a desktop flash";
+        };
+
+        relockLimit = mkOption {
+          type = types.ints.unsigned;
+          default = 0;
+          description = "Number of times to lock again if the compositor ends the lock unexpectedly";
+        };
+
+        maxBufferDimension = mkOption {
+          type = types.ints.positive;
+          default = 16384;
+          description = "Largest buffer width or height, in pixels, larger surfaces render at a lower scale";
+        };
+
+        outputs = {
+          include = mkOption {
+            type = types.listOf types.str;
+            default = [ ];
+            description = "Outputs to show the lock screen on, by name, description, or \"make model\", all if empty";
+          };
+
+          exclude = mkOption {
+            type = types.listOf types.str;
+            default = [ ];
+            description = "Outputs to only show a plain color on, by name, description, or \"make model\"";
+          };
+        };
+
+        margins = {
+          top = mkOption {
+            type = types.float;
+            default = 0.0;
+            description = "Space kept clear at the top of each output, in pixels";
+          };
+
+          right = mkOption {
+            type = types.float;
+            default = 0.0;
+            description = "Space kept clear at the right of each output, in pixels";
+          };
+
+          bottom = mkOption {
+            type = types.float;
+            default = 0.0;
+            description = "Space kept clear at the bottom of each output, in pixels";
+          };
+
+          left = mkOption {
+            type = types.float;
+            default = 0.0;
+            description = "Space kept clear at the left of each output, in pixels";
+          };
+        };
+
+        authTimeoutMs = mkOption {
+          type = types.ints.unsigned;
+          default = 0;
+          description = "Time to wait for PAM before giving up on an attempt, in milliseconds. 0 waits forever.";
+        };
+
+        secondaryMode = mkOption {
+          type = types.enum [
+            "full"
+            "background"
+            "blank"
+          ];
+          default = "full";
+          description = "What is shown on outputs other than the first: the full lock screen, only the background, or a plain color";
+        };
+
+        connectAttempts = mkOption {
+          type = types.ints.positive;
+          default = 5;
+          description = "Number of attempts to connect to the Wayland compositor, e.g. if started before it's ready";
+        };
+
+        connectRetryMs = mkOption {
+          type = types.ints.unsigned;
+          default = 100;
+          description = "Delay before the first connection retry, doubling with each retry, in milliseconds";
+        };
+
+        shmBackend = mkOption {
+          type = types.enum [
+            "memfd"
+            "posix"
+          ];
+          default = "memfd";
+          description = "How shared memory for buffers is created, memfd_create (falling back to posix), or shm_open under /dev/shm";
+        };
+
+        minSurfaceScale = mkOption {
+          type = types.float;
+          default = 0.25;
+          description = "Smallest factor the frame border and font shrink by on surfaces under 720 pixels, 1.0 disables shrinking";
+        };
+
+        auditLog = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Record unlock attempts, with the user and outcome, in the system log";
+        };
+
+        powerSave = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Only show the lock screen on the output last pointed at or focused, blanking the others";
+        };
+
+        lowMemory = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Use less memory, without a background image, caret blinking, key highlighting, spinner, or spare buffers";
+        };
+
+        prerender = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Render backgrounds before the compositor configures the lock surfaces, so they appear sooner after locking";
+        };
+
+        pixelSnap = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Round element positions and sizes to whole pixels, for crisper edges, at the cost of exact centering";
+        };
+
+        overlayFallback = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Draw the overlay onto the background surface if the compositor never releases overlay buffers, as some nested compositors do";
+        };
+
+        debugOverlay = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Show each output's name, size, scale, DPI and subpixel order in a corner, for troubleshooting scaling";
         };
       };
 
@@ -103,6 +287,48 @@ in
           default = "FF0000FF";
           description = "Frame border fail color";
         };
+
+        keyHighlight = mkOption {
+          type = types.str;
+          default = "33CC33FF";
+          description = "Input border color briefly shown on a keypress";
+        };
+
+        backspaceHighlight = mkOption {
+          type = types.str;
+          default = "CC3300FF";
+          description = "Input border color briefly shown on backspace";
+        };
+
+        spinner = mkOption {
+          type = types.str;
+          default = "FFFFFFFF";
+          description = "Spinner color";
+        };
+
+        prompt = mkOption {
+          type = types.str;
+          default = "FFFFFFFF";
+          description = "Prompt color";
+        };
+
+        textOutline = mkOption {
+          type = types.str;
+          default = "000000FF";
+          description = "Color of the outline drawn around text";
+        };
+
+        inputGlow = mkOption {
+          type = types.str;
+          default = "FFFFFF40";
+          description = "Color of the glow inside the input box edges";
+        };
+
+        vignette = mkOption {
+          type = types.str;
+          default = "000000FF";
+          description = "Color the background image darkens to at the edges, with a vignette";
+        };
       };
 
       font = {
@@ -124,6 +350,12 @@ in
           description = "Font family";
         };
 
+        fallback = mkOption {
+          type = types.listOf types.str;
+          default = [ ];
+          description = "Font families tried in order if the font family is not installed";
+        };
+
         slant = mkOption {
           type = types.enum [
             "normal"
@@ -142,6 +374,39 @@ in
           default = "normal";
           description = "Font weight";
         };
+
+        features = mkOption {
+          type = types.str;
+          default = "";
+          description = "OpenType font features, in CSS syntax, e.g. tnum, smcp";
+        };
+
+        antialias = mkOption {
+          type = types.enum [
+            "auto"
+            "subpixel"
+            "grayscale"
+            "none"
+          ];
+          default = "auto";
+          description = "Font anti-aliasing mode";
+        };
+
+        assumedDpi = mkOption {
+          type = types.float;
+          default = 0.0;
+          description = "DPI assumed for outputs without physical dimensions, 0 uses 96";
+        };
+
+        direction = mkOption {
+          type = types.enum [
+            "auto"
+            "ltr"
+            "rtl"
+          ];
+          default = "auto";
+          description = "Text direction, auto detects it from the text";
+        };
       };
 
       input = {
@@ -181,6 +446,12 @@ in
           description = "Width of input box border, absolute, typically pixels";
         };
 
+        innerGlow = mkOption {
+          type = types.float;
+          default = 0.0;
+          description = "Width of a glow inside the input box edges, absolute, typically pixels, 0 disables";
+        };
+
         visible = mkOption {
           type = types.enum [
             "never"
@@ -196,6 +467,125 @@ in
           default = false;
           description = "Whether to resize input box to fit password, up to width";
         };
+
+        echo = mkOption {
+          type = types.enum [
+            "none"
+            "mask"
+            "clear"
+            "blocks"
+          ];
+          default = "mask";
+          description = "How the entered password is echoed in the input box";
+        };
+
+        compose = mkOption {
+          type = types.bool;
+          default = true;
+          description = "Whether to support compose key and dead key sequences";
+        };
+
+        antialias = mkOption {
+          type = types.enum [
+            "best"
+            "good"
+            "fast"
+            "none"
+          ];
+          default = "best";
+          description = "Anti-aliasing mode of the input box edges";
+        };
+
+        caret = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Whether to draw a caret after the password";
+        };
+
+        caretBlinkMs = mkOption {
+          type = types.ints.unsigned;
+          default = 500;
+          description = "Caret blink interval, in milliseconds, 0 disables blinking";
+        };
+
+        overflow = mkOption {
+          type = types.enum [
+            "clip"
+            "ellipsis"
+            "count"
+          ];
+          default = "clip";
+          description = "How a masked password too long for the input box is shown: clipped, shortened with an ellipsis, or shortened with an ellipsis and the length";
+        };
+
+        height = mkOption {
+          type = types.float;
+          default = 0.0;
+          description = "Height of the input box text area, relative to display height, 0 fits the font";
+        };
+
+        wakeMs = mkOption {
+          type = types.ints.unsigned;
+          default = 3000;
+          description = "Time pointer or touch activity shows the input box when visible is \"content\", in milliseconds, 0 disables";
+        };
+
+        pamMaxLength = mkOption {
+          type = types.ints.unsigned;
+          default = 512;
+          description = "Longest password submitted to PAM, in bytes, longer passwords are rejected before authenticating, 0 disables the limit";
+        };
+
+        clearOnFail = mkOption {
+          type = types.bool;
+          default = true;
+          description = "Clear the input after a failed attempt, false restores the submitted password for editing";
+        };
+
+        numpadAlwaysDigits = mkOption {
+          type = types.bool;
+          default = true;
+          description = "Type digits with the numpad even when NumLock is off";
+        };
+
+        blockCount = mkOption {
+          type = types.ints.unsigned;
+          default = 8;
+          description = "Number of colored blocks shown with blocks echo, their colors change while typing, not their number";
+        };
+
+        emptyBackspaceFeedback = mkOption {
+          type = types.enum [
+            "none"
+            "flash"
+          ];
+          default = "none";
+          description = "Feedback for backspace with nothing left to delete, none, or flash the input border in the fail color";
+        };
+
+        forceRepeatRate = mkOption {
+          type = types.ints.unsigned;
+          default = 0;
+          description = "Key repeat rate in keys per second, used even if the compositor disables repeat, 0 uses the compositor's";
+        };
+
+        forceRepeatDelay = mkOption {
+          type = types.ints.unsigned;
+          default = 0;
+          description = "Key repeat delay in milliseconds, 0 uses the compositor's";
+        };
+
+        minSubmitLength = mkOption {
+          type = types.ints.unsigned;
+          default = 0;
+          description = "Shortest password submitted to PAM, in characters, shorter ones are not counted as failed attempts, 0 disables";
+        };
+
+        tooShortMessage = mkOption {
+          type = types.str;
+          default = "Password too short";
+          description = "Message shown when a password is too short to submit";
+        };
       };
 
       frame = {
@@ -210,6 +600,33 @@ in
           default = 0.0;
           description = "Radius of frame border, absolute, typically pixels";
         };
+
+        antialias = mkOption {
+          type = types.enum [
+            "best"
+            "good"
+            "fast"
+            "none"
+          ];
+          default = "best";
+          description = "Anti-aliasing mode of the frame edges";
+        };
+
+        strokeAlign = mkOption {
+          type = types.enum [
+            "inside"
+            "center"
+            "outside"
+          ];
+          default = "inside";
+          description = "Where the frame border is drawn, inside the surface edge, centered on it, cutting off half, or outside it, which needs an inset";
+        };
+
+        inset = mkOption {
+          type = types.float;
+          default = 0.0;
+          description = "Gap between the frame and the surface edges, absolute units, typically pixels";
+        };
       };
 
       image = {
@@ -230,6 +647,254 @@ in
           default = "fill";
           description = "Background image scaling mode";
         };
+
+        placeholderColor = mkOption {
+          type = types.str;
+          default = "00000000";
+          description = "Color painted beneath the background image, or in place of it if not loaded";
+        };
+
+        anchor = mkOption {
+          type = types.enum [
+            "start"
+            "center"
+            "end"
+          ];
+          default = "center";
+          description = "Image placement for the fill and fit scaling modes";
+        };
+
+        vignette = mkOption {
+          type = types.float;
+          default = 0.0;
+          description = "Strength of a darkened vignette around the image edges, from 0.0 to 1.0, 0.0 disables";
+        };
+
+        vignetteRadius = mkOption {
+          type = types.float;
+          default = 0.5;
+          description = "Where the vignette starts, relative to the distance from the center to a corner";
+        };
+
+        gamma = mkOption {
+          type = types.float;
+          default = 1.0;
+          description = "Gamma of the background image, from 0.1 to 10.0, above 1.0 brightens";
+        };
+
+        temperature = mkOption {
+          type = types.float;
+          default = 6500.0;
+          description = "Color temperature of the background image in kelvin, from 1000 to 10000, lower is warmer";
+        };
+
+        layers = mkOption {
+          type = types.listOf (
+            types.submodule {
+              options = {
+                path = mkOption {
+                  type = types.externalPath;
+                  description = "Path to the layer image";
+                };
+
+                scale = mkOption {
+                  type = types.enum [
+                    "center"
+                    "fit"
+                    "fill"
+                    "stretch"
+                    "tile"
+                  ];
+                  default = "fill";
+                  description = "Layer image scaling mode";
+                };
+
+                anchor = mkOption {
+                  type = types.enum [
+                    "start"
+                    "center"
+                    "end"
+                  ];
+                  default = "center";
+                  description = "Layer image placement for the fill and fit scaling modes";
+                };
+
+                opacity = mkOption {
+                  type = types.float;
+                  default = 1.0;
+                  description = "Opacity of the whole layer, 0.0 to 1.0";
+                };
+              };
+            }
+          );
+          default = [ ];
+          description = "Images drawn over the background image in order, layers that fail to load are skipped";
+        };
+      };
+
+      animation = {
+        successDelayMs = mkOption {
+          type = types.ints.unsigned;
+          default = 0;
+          description = "Time to show the success state before unlocking, in milliseconds";
+        };
+
+        syncUnlock = mkOption {
+          type = types.bool;
+          default = true;
+          description = "Wait for every output to show the success state before unlocking";
+        };
+
+        highlightMs = mkOption {
+          type = types.ints.unsigned;
+          default = 0;
+          description = "Time keypresses highlight the input border, in milliseconds, 0 disables";
+        };
+
+        shakeOnFail = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Shake the input box side to side after a failed attempt";
+        };
+
+        fps = mkOption {
+          type = types.ints.unsigned;
+          default = 30;
+          description = "Frame rate of animations, like the spinner";
+        };
+
+        fpsOnBattery = mkOption {
+          type = types.ints.unsigned;
+          default = 0;
+          description = "Frame rate of animations on battery power, 0 uses the normal frame rate";
+        };
+      };
+
+      xkb = {
+        layout = mkOption {
+          type = types.str;
+          default = "";
+          description = "Keyboard layout, instead of using the compositor keymap";
+        };
+
+        variant = mkOption {
+          type = types.str;
+          default = "";
+          description = "Keyboard layout variant";
+        };
+
+        options = mkOption {
+          type = types.str;
+          default = "";
+          description = "XKB options";
+        };
+
+        model = mkOption {
+          type = types.str;
+          default = "";
+          description = "Keyboard model";
+        };
+      };
+
+      keys = {
+        submit = mkOption {
+          type = types.enum [
+            "any"
+            "none"
+            "shift"
+            "ctrl"
+            "alt"
+            "super"
+          ];
+          default = "any";
+          description = "Modifiers required for Enter to submit the password";
+        };
+
+        redraw = mkOption {
+          type = types.str;
+          default = "Ctrl+l";
+          description = "Key which forces all surfaces to be redrawn, as modifiers and a key name joined with \"+\". \"none\" disables it.";
+        };
+      };
+
+      overlay = {
+        opacity = mkOption {
+          type = types.float;
+          default = 1.0;
+          description = "Opacity of the whole overlay, 0.0 to 1.0, multiplied with color alpha";
+        };
+      };
+
+      spinner = {
+        enabled = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Show a spinner while the password is being verified";
+        };
+
+        size = mkOption {
+          type = types.float;
+          default = 0.05;
+          description = "Spinner diameter, relative to display height";
+        };
+
+        thickness = mkOption {
+          type = types.float;
+          default = 4.0;
+          description = "Spinner line thickness";
+        };
+
+        x = mkOption {
+          type = types.float;
+          default = 0.5;
+          description = "Horizontal position of the spinner center, relative to display width";
+        };
+
+        y = mkOption {
+          type = types.float;
+          default = 0.6;
+          description = "Vertical position of the spinner center, relative to display height";
+        };
+
+        periodMs = mkOption {
+          type = types.ints.positive;
+          default = 1000;
+          description = "Time for one spinner rotation, in milliseconds";
+        };
+      };
+
+      prompt = {
+        text = mkOption {
+          type = types.str;
+          default = "";
+          description = "Label shown above the input box, e.g. Password:, empty disables it";
+        };
+
+        size = mkOption {
+          type = types.float;
+          default = 24.0;
+          description = "Prompt font size, in points";
+        };
+
+        gap = mkOption {
+          type = types.float;
+          default = 0.02;
+          description = "Gap between the prompt and the input box, relative to display height";
+        };
+
+        centerInput = mkOption {
+          type = types.bool;
+          default = true;
+          description = "Keep the input box centered, false centers the prompt and input box together";
+        };
+      };
+
+      text = {
+        outline = mkOption {
+          type = types.float;
+          default = 0.0;
+          description = "Width of an outline around text, for contrast over busy backgrounds, 0 disables";
+        };
       };
     };
   };
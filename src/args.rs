@@ -13,7 +13,10 @@ use clap::{
 use clap_complete::{Shell, aot::generate as generate_completions};
 
 use crate::util::{
-    BackgroundImageScale, BackgroundType, FontSlant, FontWeight, InputVisibility, LogLevel, Rgba,
+    BackgroundImageScale, BackgroundType, BufferFormat, EmptyBackspaceFeedback, FontAntialias,
+    FontSlant, FontWeight, ImageAnchor, InputEcho, InputOverflow, InputVisibility, KeyBinding,
+    LogLevel, Rgba, SecondaryMode, ShapeAntialias, ShmBackend, StrokeAlign, SubmitModifiers,
+    TextDirection,
 };
 
 /// Customisable, minimalist screen locker for Wayland
@@ -25,9 +28,19 @@ pub struct NLockArgs {
     /// Log verbosity
     #[arg(short, long, default_value = "info")]
     pub log_level: LogLevel,
-    /// Configuration file path
+    /// Configuration file path, or "-" to read from stdin
     #[arg(short, long)]
     pub config_file: Option<String>,
+    /// Check which globals the compositor supports, then exit without locking
+    #[arg(long)]
+    pub check: bool,
+    /// Print the results of --check as JSON
+    #[arg(long, requires = "check")]
+    pub json: bool,
+    /// Test authentication with a password read from the terminal, then exit
+    /// without locking
+    #[arg(long, conflicts_with = "check")]
+    pub test_auth: bool,
 
     /// Sets the background color
     #[arg(long)]
@@ -50,6 +63,27 @@ pub struct NLockArgs {
     /// Sets the fail frame border color
     #[arg(long)]
     pub frame_border_fail_color: Option<Rgba>,
+    /// Sets the input border color briefly shown on a keypress
+    #[arg(long)]
+    pub key_highlight_color: Option<Rgba>,
+    /// Sets the input border color briefly shown on backspace
+    #[arg(long)]
+    pub backspace_highlight_color: Option<Rgba>,
+    /// Sets the color of the spinner shown while verifying
+    #[arg(long)]
+    pub spinner_color: Option<Rgba>,
+    /// Sets the color of the prompt above the input box
+    #[arg(long)]
+    pub prompt_color: Option<Rgba>,
+    /// Sets the color of the outline drawn around text
+    #[arg(long)]
+    pub text_outline_color: Option<Rgba>,
+    /// Sets the color of the glow inside the input box edges
+    #[arg(long)]
+    pub input_glow_color: Option<Rgba>,
+    /// Sets the color the background image darkens to at the edges, with a vignette
+    #[arg(long)]
+    pub vignette_color: Option<Rgba>,
 
     /// Sets the font size, in points
     #[arg(long)]
@@ -57,6 +91,9 @@ pub struct NLockArgs {
     /// Sets the font family
     #[arg(long)]
     pub font_family: Option<String>,
+    /// Font families used in order if the font family isn't installed
+    #[arg(long, value_delimiter = ',')]
+    pub font_fallback: Option<Vec<String>>,
     /// Sets the font slant
     #[arg(long)]
     pub font_slant: Option<FontSlant>,
@@ -66,6 +103,18 @@ pub struct NLockArgs {
     /// Scale font size by display output DPI
     #[arg(long)]
     pub use_dpi_scaling: Option<bool>,
+    /// Sets the DPI assumed for outputs without physical dimensions
+    #[arg(long)]
+    pub assumed_dpi: Option<f64>,
+    /// Sets OpenType font features, e.g. "tnum, smcp"
+    #[arg(long)]
+    pub font_features: Option<String>,
+    /// Sets the font anti-aliasing mode
+    #[arg(long)]
+    pub font_antialias: Option<FontAntialias>,
+    /// Sets the text direction, instead of detecting it from the text
+    #[arg(long)]
+    pub font_direction: Option<TextDirection>,
 
     /// Sets the mask character for the input box
     #[arg(long)]
@@ -73,6 +122,9 @@ pub struct NLockArgs {
     /// Sets the relative width of the input box
     #[arg(long)]
     pub input_width: Option<f64>,
+    /// Sets the relative height of the text area of the input box, 0 fits the font
+    #[arg(long)]
+    pub input_height: Option<f64>,
     /// Sets the relative horizontal padding of the input box
     #[arg(long)]
     pub input_padding_x: Option<f64>,
@@ -85,12 +137,63 @@ pub struct NLockArgs {
     /// Sets tne border width of the input box
     #[arg(long)]
     pub input_border: Option<f64>,
+    /// Sets the width of a glow inside the input box edges, 0 disables it
+    #[arg(long)]
+    pub input_inner_glow: Option<f64>,
     /// Input box visibility
     #[arg(long)]
     pub input_visible: Option<InputVisibility>,
     /// Resize the input box to fit the entered password
     #[arg(long)]
     pub fit_to_content: Option<bool>,
+    /// Sets how the entered password is echoed in the input box
+    #[arg(long)]
+    pub input_echo: Option<InputEcho>,
+    /// Sets how a masked password too long for the input box is shown
+    #[arg(long)]
+    pub input_overflow: Option<InputOverflow>,
+    /// Sets the number of colored blocks shown with block echo
+    #[arg(long)]
+    pub input_block_count: Option<u32>,
+    /// Enable compose key and dead key sequences
+    #[arg(long)]
+    pub input_compose: Option<bool>,
+    /// Sets the anti-aliasing mode of the input box edges
+    #[arg(long)]
+    pub input_antialias: Option<ShapeAntialias>,
+    /// Draw a caret after the password in the input box
+    #[arg(long)]
+    pub input_caret: Option<bool>,
+    /// Sets the caret blink interval in milliseconds, 0 disables blinking
+    #[arg(long)]
+    pub input_caret_blink_ms: Option<u64>,
+    /// Sets how long pointer or touch activity shows a hidden input box, in milliseconds
+    #[arg(long)]
+    pub input_wake_ms: Option<u64>,
+    /// Sets the longest password submitted to PAM, in bytes, 0 disables the limit
+    #[arg(long)]
+    pub input_pam_max_length: Option<usize>,
+    /// Sets the shortest password submitted to PAM, in characters, 0 disables the check
+    #[arg(long)]
+    pub input_min_submit_length: Option<usize>,
+    /// Sets the message shown when a password is too short to submit
+    #[arg(long)]
+    pub input_too_short_message: Option<String>,
+    /// Clear the input after a failed attempt, instead of restoring the password for editing
+    #[arg(long)]
+    pub input_clear_on_fail: Option<bool>,
+    /// Type digits with the numpad even when NumLock is off
+    #[arg(long)]
+    pub input_numpad_always_digits: Option<bool>,
+    /// Sets the feedback for backspace with nothing left to delete
+    #[arg(long)]
+    pub input_empty_backspace_feedback: Option<EmptyBackspaceFeedback>,
+    /// Sets the key repeat rate in keys per second, instead of the compositor's, 0 uses the compositor's
+    #[arg(long)]
+    pub input_force_repeat_rate: Option<u32>,
+    /// Sets the key repeat delay in milliseconds, instead of the compositor's, 0 uses the compositor's
+    #[arg(long)]
+    pub input_force_repeat_delay: Option<u32>,
 
     /// Sets the border radius of the frame
     #[arg(long)]
@@ -98,6 +201,15 @@ pub struct NLockArgs {
     /// Sets the border width of the frame
     #[arg(long)]
     pub frame_border: Option<f64>,
+    /// Sets the anti-aliasing mode of the frame edges
+    #[arg(long)]
+    pub frame_antialias: Option<ShapeAntialias>,
+    /// Sets where the frame border is drawn relative to the surface edge
+    #[arg(long)]
+    pub frame_stroke_align: Option<StrokeAlign>,
+    /// Sets the gap between the frame and the surface edges
+    #[arg(long)]
+    pub frame_inset: Option<f64>,
 
     /// Validate empty passwords
     #[arg(long)]
@@ -105,16 +217,197 @@ pub struct NLockArgs {
     /// Hide the mouse cursor
     #[arg(long)]
     pub hide_cursor: Option<bool>,
+    /// Hide the cursor with a transparent image, for compositors which ignore hiding it
+    #[arg(long)]
+    pub transparent_cursor: Option<bool>,
+    /// Open a PAM session after authenticating
+    #[arg(long)]
+    pub pam_session: Option<bool>,
+    /// Maximum failed attempts before locking out permanently, 0 is unlimited
+    #[arg(long)]
+    pub max_attempts: Option<u32>,
+    /// Message displayed after too many failed attempts
+    #[arg(long)]
+    pub lockout_message: Option<String>,
+    /// Maximum number of times to restart the authenticator if it exits
+    #[arg(long)]
+    pub auth_restart_limit: Option<u32>,
+    /// Time to wait for PAM before giving up on an attempt, in milliseconds
+    #[arg(long)]
+    pub auth_timeout_ms: Option<u64>,
+    /// Render lock surfaces before continuing after locking
+    #[arg(long)]
+    pub wait_for_surfaces: Option<bool>,
+    /// Maximum number of times to lock again if the compositor ends the lock
+    #[arg(long)]
+    pub relock_limit: Option<u32>,
+    /// Number of attempts to connect to the Wayland compositor
+    #[arg(long)]
+    pub connect_attempts: Option<u32>,
+    /// Delay before the first connection retry, doubling with each retry, in milliseconds
+    #[arg(long)]
+    pub connect_retry_ms: Option<u64>,
+    /// Maximum buffer width or height, in pixels
+    #[arg(long)]
+    pub max_buffer_dimension: Option<u32>,
+    /// Sets what is shown on outputs other than the primary one
+    #[arg(long)]
+    pub secondary_mode: Option<SecondaryMode>,
+    /// Smallest factor fixed-size elements shrink by on small surfaces
+    #[arg(long)]
+    pub min_surface_scale: Option<f64>,
+    /// Record unlock attempts in the system log
+    #[arg(long)]
+    pub audit_log: Option<bool>,
+    /// Only show the lock screen on the output last given input, blank the others
+    #[arg(long)]
+    pub power_save: Option<bool>,
+    /// Use less memory, without a background image, animations, or spare buffers
+    #[arg(long)]
+    pub low_memory: Option<bool>,
+    /// Render backgrounds at the output mode size before the compositor configures them
+    #[arg(long)]
+    pub prerender: Option<bool>,
+    /// Round element positions and sizes to whole pixels, for crisper edges
+    #[arg(long)]
+    pub pixel_snap: Option<bool>,
+    /// Draw the overlay onto the background if the compositor never presents it
+    #[arg(long)]
+    pub overlay_fallback: Option<bool>,
+    /// Show each output's name, size, scale, DPI and subpixel order, for troubleshooting
+    #[arg(long)]
+    pub debug_overlay: Option<bool>,
+    /// Outputs to show the lock screen on, by name, description, or "make model"
+    #[arg(long, value_delimiter = ',')]
+    pub output_include: Option<Vec<String>>,
+    /// Outputs to only show a plain color on, by name, description, or "make model"
+    #[arg(long, value_delimiter = ',')]
+    pub output_exclude: Option<Vec<String>>,
+    /// Sets the space kept clear at the top of each output, in pixels
+    #[arg(long)]
+    pub margin_top: Option<f64>,
+    /// Sets the space kept clear at the right of each output, in pixels
+    #[arg(long)]
+    pub margin_right: Option<f64>,
+    /// Sets the space kept clear at the bottom of each output, in pixels
+    #[arg(long)]
+    pub margin_bottom: Option<f64>,
+    /// Sets the space kept clear at the left of each output, in pixels
+    #[arg(long)]
+    pub margin_left: Option<f64>,
 
     /// Sets the background type
     #[arg(long)]
     pub bg_type: Option<BackgroundType>,
+    /// Sets the preferred background buffer format, auto picks xrgb for opaque backgrounds
+    #[arg(long)]
+    pub bg_format: Option<BufferFormat>,
+    /// Sets how shared memory for buffers is created
+    #[arg(long)]
+    pub shm_backend: Option<ShmBackend>,
     /// Path to a background image
     #[arg(long)]
     pub image_path: Option<PathBuf>,
     /// Sets the image scaling mode
     #[arg(long)]
     pub image_scale: Option<BackgroundImageScale>,
+    /// Sets the image placement for the fill and fit scaling modes
+    #[arg(long)]
+    pub image_anchor: Option<ImageAnchor>,
+    /// Sets the color painted beneath, or in place of, the background image
+    #[arg(long)]
+    pub image_placeholder_color: Option<Rgba>,
+    /// Sets the strength of a vignette over the background image, from 0 to 1, 0 disables it
+    #[arg(long)]
+    pub image_vignette: Option<f64>,
+    /// Sets where the vignette starts, relative to the distance from the center to a corner
+    #[arg(long)]
+    pub image_vignette_radius: Option<f64>,
+    /// Sets the gamma of the background image, from 0.1 to 10, above 1 brightens it
+    #[arg(long)]
+    pub image_gamma: Option<f64>,
+    /// Sets the color temperature of the background image in kelvin, from 1000 to 10000, lower is warmer
+    #[arg(long)]
+    pub image_temperature: Option<f64>,
+
+    /// Delay before unlocking after a successful attempt, in milliseconds
+    #[arg(long)]
+    pub success_delay_ms: Option<u64>,
+    /// Wait for every output to show the success state before unlocking
+    #[arg(long)]
+    pub sync_unlock: Option<bool>,
+    /// Sets how long keypresses highlight the input border, in milliseconds, 0 disables
+    #[arg(long)]
+    pub highlight_ms: Option<u64>,
+    /// Shake the input box side to side after a failed attempt
+    #[arg(long)]
+    pub shake_on_fail: Option<bool>,
+    /// Sets the frame rate of animations, like the spinner
+    #[arg(long)]
+    pub fps: Option<u32>,
+    /// Sets the frame rate of animations on battery power, 0 uses the normal frame rate
+    #[arg(long)]
+    pub fps_on_battery: Option<u32>,
+
+    /// Sets the XKB layout, instead of using the compositor keymap
+    #[arg(long)]
+    pub xkb_layout: Option<String>,
+    /// Sets the XKB layout variant
+    #[arg(long)]
+    pub xkb_variant: Option<String>,
+    /// Sets the XKB options, e.g. "compose:ralt"
+    #[arg(long)]
+    pub xkb_options: Option<String>,
+    /// Sets the XKB keyboard model
+    #[arg(long)]
+    pub xkb_model: Option<String>,
+
+    /// Sets the modifiers required for Enter to submit the password
+    #[arg(long)]
+    pub submit_modifiers: Option<SubmitModifiers>,
+    /// Sets the key which forces all surfaces to be redrawn, e.g. "Ctrl+L"
+    #[arg(long)]
+    pub redraw_key: Option<KeyBinding>,
+
+    /// Sets the opacity of the whole overlay, 0.0 to 1.0
+    #[arg(long)]
+    pub overlay_opacity: Option<f64>,
+
+    /// Show a spinner while the password is being verified
+    #[arg(long)]
+    pub spinner: Option<bool>,
+    /// Sets the spinner diameter, relative to display height
+    #[arg(long)]
+    pub spinner_size: Option<f64>,
+    /// Sets the spinner line thickness
+    #[arg(long)]
+    pub spinner_thickness: Option<f64>,
+    /// Sets the horizontal position of the spinner center, relative to display width
+    #[arg(long)]
+    pub spinner_x: Option<f64>,
+    /// Sets the vertical position of the spinner center, relative to display height
+    #[arg(long)]
+    pub spinner_y: Option<f64>,
+    /// Sets the time for one spinner rotation, in milliseconds
+    #[arg(long)]
+    pub spinner_period_ms: Option<u64>,
+
+    /// Sets the prompt shown above the input box, e.g. "Password:"
+    #[arg(long)]
+    pub prompt: Option<String>,
+    /// Sets the prompt font size, in points
+    #[arg(long)]
+    pub prompt_size: Option<f64>,
+    /// Sets the gap between the prompt and the input box, relative to display height
+    #[arg(long)]
+    pub prompt_gap: Option<f64>,
+    /// Keep the input box centered, rather than centering it with the prompt
+    #[arg(long)]
+    pub prompt_center_input: Option<bool>,
+
+    /// Sets the width of the outline drawn around text, 0 disables it
+    #[arg(long)]
+    pub text_outline: Option<f64>,
 }
 
 #[derive(Subcommand, Debug)]
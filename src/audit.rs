@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026, Nathan Gill
+
+use std::sync::Once;
+
+use nix::syslog::{Facility, LogFlags, Priority, Severity, openlog, syslog};
+use tracing::{info, warn};
+
+/// Tracing target of audit records, so they can be filtered separately
+pub const AUDIT_TARGET: &str = "nlock::audit";
+
+static OPEN_LOG: Once = Once::new();
+
+/// Outcome of an authentication attempt, as recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+    TimedOut,
+    AccountUnusable,
+}
+
+impl AuditOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+            Self::TimedOut => "timed out",
+            Self::AccountUnusable => "account unusable",
+        }
+    }
+
+    fn severity(&self) -> Severity {
+        match self {
+            Self::Success => Severity::LOG_INFO,
+            Self::Failure | Self::TimedOut | Self::AccountUnusable => Severity::LOG_NOTICE,
+        }
+    }
+}
+
+/// Record an unlock attempt in the system log, with the auth facility, which
+/// the journal also collects. Only the user and the outcome are recorded,
+/// the system logger adds the timestamp.
+pub fn log_attempt(outcome: AuditOutcome) {
+    OPEN_LOG.call_once(|| {
+        if let Err(e) = openlog(Some(c"nlock"), LogFlags::LOG_PID, Facility::LOG_AUTH) {
+            warn!("Failed to open system log: {e}");
+        }
+    });
+
+    let username = uzers::get_current_username()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| format!("uid {}", uzers::get_current_uid()));
+
+    let message = format!("unlock attempt for user {username}: {}", outcome.as_str());
+    info!(target: AUDIT_TARGET, "{message}");
+
+    let priority = Priority::new(outcome.severity(), Facility::LOG_AUTH);
+    if let Err(e) = syslog(priority, &message) {
+        warn!("Failed to write audit record to system log: {e}");
+    }
+}
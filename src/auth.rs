@@ -1,24 +1,43 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright (C) 2026, Nathan Gill
 
-use std::{os::fd::AsFd, sync::Arc};
+use std::{
+    ffi::{CStr, c_char, c_int, c_void},
+    fmt,
+    io::{self, Write},
+    os::fd::AsFd,
+    ptr,
+    sync::{
+        Arc,
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
 
-use anyhow::{Result, anyhow};
+use anyhow::{Result, anyhow, bail};
 use atomic_enum::atomic_enum;
 use nix::{
     errno::Errno,
+    libc,
     poll::{PollFd, PollFlags, PollTimeout},
-    sys::eventfd::EventFd,
+    sys::{
+        eventfd::EventFd,
+        termios::{self, LocalFlags, SetArg},
+    },
 };
-use pam_rs::{Client, PamFlag};
+use pam_rs::{PamFlag, PamHandle, PamMessage, PamMessageStyle, PamResponse, PamReturnCode, ffi};
 use tracing::{debug, warn};
 use zeroize::Zeroizing;
 
-use crate::{comm::PipeCommChannel, config::NLockConfig};
+use crate::{
+    comm::{AsBytes, FromBytes, PipeCommChannel},
+    config::NLockConfig,
+};
 
 pub struct AuthChannel {
     pub request: PipeCommChannel<String>,
-    pub response: PipeCommChannel<bool>,
+    pub response: PipeCommChannel<AuthResponse>,
     pub stop_ev: EventFd,
 }
 
@@ -35,64 +54,534 @@ impl AuthChannel {
 #[atomic_enum]
 pub enum AuthState {
     Idle,
+    Verifying,
+    Success,
+    Fail,
+    LockedOut,
+    Unavailable,
+    TimedOut,
+    TooLong,
+    TooShort,
+    AccountUnusable,
+}
+
+/// Result of an authentication request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthResponse {
     Success,
     Fail,
+    TimedOut,
+    // The password was right, but the account can't be used, e.g. expired
+    AccountUnusable,
+}
+
+impl AsBytes for AuthResponse {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Success => &[0u8],
+            Self::Fail => &[1u8],
+            Self::TimedOut => &[2u8],
+            Self::AccountUnusable => &[3u8],
+        }
+    }
 }
 
+impl FromBytes for AuthResponse {
+    fn from_bytes(bytes: &[u8]) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        match bytes {
+            [0u8] => Some(Self::Success),
+            [1u8] => Some(Self::Fail),
+            [2u8] => Some(Self::TimedOut),
+            [3u8] => Some(Self::AccountUnusable),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct AuthConfig {
     pub allow_empty: bool,
+    pub open_session: bool,
+    pub max_attempts: u32,
+    pub timeout_ms: u64,
 }
 
 impl AuthConfig {
     pub fn new(config: &NLockConfig) -> Self {
         Self {
             allow_empty: config.general.pwd_allow_empty,
+            open_session: config.general.pam_session,
+            max_attempts: config.general.max_attempts,
+            timeout_ms: config.general.auth_timeout_ms,
+        }
+    }
+}
+
+/// Check whether `failures` failed attempts exhaust the allowed attempts.
+///
+/// A `max_attempts` of 0 allows unlimited attempts.
+pub fn attempts_exhausted(failures: u32, max_attempts: u32) -> bool {
+    max_attempts != 0 && failures >= max_attempts
+}
+
+/// Check whether `password` is longer than PAM accepts, in bytes, as PAM
+/// limits responses by size, not characters.
+///
+/// A `max_length` of 0 disables the check.
+pub fn exceeds_pam_length(password: &str, max_length: usize) -> bool {
+    max_length != 0 && password.len() > max_length
+}
+
+/// The password was accepted, but PAM account management rejected the
+/// account, so typing it again won't help.
+#[derive(Debug)]
+struct AccountUnusable(PamReturnCode);
+
+impl fmt::Display for AccountUnusable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PAM account validation failed: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for AccountUnusable {}
+
+/// Username and password given to PAM when it prompts for them, as
+/// NUL-terminated strings
+struct Credentials {
+    username: Vec<u8>,
+    password: Zeroizing<Vec<u8>>,
+}
+
+impl Credentials {
+    fn new(username: &str, password: &str) -> Result<Self> {
+        if username.contains('\0') || password.contains('\0') {
+            bail!("Credentials contain a NUL byte");
+        }
+
+        let mut username = username.as_bytes().to_vec();
+        username.push(0);
+
+        let mut nul_terminated = Zeroizing::new(Vec::with_capacity(password.len() + 1));
+        nul_terminated.extend_from_slice(password.as_bytes());
+        nul_terminated.push(0);
+
+        Ok(Self {
+            username,
+            password: nul_terminated,
+        })
+    }
+}
+
+/// PAM conversation function, answering prompts from the `Credentials` given
+/// as `appdata_ptr` to `pam_start`
+unsafe extern "C" fn converse(
+    num_msg: c_int,
+    msg: *mut *const PamMessage,
+    resp: *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int {
+    if num_msg <= 0 || msg.is_null() || resp.is_null() || appdata_ptr.is_null() {
+        return PamReturnCode::Conv_Err as c_int;
+    }
+
+    // SAFETY: this is the pointer given to pam_start, the credentials are
+    // boxed in the PamSession, which outlives the transaction
+    let credentials = unsafe { &*(appdata_ptr as *const Credentials) };
+
+    // PAM frees the responses, and each answer, so they're allocated with libc
+    let count = num_msg as usize;
+    let responses = unsafe { libc::calloc(count, size_of::<PamResponse>()) } as *mut PamResponse;
+    if responses.is_null() {
+        return PamReturnCode::Buf_Err as c_int;
+    }
+
+    for i in 0..count {
+        // SAFETY: PAM passes `num_msg` valid messages
+        let message = unsafe { &**msg.add(i) };
+        let answer = match PamMessageStyle::from(message.msg_style) {
+            PamMessageStyle::Prompt_Echo_On => credentials.username.as_slice(),
+            PamMessageStyle::Prompt_Echo_Off => credentials.password.as_slice(),
+            // Nothing to answer, and nowhere to show it but the log
+            PamMessageStyle::Text_Info | PamMessageStyle::Error_Msg => {
+                let text = unsafe { CStr::from_ptr(message.msg) };
+                debug!("PAM message: {}", text.to_string_lossy());
+                continue;
+            }
+        };
+
+        let copy = unsafe { libc::strdup(answer.as_ptr() as *const c_char) };
+        if copy.is_null() {
+            // Scrub and free the answers given so far, PAM won't see them
+            for j in 0..i {
+                let response = unsafe { &mut *responses.add(j) };
+                if !response.resp.is_null() {
+                    let len = unsafe { libc::strlen(response.resp) };
+                    unsafe { ptr::write_bytes(response.resp, 0, len) };
+                    unsafe { libc::free(response.resp as *mut c_void) };
+                }
+            }
+            unsafe { libc::free(responses as *mut c_void) };
+            return PamReturnCode::Buf_Err as c_int;
+        }
+
+        unsafe { (*responses.add(i)).resp = copy };
+    }
+
+    unsafe { *resp = responses };
+    PamReturnCode::Success as c_int
+}
+
+/// A PAM transaction for an authenticated user. A session opened through it,
+/// along with the credentials set for it, stays open until this is dropped.
+pub struct PamSession {
+    handle: &'static mut PamHandle,
+    // Given to PAM as the conversation data, so must outlive the handle
+    _credentials: Box<Credentials>,
+    credentials_set: bool,
+    session_open: bool,
+    status: PamReturnCode,
+    env: Vec<(String, String)>,
+}
+
+// SAFETY: the handle is only used by one thread at a time, whichever owns
+// the PamSession
+unsafe impl Send for PamSession {}
+
+impl PamSession {
+    fn start(username: &str, password: &str) -> Result<Self> {
+        let credentials = Box::new(Credentials::new(username, password)?);
+        let conversation = ffi::pam_conv {
+            conv: Some(converse),
+            appdata_ptr: &*credentials as *const Credentials as *mut c_void,
+        };
+
+        // PAM keeps its own copy of the conversation struct
+        let handle = pam_rs::start("nlock", Some(username), &conversation)
+            .map_err(|e| anyhow!("PAM start failed: {e}"))?;
+
+        Ok(Self {
+            handle,
+            _credentials: credentials,
+            credentials_set: false,
+            session_open: false,
+            status: PamReturnCode::Success,
+            env: Vec::new(),
+        })
+    }
+
+    fn check(&mut self, code: PamReturnCode) -> std::result::Result<(), PamReturnCode> {
+        self.status = code;
+        match code {
+            PamReturnCode::Success => Ok(()),
+            code => Err(code),
+        }
+    }
+
+    /// Run the `auth` and `account` stacks
+    fn authenticate(&mut self, flags: PamFlag) -> Result<()> {
+        let code = pam_rs::authenticate(self.handle, flags);
+        if let Err(code) = self.check(code) {
+            bail!("PAM authentication failed: {code:?}");
+        }
+
+        // Only account management reports an expired account, or a required
+        // password change, so those mean the password itself was right
+        let code = pam_rs::acct_mgmt(self.handle, flags);
+        if let Err(code) = self.check(code) {
+            if matches!(
+                code,
+                PamReturnCode::Acct_Expired | PamReturnCode::New_Authtok_Reqd
+            ) {
+                return Err(AccountUnusable(code).into());
+            }
+
+            bail!("PAM account management failed: {code:?}");
+        }
+
+        Ok(())
+    }
+
+    /// Set credentials and open a session, then collect the environment
+    /// session modules set up. Credentials are set again once the session
+    /// is open, as OpenSSH does.
+    fn open(&mut self) -> Result<()> {
+        let code = pam_rs::setcred(self.handle, PamFlag::Establish_Cred);
+        if let Err(code) = self.check(code) {
+            bail!("PAM credential setup failed: {code:?}");
+        }
+        self.credentials_set = true;
+
+        let code = pam_rs::open_session(self.handle, false);
+        if let Err(code) = self.check(code) {
+            bail!("PAM session open failed: {code:?}");
+        }
+        self.session_open = true;
+
+        let code = pam_rs::setcred(self.handle, PamFlag::Reinitialize_Cred);
+        if let Err(code) = self.check(code) {
+            warn!("PAM credential reinitialization failed: {code:?}");
+        }
+
+        self.env = self.collect_env();
+        Ok(())
+    }
+
+    /// Copy the PAM environment, as set by modules in the transaction
+    fn collect_env(&mut self) -> Vec<(String, String)> {
+        let list = unsafe { ffi::pam_getenvlist(self.handle) };
+        if list.is_null() {
+            return Vec::new();
+        }
+
+        let mut env = Vec::new();
+        let mut entry = list;
+        // SAFETY: the list, and each entry, is allocated for the caller and
+        // ends with a null pointer
+        unsafe {
+            while !(*entry).is_null() {
+                let pair = CStr::from_ptr(*entry).to_string_lossy();
+                if let Some((name, value)) = pair.split_once('=') {
+                    env.push((name.to_string(), value.to_string()));
+                }
+
+                // Values may hold secrets, e.g. keyring control sockets
+                ptr::write_bytes(*entry, 0, libc::strlen(*entry));
+                libc::free(*entry as *mut c_void);
+                entry = entry.add(1);
+            }
+            libc::free(list as *mut c_void);
+        }
+
+        env
+    }
+
+    /// Environment variables set by PAM modules, e.g. for a keyring daemon
+    pub fn env(&self) -> &[(String, String)] {
+        &self.env
+    }
+}
+
+impl Drop for PamSession {
+    fn drop(&mut self) {
+        if self.session_open {
+            pam_rs::close_session(self.handle, false);
+        }
+        if self.credentials_set {
+            pam_rs::setcred(self.handle, PamFlag::Delete_Cred);
         }
+        pam_rs::end(self.handle, self.status);
     }
 }
 
-fn authenticate(config: &AuthConfig, username: &str, password: Zeroizing<String>) -> Result<()> {
-    let mut client = Client::with_password("nlock")?;
-    client
-        .conversation_mut()
-        .set_credentials(username, password.as_str());
+/// Authenticate `username` with PAM, returning the PAM transaction if a
+/// session was opened, which keeps it open until dropped.
+fn authenticate(
+    config: &AuthConfig,
+    username: &str,
+    password: Zeroizing<String>,
+) -> Result<Option<PamSession>> {
+    let mut session = PamSession::start(username, &password)?;
 
     let mut flags = PamFlag::None;
     if !config.allow_empty {
         flags = PamFlag::Disallow_Null_AuthTok;
     }
 
-    client.authenticate(flags)?;
+    session.authenticate(flags)?;
 
-    Ok(())
+    // Credentials are only set along with a session, see `pamSession`
+    if !config.open_session {
+        return Ok(None);
+    }
+
+    // Opening a session gives session modules like `pam_gnome_keyring` a
+    // chance to unlock keyrings. Failing to open one isn't fatal, keyrings
+    // stay locked, but the user has still authenticated.
+    if let Err(e) = session.open() {
+        warn!("{e}");
+        return Ok(None);
+    }
+
+    Ok(Some(session))
 }
 
-/// Handle an authentication request, returning a value to indicate success
-fn handle_auth_request(config: &AuthConfig, auth_comm: Arc<AuthChannel>, username: &str) -> bool {
+/// Read a password from standard input, without echoing it if it's a terminal
+fn read_password(prompt: &str) -> Result<Zeroizing<String>> {
+    let stdin = io::stdin();
+
+    eprint!("{prompt}");
+    io::stderr().flush()?;
+
+    // Not a terminal, e.g. piped input, so there's no echo to disable
+    let original = termios::tcgetattr(&stdin).ok();
+    if let Some(original) = &original {
+        let mut no_echo = original.clone();
+        no_echo.local_flags.remove(LocalFlags::ECHO);
+        termios::tcsetattr(&stdin, SetArg::TCSANOW, &no_echo)?;
+    }
+
+    let mut password = Zeroizing::new(String::new());
+    let result = stdin.read_line(&mut password);
+
+    // Always restore echo, even if reading failed
+    if let Some(original) = &original {
+        termios::tcsetattr(&stdin, SetArg::TCSANOW, original)?;
+        eprintln!();
+    }
+    result?;
+
+    while password.ends_with(['\n', '\r']) {
+        password.pop();
+    }
+
+    Ok(password)
+}
+
+/// Run a single authentication attempt with a password read from the
+/// terminal, using the same PAM path as the lock screen, without locking.
+pub fn test_auth(config: &NLockConfig) -> Result<()> {
+    let auth_config = AuthConfig::new(config);
+
+    let username = uzers::get_current_username().ok_or(anyhow!("Current user does not exist"))?;
+    let username = username.to_string_lossy().to_string();
+
+    let password = read_password(&format!("Password for {username}: "))?;
+
+    // A session opened here is closed again straight away
+    match authenticate(&auth_config, &username, password) {
+        Ok(_session) => {
+            println!("Authentication succeeded");
+            Ok(())
+        }
+        Err(e) => {
+            println!("Authentication failed: {e:#}");
+            bail!("Authentication test failed");
+        }
+    }
+}
+
+/// Run `authenticate` on a worker thread, giving up on it after the configured
+/// timeout, if any. PAM can't be interrupted, so a worker which timed out is
+/// left to finish in `pending`, and its result is discarded.
+fn authenticate_with_timeout(
+    config: &AuthConfig,
+    username: &str,
+    password: Zeroizing<String>,
+    pending: &mut Option<JoinHandle<()>>,
+) -> Option<Result<Option<PamSession>>> {
+    // Don't pile up workers while an abandoned attempt is still stuck in PAM
+    if let Some(worker) = pending.take() {
+        if !worker.is_finished() {
+            warn!("Previous PAM conversation still running, rejecting auth request");
+            *pending = Some(worker);
+            return None;
+        }
+
+        let _ = worker.join();
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let worker_config = config.clone();
+    let worker_username = username.to_string();
+    let worker = std::thread::spawn(move || {
+        // The receiver is gone if the attempt timed out
+        let _ = tx.send(authenticate(&worker_config, &worker_username, password));
+    });
+
+    let result = if config.timeout_ms == 0 {
+        rx.recv().ok()
+    } else {
+        match rx.recv_timeout(Duration::from_millis(config.timeout_ms)) {
+            Ok(result) => Some(result),
+            Err(RecvTimeoutError::Timeout) => {
+                warn!("PAM conversation timed out, abandoning it");
+                *pending = Some(worker);
+                return None;
+            }
+            Err(RecvTimeoutError::Disconnected) => None,
+        }
+    };
+
+    let _ = worker.join();
+    result.or_else(|| Some(Err(anyhow!("Auth worker exited without a result"))))
+}
+
+/// Handle an authentication request, returning the response to send. A PAM
+/// session opened on success is stored in `session`.
+fn handle_auth_request(
+    config: &AuthConfig,
+    auth_comm: Arc<AuthChannel>,
+    username: &str,
+    failures: u32,
+    pending: &mut Option<JoinHandle<()>>,
+    session: &mut Option<PamSession>,
+) -> AuthResponse {
     let pwd = match auth_comm.request.read().map(Zeroizing::new) {
         Ok(p) => p,
         Err(e) => {
             warn!("Auth comm error: {e}");
-            return false;
+            return AuthResponse::Fail;
         }
     };
 
-    match authenticate(config, username, pwd) {
-        Ok(()) => true,
-        Err(e) => {
-            warn!("Auth failed: {e}");
-            false
+    // Requests are still consumed when locked out, but never reach PAM
+    if attempts_exhausted(failures, config.max_attempts) {
+        warn!("Maximum attempts exceeded, rejecting auth request");
+        return AuthResponse::Fail;
+    }
+
+    match authenticate_with_timeout(config, username, pwd, pending) {
+        Some(Ok(opened)) => {
+            *session = opened;
+            AuthResponse::Success
+        }
+        Some(Err(e)) if e.is::<AccountUnusable>() => {
+            warn!("Auth failed, account unusable: {e:#}");
+            AuthResponse::AccountUnusable
+        }
+        Some(Err(e)) => {
+            warn!("Auth failed: {e:#}");
+            AuthResponse::Fail
         }
+        None => AuthResponse::TimedOut,
     }
 }
 
-pub fn run_auth_loop(config: AuthConfig, auth_comm: Arc<AuthChannel>) -> Result<()> {
+/// Spawn the authenticator loop in another thread.
+///
+/// `failures` is the number of failed attempts already made, so a restarted
+/// authenticator continues counting towards the attempt limit.
+pub fn spawn_auth_thread(
+    config: AuthConfig,
+    auth_comm: Arc<AuthChannel>,
+    failures: u32,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        if let Err(e) = run_auth_loop(config, auth_comm, failures) {
+            warn!("Error in auth thread: {e}");
+        }
+        debug!("Auth thread exited");
+    })
+}
+
+pub fn run_auth_loop(
+    config: AuthConfig,
+    auth_comm: Arc<AuthChannel>,
+    mut failures: u32,
+) -> Result<()> {
     let username = uzers::get_current_username().ok_or(anyhow!("Current user does not exist"))?;
     let username = username.to_string_lossy().to_string();
 
     debug!("Running authenticator for '{username}'");
 
     let mut success = false;
+    let mut pending = None;
+    // Kept until the authenticator stops, after unlocking, so session
+    // modules' keyrings stay unlocked for as long as nlock runs
+    let mut session: Option<PamSession> = None;
 
     loop {
         let req_fd = PollFd::new(auth_comm.request.rx().as_fd(), PollFlags::POLLIN);
@@ -110,10 +599,30 @@ pub fn run_auth_loop(config: AuthConfig, auth_comm: Arc<AuthChannel>) -> Result<
 
                 // auth was requested for a password
                 if events[0].any().unwrap_or_default() && !success {
-                    success = handle_auth_request(&config, auth_comm.clone(), &username);
+                    let response = handle_auth_request(
+                        &config,
+                        auth_comm.clone(),
+                        &username,
+                        failures,
+                        &mut pending,
+                        &mut session,
+                    );
+
+                    // Timeouts and unusable accounts don't count as failed
+                    // attempts, neither was a wrong password
+                    success = response == AuthResponse::Success;
+                    if response == AuthResponse::Fail {
+                        failures = failures.saturating_add(1);
+                    }
+
+                    if let Some(session) = &session {
+                        for (name, _) in session.env() {
+                            debug!("PAM session set environment variable {name}");
+                        }
+                    }
 
                     // dump auth result in response pipe
-                    if let Err(e) = auth_comm.response.write(success) {
+                    if let Err(e) = auth_comm.response.write(response) {
                         warn!("Failed to write auth response: {e}");
                     }
                 }
@@ -125,3 +634,45 @@ pub fn run_auth_loop(config: AuthConfig, auth_comm: Arc<AuthChannel>) -> Result<
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited_attempts_are_never_exhausted() {
+        assert!(!attempts_exhausted(0, 0));
+        assert!(!attempts_exhausted(u32::MAX, 0));
+    }
+
+    #[test]
+    fn attempts_exhausted_at_the_limit() {
+        assert!(!attempts_exhausted(2, 3));
+        assert!(attempts_exhausted(3, 3));
+        assert!(attempts_exhausted(4, 3));
+    }
+
+    #[test]
+    fn single_attempt_limit() {
+        assert!(!attempts_exhausted(0, 1));
+        assert!(attempts_exhausted(1, 1));
+    }
+
+    #[test]
+    fn pam_length_limit_is_inclusive() {
+        assert!(!exceeds_pam_length("abcd", 4));
+        assert!(exceeds_pam_length("abcde", 4));
+    }
+
+    #[test]
+    fn pam_length_counts_bytes() {
+        // Two characters, but four bytes
+        assert!(exceeds_pam_length("éé", 3));
+        assert!(!exceeds_pam_length("éé", 4));
+    }
+
+    #[test]
+    fn pam_length_zero_disables_check() {
+        assert!(!exceeds_pam_length(&"a".repeat(10_000), 0));
+    }
+}
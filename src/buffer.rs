@@ -2,7 +2,10 @@
 // Copyright (C) 2026, Nathan Gill
 
 use std::{
-    os::{fd::AsFd, raw::c_void},
+    os::{
+        fd::{AsFd, OwnedFd},
+        raw::c_void,
+    },
     ptr::NonNull,
     sync::{
         Arc,
@@ -12,22 +15,69 @@ use std::{
 
 use nix::{
     sys::mman::{MapFlags, ProtFlags, mmap, munmap},
-    unistd::ftruncate,
+    unistd::{SysconfVar, ftruncate, sysconf},
 };
 use wayland_client::{
-    Dispatch, QueueHandle,
-    protocol::{wl_buffer, wl_shm, wl_surface},
+    Dispatch, Proxy, QueueHandle,
+    protocol::{wl_buffer, wl_shm, wl_shm_pool, wl_surface},
+};
+
+use crate::{
+    state::NLockState,
+    util::{ShmBackend, open_shm},
 };
 
-use crate::{state::NLockState, util::open_shm};
+/// Shared memory pool backing all buffers of a surface, each buffer takes
+/// its own page aligned region. The pool only ever grows, the compositor
+/// can't shrink it, but regions of destroyed buffers are reused.
+pub struct NLockShmPool {
+    pool: wl_shm_pool::WlShmPool,
+    fd: OwnedFd,
+    // Bytes the pool currently holds
+    size: usize,
+    regions: PoolRegions,
+}
+
+/// Regions of a pool handed out to buffers, as offset and length, sorted by
+/// offset
+#[derive(Default)]
+struct PoolRegions(Vec<(usize, usize)>);
+
+impl PoolRegions {
+    /// Find the lowest page aligned offset with room for `size` bytes, either
+    /// in a gap left by a freed region, or past the last region
+    fn find(&self, size: usize, page: usize) -> Option<usize> {
+        let mut offset = 0;
+        for &(start, len) in &self.0 {
+            if offset.checked_add(size)? <= start {
+                return Some(offset);
+            }
+            offset = start.checked_add(len)?.checked_next_multiple_of(page)?;
+        }
+
+        Some(offset)
+    }
+
+    fn insert(&mut self, offset: usize, size: usize) {
+        let i = self.0.partition_point(|&(start, _)| start < offset);
+        self.0.insert(i, (offset, size));
+    }
+
+    fn remove(&mut self, offset: usize) {
+        self.0.retain(|&(start, _)| start != offset);
+    }
+}
 
 pub struct NLockBuffer {
     buffer: wl_buffer::WlBuffer,
     data: NonNull<c_void>,
+    // Start of this buffer's region in its pool
+    offset: usize,
 
     pub width: i32,
     pub height: i32,
     pub size: usize,
+    pub format: wl_shm::Format,
     pub state: Arc<NLockBufferState>,
     pub surface: cairo::ImageSurface,
     pub context: cairo::Context,
@@ -46,10 +96,28 @@ pub struct NLockBufferGuard<'a> {
 impl<'a> NLockBufferGuard<'a> {
     /// Attaches, damages, and commits the current buffer onto the specified
     /// surface.
-    pub fn commit_to(&mut self, surface: &wl_surface::WlSurface, scale: i32) {
+    ///
+    /// If `damage` is `None`, the whole surface is damaged, otherwise only
+    /// the specified rectangles, in buffer coordinates.
+    pub fn commit_to(
+        &mut self,
+        surface: &wl_surface::WlSurface,
+        scale: i32,
+        damage: Option<&[cairo::RectangleInt]>,
+    ) {
         surface.attach(Some(self.wl_buffer), 0, 0);
         surface.set_buffer_scale(scale);
-        surface.damage(0, 0, i32::MAX, i32::MAX);
+
+        // damage_buffer is only available from wl_surface version 4
+        match damage {
+            Some(rects) if surface.version() >= 4 => {
+                for rect in rects {
+                    surface.damage_buffer(rect.x(), rect.y(), rect.width(), rect.height());
+                }
+            }
+            _ => surface.damage(0, 0, i32::MAX, i32::MAX),
+        }
+
         surface.commit();
 
         self.committed = true;
@@ -64,9 +132,85 @@ impl<'a> Drop for NLockBufferGuard<'a> {
     }
 }
 
-impl NLockBuffer {
+impl NLockShmPool {
     pub fn new(
         shm: &wl_shm::WlShm,
+        size: usize,
+        shm_backend: ShmBackend,
+        qh: &QueueHandle<NLockState>,
+    ) -> Option<Self> {
+        let size = size.next_multiple_of(page_size());
+        // The protocol limits pools to an i32 size, checked before the file
+        // is sized
+        let pool_size = i32::try_from(size).ok()?;
+
+        let fd = open_shm(shm_backend)?;
+        ftruncate(&fd, i64::try_from(size).ok()?).ok()?;
+
+        let pool = shm.create_pool(fd.as_fd(), pool_size, qh, ());
+
+        Some(Self {
+            pool,
+            fd,
+            size,
+            regions: PoolRegions::default(),
+        })
+    }
+
+    /// Reserve `size` bytes in the pool, growing it if needed, and return
+    /// the offset of the reserved region.
+    fn allocate(&mut self, size: usize) -> Option<usize> {
+        // mmap offsets must be page aligned
+        let offset = self.regions.find(size, page_size())?;
+        let end = offset.checked_add(size)?;
+
+        if end > self.size {
+            // Grow past what's needed, so the next resize can reuse the space
+            let new_size = end
+                .max(self.size.saturating_mul(2))
+                .next_multiple_of(page_size());
+            let new_size = if i32::try_from(new_size).is_ok() {
+                new_size
+            } else {
+                end
+            };
+
+            // Checked before the file grows, so it never gets out of step
+            // with the pool
+            let pool_size = i32::try_from(new_size).ok()?;
+            ftruncate(&self.fd, i64::try_from(new_size).ok()?).ok()?;
+            self.pool.resize(pool_size);
+            self.size = new_size;
+        }
+
+        self.regions.insert(offset, size);
+
+        Some(offset)
+    }
+
+    /// Destroy `buffer`, and make its region available to new buffers
+    pub fn free(&mut self, buffer: &mut NLockBuffer) {
+        buffer.destroy();
+        self.regions.remove(buffer.offset);
+    }
+
+    pub fn destroy(&mut self) {
+        // Buffers created from the pool stay valid after this
+        self.pool.destroy();
+    }
+}
+
+fn page_size() -> usize {
+    sysconf(SysconfVar::PAGE_SIZE)
+        .ok()
+        .flatten()
+        .and_then(|size| usize::try_from(size).ok())
+        .unwrap_or(4096)
+}
+
+impl NLockBuffer {
+    pub fn new(
+        pool: &mut NLockShmPool,
         width: i32,
         height: i32,
         format: wl_shm::Format,
@@ -75,8 +219,7 @@ impl NLockBuffer {
         let stride = width * 4;
         let size = stride * height;
 
-        let fd = open_shm()?;
-        ftruncate(&fd, size as i64).ok()?;
+        let offset = pool.allocate(size as usize)?;
 
         let data = unsafe {
             mmap(
@@ -84,8 +227,8 @@ impl NLockBuffer {
                 std::num::NonZeroUsize::new_unchecked(size as usize),
                 ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
                 MapFlags::MAP_SHARED,
-                &fd,
-                0,
+                &pool.fd,
+                offset as i64,
             )
             .ok()?
         };
@@ -94,15 +237,27 @@ impl NLockBuffer {
             in_use: AtomicBool::new(false),
         });
 
-        let pool = shm.create_pool(fd.as_fd(), size, qh, ());
-        let buffer = pool.create_buffer(0, width, height, stride, format, qh, state.clone());
+        // The pool size always fits an i32, so does any offset into it
+        let buffer = pool.pool.create_buffer(
+            offset as i32,
+            width,
+            height,
+            stride,
+            format,
+            qh,
+            state.clone(),
+        );
 
-        pool.destroy();
+        // Cairo's RGB24 is XRGB8888 in memory, alpha byte is ignored
+        let cairo_format = match format {
+            wl_shm::Format::Xrgb8888 => cairo::Format::Rgb24,
+            _ => cairo::Format::ARgb32,
+        };
 
         let surface = unsafe {
             cairo::ImageSurface::create_for_data_unsafe(
                 data.as_ptr() as *mut u8,
-                cairo::Format::ARgb32,
+                cairo_format,
                 width,
                 height,
                 width * 4,
@@ -112,12 +267,20 @@ impl NLockBuffer {
 
         let context = cairo::Context::new(&surface).ok()?;
 
+        // Clear the buffer explicitly, so it never shows anything other than
+        // transparency before its first full render
+        context.set_operator(cairo::Operator::Clear);
+        context.paint().ok()?;
+        context.set_operator(cairo::Operator::Over);
+
         Some(Self {
             buffer,
             data,
+            offset,
             width,
             height,
             size: size as usize,
+            format,
             state,
             surface,
             context,
@@ -145,7 +308,7 @@ impl NLockBuffer {
 
 impl Dispatch<wl_buffer::WlBuffer, Arc<NLockBufferState>> for NLockState {
     fn event(
-        _: &mut Self,
+        state: &mut Self,
         _: &wl_buffer::WlBuffer,
         event: <wl_buffer::WlBuffer as wayland_client::Proxy>::Event,
         data: &Arc<NLockBufferState>,
@@ -154,6 +317,109 @@ impl Dispatch<wl_buffer::WlBuffer, Arc<NLockBufferState>> for NLockState {
     ) {
         if let wl_buffer::Event::Release = event {
             data.in_use.store(false, Ordering::Release);
+
+            // A render skipped for lack of a free buffer can go ahead now
+            if state.surfaces.iter().any(|s| s.render_deferred) {
+                state.state_changed.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use wayland_client::{Connection, EventQueue};
+
+    use super::*;
+
+    /// A pool whose protocol objects are inert, the file and mappings are real
+    fn test_pool(size: usize) -> (NLockShmPool, EventQueue<NLockState>) {
+        // Nothing is ever sent, so the other end needn't be a compositor
+        let (socket, _) = UnixStream::pair().unwrap();
+        let connection = Connection::from_socket(socket).unwrap();
+        let queue = connection.new_event_queue();
+        let shm = wl_shm::WlShm::inert(connection.backend().downgrade());
+
+        let pool = NLockShmPool::new(&shm, size, ShmBackend::Memfd, &queue.handle()).unwrap();
+        (pool, queue)
+    }
+
+    fn pixels(buffer: &NLockBuffer) -> &[u32] {
+        buffer.surface.flush();
+        unsafe { std::slice::from_raw_parts(buffer.data.as_ptr() as *const u32, buffer.size / 4) }
+    }
+
+    #[test]
+    fn two_buffers_share_one_pool() {
+        let (mut pool, queue) = test_pool(16 * 16 * 4);
+        let format = wl_shm::Format::Argb8888;
+
+        let first = NLockBuffer::new(&mut pool, 16, 16, format, &queue.handle()).unwrap();
+        let second = NLockBuffer::new(&mut pool, 16, 16, format, &queue.handle()).unwrap();
+
+        // Separate regions of the same pool, grown to fit both
+        assert_eq!(pool.regions.0.len(), 2);
+        assert!(second.offset >= first.offset + first.size);
+        assert!(pool.size >= second.offset + second.size);
+
+        for (buffer, (r, g, b)) in [(&first, (1.0, 0.0, 0.0)), (&second, (0.0, 0.0, 1.0))] {
+            buffer.context.set_source_rgb(r, g, b);
+            buffer.context.paint().unwrap();
         }
+
+        // Neither render overwrote the other
+        assert!(pixels(&first).iter().all(|&px| px == 0xffff0000));
+        assert!(pixels(&second).iter().all(|&px| px == 0xff0000ff));
+    }
+
+    #[test]
+    fn new_buffers_are_zeroed() {
+        let (mut pool, queue) = test_pool(16 * 16 * 4);
+        let format = wl_shm::Format::Argb8888;
+
+        let mut first = NLockBuffer::new(&mut pool, 16, 16, format, &queue.handle()).unwrap();
+        assert!(pixels(&first).iter().all(|&px| px == 0));
+
+        first.context.set_source_rgb(1.0, 1.0, 1.0);
+        first.context.paint().unwrap();
+        pool.free(&mut first);
+
+        // Takes over the region the white buffer left behind
+        let second = NLockBuffer::new(&mut pool, 16, 16, format, &queue.handle()).unwrap();
+        assert_eq!(second.offset, first.offset);
+        assert!(pixels(&second).iter().all(|&px| px == 0));
+    }
+
+    #[test]
+    fn freed_regions_are_reused() {
+        let (mut pool, queue) = test_pool(16 * 16 * 4);
+        let format = wl_shm::Format::Argb8888;
+
+        let mut first = NLockBuffer::new(&mut pool, 16, 16, format, &queue.handle()).unwrap();
+        let second = NLockBuffer::new(&mut pool, 16, 16, format, &queue.handle()).unwrap();
+        let size = pool.size;
+
+        pool.free(&mut first);
+        let third = NLockBuffer::new(&mut pool, 16, 16, format, &queue.handle()).unwrap();
+
+        assert_eq!(third.offset, 0);
+        assert_ne!(third.offset, second.offset);
+        assert_eq!(pool.size, size);
+    }
+
+    #[test]
+    fn regions_fill_gaps_page_aligned() {
+        let mut regions = PoolRegions::default();
+        assert_eq!(regions.find(100, 4096), Some(0));
+
+        regions.insert(0, 100);
+        regions.insert(4096, 8192);
+        assert_eq!(regions.find(100, 4096), Some(12288));
+
+        regions.remove(0);
+        assert_eq!(regions.find(4096, 4096), Some(0));
+        assert_eq!(regions.find(4097, 4096), Some(12288));
     }
 }
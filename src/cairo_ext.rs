@@ -21,6 +21,7 @@ impl CairoExt for Context {
 
 pub trait SubpixelOrderExt {
     fn from_wl_subpixel(subpixel: WEnum<wl_output::Subpixel>) -> SubpixelOrder;
+    fn transformed(self, transform: wl_output::Transform) -> Option<SubpixelOrder>;
 }
 
 impl SubpixelOrderExt for SubpixelOrder {
@@ -37,10 +38,69 @@ impl SubpixelOrderExt for SubpixelOrder {
             _ => SubpixelOrder::Default,
         }
     }
+
+    /// Get the subpixel order as seen by a buffer on an output with the
+    /// specified transform.
+    ///
+    /// Returns `None` if the subpixels are no longer aligned with the buffer
+    /// rows, i.e. the output is rotated by 90 or 270 degrees, in which case
+    /// subpixel anti-aliasing should not be used.
+    fn transformed(self, transform: wl_output::Transform) -> Option<SubpixelOrder> {
+        let flip_h = |order| match order {
+            SubpixelOrder::Rgb => SubpixelOrder::Bgr,
+            SubpixelOrder::Bgr => SubpixelOrder::Rgb,
+            order => order,
+        };
+        let flip_v = |order| match order {
+            SubpixelOrder::Vrgb => SubpixelOrder::Vbgr,
+            SubpixelOrder::Vbgr => SubpixelOrder::Vrgb,
+            order => order,
+        };
+
+        match transform {
+            wl_output::Transform::Normal => Some(self),
+            wl_output::Transform::_180 => Some(flip_v(flip_h(self))),
+            wl_output::Transform::Flipped => Some(flip_h(self)),
+            wl_output::Transform::Flipped180 => Some(flip_v(self)),
+            _ => None,
+        }
+    }
 }
 
 pub trait ImageSurfaceExt {
     fn create_from_pixbuf(pixbuf: &Pixbuf) -> Result<ImageSurface>;
+    fn apply_color_curve(&mut self, gamma: f64, temperature: f64) -> Result<()>;
+}
+
+pub const NEUTRAL_TEMPERATURE: f64 = 6500.0;
+
+/// Approximate the color of a black body at the specified temperature, in
+/// kelvin, as RGB factors from 0 to 1.
+///
+/// Uses Tanner Helland's curve fit, which is good enough between 1000K and
+/// 40000K.
+fn temperature_rgb(temperature: f64) -> [f64; 3] {
+    let t = temperature / 100.0;
+
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698727446 * (t - 60.0).powf(-0.1332047592)
+    };
+    let g = if t <= 66.0 {
+        99.4708025861 * t.ln() - 161.1195681661
+    } else {
+        288.1221695283 * (t - 60.0).powf(-0.0755148492)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.5177312231 * (t - 10.0).ln() - 305.0447927307
+    };
+
+    [r, g, b].map(|c| (c / 255.0).clamp(0.0, 1.0))
 }
 
 #[inline]
@@ -123,4 +183,144 @@ impl ImageSurfaceExt for ImageSurface {
         surface.mark_dirty();
         Ok(surface)
     }
+
+    /// Apply a gamma and color temperature curve to each pixel of an
+    /// `ARgb32` or `Rgb24` surface, in place.
+    ///
+    /// The temperature is relative to `NEUTRAL_TEMPERATURE`, so that leaves
+    /// colors unchanged, as does a gamma of 1.
+    fn apply_color_curve(&mut self, gamma: f64, temperature: f64) -> Result<()> {
+        let fmt = self.format();
+        if fmt != Format::ARgb32 && fmt != Format::Rgb24 {
+            return Err(anyhow!(cairo::Error::InvalidFormat));
+        }
+
+        let white = temperature_rgb(temperature);
+        let neutral = temperature_rgb(NEUTRAL_TEMPERATURE);
+
+        // One lookup table per channel, indexed by the unpremultiplied value
+        let mut curves = [[0u8; 256]; 3];
+        for (curve, factor) in curves
+            .iter_mut()
+            .zip(white.iter().zip(neutral).map(|(w, n)| w / n))
+        {
+            for (v, out) in curve.iter_mut().enumerate() {
+                let c = (v as f64 / 255.0).powf(1.0 / gamma) * factor;
+                *out = (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+
+        self.flush();
+
+        {
+            let width = self.width() as usize;
+            let height = self.height() as usize;
+            let stride = self.stride() as usize;
+            let mut pixels = self.data()?;
+
+            for y in 0..height {
+                let row = &mut pixels[y * stride..y * stride + 4 * width];
+
+                for px in row.chunks_exact_mut(4) {
+                    // Pixels are native endian 32-bit values, alpha highest
+                    let value = u32::from_ne_bytes([px[0], px[1], px[2], px[3]]);
+                    let a = if fmt == Format::Rgb24 {
+                        0xFF
+                    } else {
+                        (value >> 24) as u16
+                    };
+                    if a == 0 {
+                        continue;
+                    }
+
+                    let mut out = value & 0xFF00_0000;
+                    for (i, curve) in curves.iter().enumerate() {
+                        let shift = 16 - 8 * i;
+                        let c = ((value >> shift) & 0xFF) as u16;
+                        let c = ((c * 255 + a / 2) / a).min(255);
+                        out |= (premul(curve[c as usize] as u16, a) as u32) << shift;
+                    }
+
+                    px.copy_from_slice(&out.to_ne_bytes());
+                }
+            }
+        }
+
+        self.mark_dirty();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gdk_pixbuf::Colorspace;
+
+    use super::*;
+
+    /// Pixels of a surface as native-endian words, row by row
+    fn pixels(surface: &ImageSurface) -> Vec<u32> {
+        let width = surface.width() as usize;
+        let stride = surface.stride() as usize;
+
+        let mut pixels = Vec::new();
+        surface
+            .with_data(|data| {
+                for row in data.chunks(stride) {
+                    pixels.extend(
+                        row[..width * 4]
+                            .chunks(4)
+                            .map(|px| u32::from_ne_bytes(px.try_into().unwrap())),
+                    );
+                }
+            })
+            .unwrap();
+
+        pixels
+    }
+
+    /// A red, green and blue pixel, opaque on the first row, half transparent
+    /// on the second. An odd width pads the rows of an RGB pixbuf.
+    fn test_pixbuf(has_alpha: bool) -> Pixbuf {
+        let pixbuf = Pixbuf::new(Colorspace::Rgb, has_alpha, 8, 3, 2).unwrap();
+        for (x, (r, g, b)) in [(255, 0, 0), (0, 255, 0), (0, 0, 255)]
+            .into_iter()
+            .enumerate()
+        {
+            pixbuf.put_pixel(x as u32, 0, r, g, b, 255);
+            pixbuf.put_pixel(x as u32, 1, r, g, b, 128);
+        }
+
+        pixbuf
+    }
+
+    #[test]
+    fn rgb_pixbuf_converts_to_rgb24() {
+        let pixbuf = test_pixbuf(false);
+        assert_eq!(pixbuf.n_channels(), 3);
+
+        let surface = ImageSurface::create_from_pixbuf(&pixbuf).unwrap();
+        assert_eq!(surface.format(), Format::Rgb24);
+
+        // The top byte is unused in RGB24, and alpha is ignored
+        let pixels: Vec<u32> = pixels(&surface).iter().map(|px| px & 0xffffff).collect();
+        assert_eq!(
+            pixels,
+            [0xff0000, 0x00ff00, 0x0000ff, 0xff0000, 0x00ff00, 0x0000ff]
+        );
+    }
+
+    #[test]
+    fn rgba_pixbuf_converts_to_premultiplied_argb32() {
+        let pixbuf = test_pixbuf(true);
+        assert_eq!(pixbuf.n_channels(), 4);
+
+        let surface = ImageSurface::create_from_pixbuf(&pixbuf).unwrap();
+        assert_eq!(surface.format(), Format::ARgb32);
+        assert_eq!(
+            pixels(&surface),
+            [
+                0xffff0000, 0xff00ff00, 0xff0000ff, 0x80800000, 0x80008000, 0x80000080
+            ]
+        );
+    }
 }
@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2026, Nathan Gill
+
+use crate::state::NLockState;
+
+/// Globals nlock cannot lock without
+const REQUIRED_GLOBALS: &[&str] = &[
+    "wl_compositor",
+    "wl_subcompositor",
+    "wl_shm",
+    "wl_seat",
+    "ext_session_lock_manager_v1",
+];
+
+/// Globals which are useful, but not required
+const OPTIONAL_GLOBALS: &[&str] = &[
+    "wp_fractional_scale_manager_v1",
+    "wp_viewporter",
+    "wp_single_pixel_buffer_manager_v1",
+    "wp_cursor_shape_manager_v1",
+    "zwlr_screencopy_manager_v1",
+    "ext_image_copy_capture_manager_v1",
+];
+
+/// Result of checking the globals advertised by the compositor
+pub struct NLockCheck {
+    pub required: Vec<(&'static str, Option<u32>)>,
+    pub optional: Vec<(&'static str, Option<u32>)>,
+    pub outputs: usize,
+}
+
+impl NLockCheck {
+    pub fn new(state: &NLockState) -> Self {
+        let version = |interface: &'static str| {
+            let version = state
+                .globals
+                .iter()
+                .find(|(name, _)| name == interface)
+                .map(|(_, version)| *version);
+
+            (interface, version)
+        };
+
+        Self {
+            required: REQUIRED_GLOBALS.iter().map(|&i| version(i)).collect(),
+            optional: OPTIONAL_GLOBALS.iter().map(|&i| version(i)).collect(),
+            outputs: state.surfaces.len(),
+        }
+    }
+
+    /// Check whether all required globals are present
+    pub fn ok(&self) -> bool {
+        self.required.iter().all(|(_, version)| version.is_some())
+    }
+
+    pub fn print(&self) {
+        let width = REQUIRED_GLOBALS
+            .iter()
+            .chain(OPTIONAL_GLOBALS)
+            .map(|i| i.len())
+            .max()
+            .unwrap_or(0);
+
+        let print_globals = |globals: &[(&str, Option<u32>)]| {
+            for (interface, version) in globals {
+                match version {
+                    Some(version) => println!("  {interface:width$}  v{version}"),
+                    None => println!("  {interface:width$}  missing"),
+                }
+            }
+        };
+
+        println!("Required globals:");
+        print_globals(&self.required);
+        println!("Optional globals:");
+        print_globals(&self.optional);
+        println!("Outputs: {}", self.outputs);
+    }
+
+    pub fn print_json(&self) {
+        // Interface names are plain identifiers, so need no escaping
+        let json_globals = |globals: &[(&str, Option<u32>)]| {
+            globals
+                .iter()
+                .map(|(interface, version)| {
+                    let version = version.map_or("null".to_string(), |v| v.to_string());
+                    format!("{{\"interface\":\"{interface}\",\"version\":{version}}}")
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        println!(
+            "{{\"ok\":{},\"required\":[{}],\"optional\":[{}],\"outputs\":{}}}",
+            self.ok(),
+            json_globals(&self.required),
+            json_globals(&self.optional),
+            self.outputs
+        );
+    }
+}
@@ -1,17 +1,25 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright (C) 2026, Nathan Gill
 
-use std::path::PathBuf;
+use std::{
+    io::{self, Read},
+    path::PathBuf,
+};
 
-use anyhow::{Result, anyhow};
-use config::{Config, File, FileFormat};
+use anyhow::{Result, anyhow, bail};
+use config::{Config, File, FileFormat, Value, ValueKind};
 use dirs::config_dir;
 use serde::Deserialize;
-use tracing::debug;
+use tracing::{Level, debug, enabled, info};
 
 use crate::{
     args::NLockArgs,
-    util::{BackgroundImageScale, BackgroundType, FontSlant, FontWeight, InputVisibility, Rgba},
+    util::{
+        BackgroundImageScale, BackgroundType, BufferFormat, EmptyBackspaceFeedback, FontAntialias,
+        FontSlant, FontWeight, ImageAnchor, InputEcho, InputOverflow, InputVisibility, KeyBinding,
+        Rgba, SecondaryMode, ShapeAntialias, ShmBackend, StrokeAlign, SubmitModifiers,
+        TextDirection,
+    },
 };
 
 const CONFIG_FILE_NAME: &str = "nlock.toml";
@@ -66,6 +74,27 @@ pub struct NLockConfig {
 
     #[serde(default)]
     pub image: NLockConfigImage,
+
+    #[serde(default)]
+    pub animation: NLockConfigAnimation,
+
+    #[serde(default)]
+    pub xkb: NLockConfigXkb,
+
+    #[serde(default)]
+    pub keys: NLockConfigKeys,
+
+    #[serde(default)]
+    pub overlay: NLockConfigOverlay,
+
+    #[serde(default)]
+    pub spinner: NLockConfigSpinner,
+
+    #[serde(default)]
+    pub prompt: NLockConfigPrompt,
+
+    #[serde(default)]
+    pub text: NLockConfigText,
 }
 
 impl LoadArgOverrides for NLockConfig {
@@ -76,6 +105,13 @@ impl LoadArgOverrides for NLockConfig {
         self.frame.load_arg_overrides(args);
         self.general.load_arg_overrides(args);
         self.image.load_arg_overrides(args);
+        self.animation.load_arg_overrides(args);
+        self.xkb.load_arg_overrides(args);
+        self.keys.load_arg_overrides(args);
+        self.overlay.load_arg_overrides(args);
+        self.spinner.load_arg_overrides(args);
+        self.prompt.load_arg_overrides(args);
+        self.text.load_arg_overrides(args);
     }
 }
 
@@ -111,6 +147,30 @@ pub struct NLockConfigColors {
         rename = "frameBorderFail"
     )]
     pub frame_border_fail: Rgba,
+
+    #[serde(default = "default_key_highlight_color", rename = "keyHighlight")]
+    pub key_highlight: Rgba,
+
+    #[serde(
+        default = "default_backspace_highlight_color",
+        rename = "backspaceHighlight"
+    )]
+    pub backspace_highlight: Rgba,
+
+    #[serde(default = "default_spinner_color", rename = "spinner")]
+    pub spinner: Rgba,
+
+    #[serde(default = "default_prompt_color", rename = "prompt")]
+    pub prompt: Rgba,
+
+    #[serde(default = "default_text_outline_color", rename = "textOutline")]
+    pub text_outline: Rgba,
+
+    #[serde(default = "default_input_glow_color", rename = "inputGlow")]
+    pub input_glow: Rgba,
+
+    #[serde(default = "default_vignette_color", rename = "vignette")]
+    pub vignette: Rgba,
 }
 
 impl Default for NLockConfigColors {
@@ -123,6 +183,13 @@ impl Default for NLockConfigColors {
             frame_border_idle: default_frame_border_idle_color(),
             frame_border_success: default_frame_border_success_color(),
             frame_border_fail: default_frame_border_fail_color(),
+            key_highlight: default_key_highlight_color(),
+            backspace_highlight: default_backspace_highlight_color(),
+            spinner: default_spinner_color(),
+            prompt: default_prompt_color(),
+            text_outline: default_text_outline_color(),
+            input_glow: default_input_glow_color(),
+            vignette: default_vignette_color(),
         }
     }
 }
@@ -136,6 +203,13 @@ impl LoadArgOverrides for NLockConfigColors {
         set_if_some!(self.frame_border_idle, args.frame_border_idle_color);
         set_if_some!(self.frame_border_success, args.frame_border_success_color);
         set_if_some!(self.frame_border_fail, args.frame_border_fail_color);
+        set_if_some!(self.key_highlight, args.key_highlight_color);
+        set_if_some!(self.backspace_highlight, args.backspace_highlight_color);
+        set_if_some!(self.spinner, args.spinner_color);
+        set_if_some!(self.prompt, args.prompt_color);
+        set_if_some!(self.text_outline, args.text_outline_color);
+        set_if_some!(self.input_glow, args.input_glow_color);
+        set_if_some!(self.vignette, args.vignette_color);
     }
 }
 
@@ -167,6 +241,34 @@ fn default_frame_border_fail_color() -> Rgba {
     Rgba::new(1.0, 0.0, 0.0, 1.0)
 }
 
+fn default_key_highlight_color() -> Rgba {
+    Rgba::new(0.2, 0.8, 0.2, 1.0)
+}
+
+fn default_backspace_highlight_color() -> Rgba {
+    Rgba::new(0.8, 0.2, 0.0, 1.0)
+}
+
+fn default_spinner_color() -> Rgba {
+    Rgba::new(1.0, 1.0, 1.0, 1.0)
+}
+
+fn default_prompt_color() -> Rgba {
+    Rgba::new(1.0, 1.0, 1.0, 1.0)
+}
+
+fn default_text_outline_color() -> Rgba {
+    Rgba::new(0.0, 0.0, 0.0, 1.0)
+}
+
+fn default_input_glow_color() -> Rgba {
+    Rgba::new(1.0, 1.0, 1.0, 0.25)
+}
+
+fn default_vignette_color() -> Rgba {
+    Rgba::new(0.0, 0.0, 0.0, 1.0)
+}
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct NLockConfigFont {
@@ -176,6 +278,9 @@ pub struct NLockConfigFont {
     #[serde(default = "default_font_family")]
     pub family: String,
 
+    #[serde(default)]
+    pub fallback: Vec<String>,
+
     #[serde(default = "default_font_slant")]
     pub slant: FontSlant,
 
@@ -184,6 +289,18 @@ pub struct NLockConfigFont {
 
     #[serde(default = "default_font_use_dpi_scaling", rename = "useDpiScaling")]
     pub use_dpi_scaling: bool,
+
+    #[serde(default = "default_font_assumed_dpi", rename = "assumedDpi")]
+    pub assumed_dpi: f64,
+
+    #[serde(default = "default_font_features")]
+    pub features: String,
+
+    #[serde(default = "default_font_antialias")]
+    pub antialias: FontAntialias,
+
+    #[serde(default = "default_font_direction")]
+    pub direction: TextDirection,
 }
 
 impl Default for NLockConfigFont {
@@ -191,9 +308,14 @@ impl Default for NLockConfigFont {
         Self {
             size: default_font_size(),
             family: default_font_family(),
+            fallback: Vec::new(),
             slant: default_font_slant(),
             weight: default_font_weight(),
             use_dpi_scaling: default_font_use_dpi_scaling(),
+            assumed_dpi: default_font_assumed_dpi(),
+            features: default_font_features(),
+            antialias: default_font_antialias(),
+            direction: default_font_direction(),
         }
     }
 }
@@ -202,9 +324,14 @@ impl LoadArgOverrides for NLockConfigFont {
     fn load_arg_overrides(&mut self, args: &NLockArgs) {
         set_if_some!(self.size, args.font_size);
         set_if_some_string!(self.family, &args.font_family);
+        set_if_some!(self.fallback, args.font_fallback.clone());
         set_if_some!(self.slant, args.font_slant);
         set_if_some!(self.weight, args.font_weight);
         set_if_some!(self.use_dpi_scaling, args.use_dpi_scaling);
+        set_if_some!(self.assumed_dpi, args.assumed_dpi);
+        set_if_some_string!(self.features, &args.font_features);
+        set_if_some!(self.antialias, args.font_antialias);
+        set_if_some!(self.direction, args.font_direction);
     }
 }
 
@@ -216,6 +343,10 @@ fn default_font_family() -> String {
     "".to_string()
 }
 
+fn default_font_direction() -> TextDirection {
+    TextDirection::Auto
+}
+
 fn default_font_slant() -> FontSlant {
     FontSlant::Normal
 }
@@ -228,6 +359,18 @@ fn default_font_use_dpi_scaling() -> bool {
     false
 }
 
+fn default_font_assumed_dpi() -> f64 {
+    0.0f64
+}
+
+fn default_font_features() -> String {
+    "".to_string()
+}
+
+fn default_font_antialias() -> FontAntialias {
+    FontAntialias::Auto
+}
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct NLockConfigInput {
@@ -237,6 +380,9 @@ pub struct NLockConfigInput {
     #[serde(default = "default_input_width")]
     pub width: f64,
 
+    #[serde(default = "default_input_height")]
+    pub height: f64,
+
     #[serde(default = "default_input_padding", rename = "paddingX")]
     pub padding_x: f64,
 
@@ -249,11 +395,74 @@ pub struct NLockConfigInput {
     #[serde(default = "default_input_border")]
     pub border: f64,
 
+    #[serde(default = "default_input_inner_glow", rename = "innerGlow")]
+    pub inner_glow: f64,
+
     #[serde(default = "default_input_visible")]
     pub visible: InputVisibility,
 
     #[serde(default = "default_input_fit_to_content", rename = "fitToContent")]
     pub fit_to_content: bool,
+
+    #[serde(default = "default_input_echo")]
+    pub echo: InputEcho,
+
+    #[serde(default = "default_input_overflow")]
+    pub overflow: InputOverflow,
+
+    #[serde(default = "default_input_block_count", rename = "blockCount")]
+    pub block_count: u32,
+
+    #[serde(default = "default_input_compose")]
+    pub compose: bool,
+
+    #[serde(default = "default_shape_antialias")]
+    pub antialias: ShapeAntialias,
+
+    #[serde(default = "default_input_caret")]
+    pub caret: bool,
+
+    #[serde(default = "default_input_caret_blink_ms", rename = "caretBlinkMs")]
+    pub caret_blink_ms: u64,
+
+    #[serde(default = "default_input_wake_ms", rename = "wakeMs")]
+    pub wake_ms: u64,
+
+    #[serde(default = "default_input_pam_max_length", rename = "pamMaxLength")]
+    pub pam_max_length: usize,
+
+    #[serde(
+        default = "default_input_min_submit_length",
+        rename = "minSubmitLength"
+    )]
+    pub min_submit_length: usize,
+
+    #[serde(
+        default = "default_input_too_short_message",
+        rename = "tooShortMessage"
+    )]
+    pub too_short_message: String,
+
+    #[serde(default = "default_input_clear_on_fail", rename = "clearOnFail")]
+    pub clear_on_fail: bool,
+
+    #[serde(
+        default = "default_input_numpad_always_digits",
+        rename = "numpadAlwaysDigits"
+    )]
+    pub numpad_always_digits: bool,
+
+    #[serde(
+        default = "default_input_empty_backspace_feedback",
+        rename = "emptyBackspaceFeedback"
+    )]
+    pub empty_backspace_feedback: EmptyBackspaceFeedback,
+
+    #[serde(default = "default_input_force_repeat", rename = "forceRepeatRate")]
+    pub force_repeat_rate: u32,
+
+    #[serde(default = "default_input_force_repeat", rename = "forceRepeatDelay")]
+    pub force_repeat_delay: u32,
 }
 
 impl Default for NLockConfigInput {
@@ -261,12 +470,30 @@ impl Default for NLockConfigInput {
         Self {
             mask_char: default_mask_char(),
             width: default_input_width(),
+            height: default_input_height(),
             padding_x: default_input_padding(),
             padding_y: default_input_padding(),
             radius: default_input_radius(),
             border: default_input_border(),
+            inner_glow: default_input_inner_glow(),
             visible: default_input_visible(),
             fit_to_content: default_input_fit_to_content(),
+            echo: default_input_echo(),
+            overflow: default_input_overflow(),
+            block_count: default_input_block_count(),
+            compose: default_input_compose(),
+            antialias: default_shape_antialias(),
+            caret: default_input_caret(),
+            caret_blink_ms: default_input_caret_blink_ms(),
+            wake_ms: default_input_wake_ms(),
+            pam_max_length: default_input_pam_max_length(),
+            min_submit_length: default_input_min_submit_length(),
+            too_short_message: default_input_too_short_message(),
+            clear_on_fail: default_input_clear_on_fail(),
+            numpad_always_digits: default_input_numpad_always_digits(),
+            empty_backspace_feedback: default_input_empty_backspace_feedback(),
+            force_repeat_rate: default_input_force_repeat(),
+            force_repeat_delay: default_input_force_repeat(),
         }
     }
 }
@@ -275,12 +502,33 @@ impl LoadArgOverrides for NLockConfigInput {
     fn load_arg_overrides(&mut self, args: &NLockArgs) {
         set_if_some_string!(self.mask_char, &args.mask_char);
         set_if_some!(self.width, args.input_width);
+        set_if_some!(self.height, args.input_height);
         set_if_some!(self.padding_x, args.input_padding_x);
         set_if_some!(self.padding_y, args.input_padding_y);
         set_if_some!(self.radius, args.input_radius);
         set_if_some!(self.border, args.input_border);
+        set_if_some!(self.inner_glow, args.input_inner_glow);
         set_if_some!(self.visible, args.input_visible);
         set_if_some!(self.fit_to_content, args.fit_to_content);
+        set_if_some!(self.echo, args.input_echo);
+        set_if_some!(self.overflow, args.input_overflow);
+        set_if_some!(self.block_count, args.input_block_count);
+        set_if_some!(self.compose, args.input_compose);
+        set_if_some!(self.antialias, args.input_antialias);
+        set_if_some!(self.caret, args.input_caret);
+        set_if_some!(self.caret_blink_ms, args.input_caret_blink_ms);
+        set_if_some!(self.wake_ms, args.input_wake_ms);
+        set_if_some!(self.pam_max_length, args.input_pam_max_length);
+        set_if_some!(self.min_submit_length, args.input_min_submit_length);
+        set_if_some_string!(self.too_short_message, &args.input_too_short_message);
+        set_if_some!(self.clear_on_fail, args.input_clear_on_fail);
+        set_if_some!(self.numpad_always_digits, args.input_numpad_always_digits);
+        set_if_some!(
+            self.empty_backspace_feedback,
+            args.input_empty_backspace_feedback
+        );
+        set_if_some!(self.force_repeat_rate, args.input_force_repeat_rate);
+        set_if_some!(self.force_repeat_delay, args.input_force_repeat_delay);
     }
 }
 
@@ -292,6 +540,10 @@ fn default_input_width() -> f64 {
     0.5f64
 }
 
+fn default_input_height() -> f64 {
+    0.0f64
+}
+
 fn default_input_padding() -> f64 {
     0.05f64
 }
@@ -304,6 +556,10 @@ fn default_input_border() -> f64 {
     0.0f64
 }
 
+fn default_input_inner_glow() -> f64 {
+    0.0
+}
+
 fn default_input_visible() -> InputVisibility {
     InputVisibility::Always
 }
@@ -312,6 +568,63 @@ fn default_input_fit_to_content() -> bool {
     false
 }
 
+fn default_input_echo() -> InputEcho {
+    InputEcho::Mask
+}
+
+fn default_input_block_count() -> u32 {
+    8
+}
+
+fn default_input_overflow() -> InputOverflow {
+    InputOverflow::Clip
+}
+
+fn default_input_compose() -> bool {
+    true
+}
+
+fn default_input_caret() -> bool {
+    false
+}
+
+fn default_input_caret_blink_ms() -> u64 {
+    500
+}
+
+fn default_input_wake_ms() -> u64 {
+    3000
+}
+
+/// Linux-PAM and OpenPAM both limit responses to `PAM_MAX_RESP_SIZE` bytes
+fn default_input_pam_max_length() -> usize {
+    512
+}
+
+fn default_input_min_submit_length() -> usize {
+    0
+}
+
+fn default_input_too_short_message() -> String {
+    "Password too short".to_string()
+}
+
+fn default_input_clear_on_fail() -> bool {
+    true
+}
+
+fn default_input_numpad_always_digits() -> bool {
+    true
+}
+
+fn default_input_empty_backspace_feedback() -> EmptyBackspaceFeedback {
+    EmptyBackspaceFeedback::None
+}
+
+fn default_input_force_repeat() -> u32 {
+    0
+}
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct NLockConfigFrame {
@@ -320,6 +633,15 @@ pub struct NLockConfigFrame {
 
     #[serde(default = "default_frame_radius")]
     pub radius: f64,
+
+    #[serde(default = "default_shape_antialias")]
+    pub antialias: ShapeAntialias,
+
+    #[serde(default = "default_frame_stroke_align", rename = "strokeAlign")]
+    pub stroke_align: StrokeAlign,
+
+    #[serde(default = "default_frame_inset")]
+    pub inset: f64,
 }
 
 impl Default for NLockConfigFrame {
@@ -327,6 +649,9 @@ impl Default for NLockConfigFrame {
         Self {
             border: default_frame_border(),
             radius: default_frame_radius(),
+            antialias: default_shape_antialias(),
+            stroke_align: default_frame_stroke_align(),
+            inset: default_frame_inset(),
         }
     }
 }
@@ -335,6 +660,9 @@ impl LoadArgOverrides for NLockConfigFrame {
     fn load_arg_overrides(&mut self, args: &NLockArgs) {
         set_if_some!(self.border, args.frame_border);
         set_if_some!(self.radius, args.frame_radius);
+        set_if_some!(self.antialias, args.frame_antialias);
+        set_if_some!(self.stroke_align, args.frame_stroke_align);
+        set_if_some!(self.inset, args.frame_inset);
     }
 }
 
@@ -346,6 +674,18 @@ fn default_frame_radius() -> f64 {
     0.0f64
 }
 
+fn default_frame_stroke_align() -> StrokeAlign {
+    StrokeAlign::Inside
+}
+
+fn default_frame_inset() -> f64 {
+    0.0f64
+}
+
+fn default_shape_antialias() -> ShapeAntialias {
+    ShapeAntialias::Best
+}
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct NLockConfigGeneral {
@@ -355,8 +695,83 @@ pub struct NLockConfigGeneral {
     #[serde(default = "default_hide_cursor", rename = "hideCursor")]
     pub hide_cursor: bool,
 
+    #[serde(default = "default_transparent_cursor", rename = "transparentCursor")]
+    pub transparent_cursor: bool,
+
     #[serde(default = "default_bg_type", rename = "backgroundType")]
     pub bg_type: BackgroundType,
+
+    #[serde(default = "default_bg_format", rename = "backgroundFormat")]
+    pub bg_format: BufferFormat,
+
+    #[serde(default = "default_shm_backend", rename = "shmBackend")]
+    pub shm_backend: ShmBackend,
+
+    #[serde(default = "default_pam_session", rename = "pamSession")]
+    pub pam_session: bool,
+
+    #[serde(default = "default_max_attempts", rename = "maxAttempts")]
+    pub max_attempts: u32,
+
+    #[serde(default = "default_lockout_message", rename = "lockoutMessage")]
+    pub lockout_message: String,
+
+    #[serde(default = "default_auth_restart_limit", rename = "authRestartLimit")]
+    pub auth_restart_limit: u32,
+
+    #[serde(default = "default_auth_timeout_ms", rename = "authTimeoutMs")]
+    pub auth_timeout_ms: u64,
+
+    #[serde(default = "default_wait_for_surfaces", rename = "waitForSurfaces")]
+    pub wait_for_surfaces: bool,
+
+    #[serde(default = "default_relock_limit", rename = "relockLimit")]
+    pub relock_limit: u32,
+
+    #[serde(default = "default_connect_attempts", rename = "connectAttempts")]
+    pub connect_attempts: u32,
+
+    #[serde(default = "default_connect_retry_ms", rename = "connectRetryMs")]
+    pub connect_retry_ms: u64,
+
+    #[serde(
+        default = "default_max_buffer_dimension",
+        rename = "maxBufferDimension"
+    )]
+    pub max_buffer_dimension: u32,
+
+    #[serde(default = "default_secondary_mode", rename = "secondaryMode")]
+    pub secondary_mode: SecondaryMode,
+
+    #[serde(default = "default_min_surface_scale", rename = "minSurfaceScale")]
+    pub min_surface_scale: f64,
+
+    #[serde(default = "default_audit_log", rename = "auditLog")]
+    pub audit_log: bool,
+
+    #[serde(default = "default_power_save", rename = "powerSave")]
+    pub power_save: bool,
+
+    #[serde(default = "default_low_memory", rename = "lowMemory")]
+    pub low_memory: bool,
+
+    #[serde(default = "default_prerender")]
+    pub prerender: bool,
+
+    #[serde(default = "default_pixel_snap", rename = "pixelSnap")]
+    pub pixel_snap: bool,
+
+    #[serde(default = "default_overlay_fallback", rename = "overlayFallback")]
+    pub overlay_fallback: bool,
+
+    #[serde(default = "default_debug_overlay", rename = "debugOverlay")]
+    pub debug_overlay: bool,
+
+    #[serde(default)]
+    pub outputs: NLockConfigOutputs,
+
+    #[serde(default)]
+    pub margins: NLockConfigMargins,
 }
 
 impl Default for NLockConfigGeneral {
@@ -364,7 +779,31 @@ impl Default for NLockConfigGeneral {
         Self {
             pwd_allow_empty: default_pwd_allow_empty(),
             hide_cursor: default_hide_cursor(),
+            transparent_cursor: default_transparent_cursor(),
             bg_type: default_bg_type(),
+            bg_format: default_bg_format(),
+            shm_backend: default_shm_backend(),
+            pam_session: default_pam_session(),
+            max_attempts: default_max_attempts(),
+            lockout_message: default_lockout_message(),
+            auth_restart_limit: default_auth_restart_limit(),
+            auth_timeout_ms: default_auth_timeout_ms(),
+            wait_for_surfaces: default_wait_for_surfaces(),
+            relock_limit: default_relock_limit(),
+            connect_attempts: default_connect_attempts(),
+            connect_retry_ms: default_connect_retry_ms(),
+            max_buffer_dimension: default_max_buffer_dimension(),
+            secondary_mode: default_secondary_mode(),
+            min_surface_scale: default_min_surface_scale(),
+            audit_log: default_audit_log(),
+            power_save: default_power_save(),
+            low_memory: default_low_memory(),
+            prerender: default_prerender(),
+            pixel_snap: default_pixel_snap(),
+            overlay_fallback: default_overlay_fallback(),
+            debug_overlay: default_debug_overlay(),
+            outputs: NLockConfigOutputs::default(),
+            margins: NLockConfigMargins::default(),
         }
     }
 }
@@ -373,7 +812,31 @@ impl LoadArgOverrides for NLockConfigGeneral {
     fn load_arg_overrides(&mut self, args: &NLockArgs) {
         set_if_some!(self.pwd_allow_empty, args.pwd_allow_empty);
         set_if_some!(self.hide_cursor, args.hide_cursor);
+        set_if_some!(self.transparent_cursor, args.transparent_cursor);
         set_if_some!(self.bg_type, args.bg_type);
+        set_if_some!(self.bg_format, args.bg_format);
+        set_if_some!(self.shm_backend, args.shm_backend);
+        set_if_some!(self.pam_session, args.pam_session);
+        set_if_some!(self.max_attempts, args.max_attempts);
+        set_if_some_string!(self.lockout_message, &args.lockout_message);
+        set_if_some!(self.auth_restart_limit, args.auth_restart_limit);
+        set_if_some!(self.auth_timeout_ms, args.auth_timeout_ms);
+        set_if_some!(self.wait_for_surfaces, args.wait_for_surfaces);
+        set_if_some!(self.relock_limit, args.relock_limit);
+        set_if_some!(self.connect_attempts, args.connect_attempts);
+        set_if_some!(self.connect_retry_ms, args.connect_retry_ms);
+        set_if_some!(self.max_buffer_dimension, args.max_buffer_dimension);
+        set_if_some!(self.secondary_mode, args.secondary_mode);
+        set_if_some!(self.min_surface_scale, args.min_surface_scale);
+        set_if_some!(self.audit_log, args.audit_log);
+        set_if_some!(self.power_save, args.power_save);
+        set_if_some!(self.low_memory, args.low_memory);
+        set_if_some!(self.prerender, args.prerender);
+        set_if_some!(self.pixel_snap, args.pixel_snap);
+        set_if_some!(self.overlay_fallback, args.overlay_fallback);
+        set_if_some!(self.debug_overlay, args.debug_overlay);
+        self.outputs.load_arg_overrides(args);
+        self.margins.load_arg_overrides(args);
     }
 }
 
@@ -385,10 +848,161 @@ fn default_hide_cursor() -> bool {
     true
 }
 
+fn default_transparent_cursor() -> bool {
+    false
+}
+
 fn default_bg_type() -> BackgroundType {
     BackgroundType::Color
 }
 
+fn default_bg_format() -> BufferFormat {
+    BufferFormat::Argb
+}
+
+fn default_shm_backend() -> ShmBackend {
+    ShmBackend::Memfd
+}
+
+fn default_pam_session() -> bool {
+    false
+}
+
+fn default_max_attempts() -> u32 {
+    0
+}
+
+fn default_lockout_message() -> String {
+    "Too many failed attempts".to_string()
+}
+
+fn default_auth_restart_limit() -> u32 {
+    3
+}
+
+fn default_auth_timeout_ms() -> u64 {
+    0
+}
+
+fn default_wait_for_surfaces() -> bool {
+    false
+}
+
+fn default_relock_limit() -> u32 {
+    0
+}
+
+fn default_connect_attempts() -> u32 {
+    5
+}
+
+fn default_connect_retry_ms() -> u64 {
+    100
+}
+
+fn default_max_buffer_dimension() -> u32 {
+    16384
+}
+
+fn default_secondary_mode() -> SecondaryMode {
+    SecondaryMode::Full
+}
+
+fn default_min_surface_scale() -> f64 {
+    0.25
+}
+
+fn default_audit_log() -> bool {
+    false
+}
+
+fn default_power_save() -> bool {
+    false
+}
+
+fn default_low_memory() -> bool {
+    false
+}
+
+fn default_prerender() -> bool {
+    false
+}
+
+fn default_pixel_snap() -> bool {
+    false
+}
+
+fn default_overlay_fallback() -> bool {
+    false
+}
+
+fn default_debug_overlay() -> bool {
+    false
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NLockConfigOutputs {
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl NLockConfigOutputs {
+    /// Check whether the full lock screen is shown on an output, matched by
+    /// its name, description, or "make model". Other outputs only show a plain
+    /// color.
+    pub fn allows(
+        &self,
+        name: Option<&str>,
+        description: Option<&str>,
+        make_model: Option<&str>,
+    ) -> bool {
+        let matches = |list: &[String]| {
+            list.iter().any(|o| {
+                let o = Some(o.as_str());
+                o == name || o == description || o == make_model
+            })
+        };
+
+        (self.include.is_empty() || matches(&self.include)) && !matches(&self.exclude)
+    }
+}
+
+impl LoadArgOverrides for NLockConfigOutputs {
+    fn load_arg_overrides(&mut self, args: &NLockArgs) {
+        set_if_some!(self.include, args.output_include.clone());
+        set_if_some!(self.exclude, args.output_exclude.clone());
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NLockConfigMargins {
+    #[serde(default)]
+    pub top: f64,
+
+    #[serde(default)]
+    pub right: f64,
+
+    #[serde(default)]
+    pub bottom: f64,
+
+    #[serde(default)]
+    pub left: f64,
+}
+
+impl LoadArgOverrides for NLockConfigMargins {
+    fn load_arg_overrides(&mut self, args: &NLockArgs) {
+        set_if_some!(self.top, args.margin_top);
+        set_if_some!(self.right, args.margin_right);
+        set_if_some!(self.bottom, args.margin_bottom);
+        set_if_some!(self.left, args.margin_left);
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct NLockConfigImage {
@@ -397,6 +1011,30 @@ pub struct NLockConfigImage {
 
     #[serde(default = "default_image_scale")]
     pub scale: BackgroundImageScale,
+
+    #[serde(default = "default_image_anchor")]
+    pub anchor: ImageAnchor,
+
+    #[serde(
+        default = "default_image_placeholder_color",
+        rename = "placeholderColor"
+    )]
+    pub placeholder_color: Rgba,
+
+    #[serde(default = "default_image_vignette")]
+    pub vignette: f64,
+
+    #[serde(default = "default_image_vignette_radius", rename = "vignetteRadius")]
+    pub vignette_radius: f64,
+
+    #[serde(default = "default_image_gamma")]
+    pub gamma: f64,
+
+    #[serde(default = "default_image_temperature")]
+    pub temperature: f64,
+
+    #[serde(default)]
+    pub layers: Vec<NLockConfigImageLayer>,
 }
 
 impl Default for NLockConfigImage {
@@ -404,6 +1042,13 @@ impl Default for NLockConfigImage {
         Self {
             path: default_image_path(),
             scale: default_image_scale(),
+            anchor: default_image_anchor(),
+            placeholder_color: default_image_placeholder_color(),
+            vignette: default_image_vignette(),
+            vignette_radius: default_image_vignette_radius(),
+            gamma: default_image_gamma(),
+            temperature: default_image_temperature(),
+            layers: Vec::new(),
         }
     }
 }
@@ -412,6 +1057,12 @@ impl LoadArgOverrides for NLockConfigImage {
     fn load_arg_overrides(&mut self, args: &NLockArgs) {
         set_if_some_path!(self.path, &args.image_path);
         set_if_some!(self.scale, args.image_scale);
+        set_if_some!(self.anchor, args.image_anchor);
+        set_if_some!(self.placeholder_color, args.image_placeholder_color);
+        set_if_some!(self.vignette, args.image_vignette);
+        set_if_some!(self.vignette_radius, args.image_vignette_radius);
+        set_if_some!(self.gamma, args.image_gamma);
+        set_if_some!(self.temperature, args.image_temperature);
     }
 }
 
@@ -423,11 +1074,383 @@ fn default_image_scale() -> BackgroundImageScale {
     BackgroundImageScale::Fill
 }
 
+fn default_image_anchor() -> ImageAnchor {
+    ImageAnchor::Center
+}
+
+fn default_image_placeholder_color() -> Rgba {
+    Rgba::new(0.0, 0.0, 0.0, 0.0)
+}
+
+fn default_image_vignette() -> f64 {
+    0.0
+}
+
+fn default_image_vignette_radius() -> f64 {
+    0.5
+}
+
+fn default_image_gamma() -> f64 {
+    1.0
+}
+
+fn default_image_temperature() -> f64 {
+    6500.0
+}
+
+/// An image drawn over the background image, like a texture or vignette
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NLockConfigImageLayer {
+    pub path: PathBuf,
+
+    #[serde(default = "default_image_scale")]
+    pub scale: BackgroundImageScale,
+
+    #[serde(default = "default_image_anchor")]
+    pub anchor: ImageAnchor,
+
+    #[serde(default = "default_image_layer_opacity")]
+    pub opacity: f64,
+}
+
+fn default_image_layer_opacity() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NLockConfigAnimation {
+    #[serde(default = "default_success_delay_ms", rename = "successDelayMs")]
+    pub success_delay_ms: u64,
+
+    #[serde(default = "default_sync_unlock", rename = "syncUnlock")]
+    pub sync_unlock: bool,
+
+    #[serde(default = "default_highlight_ms", rename = "highlightMs")]
+    pub highlight_ms: u64,
+
+    #[serde(default = "default_shake_on_fail", rename = "shakeOnFail")]
+    pub shake_on_fail: bool,
+
+    #[serde(default = "default_fps")]
+    pub fps: u32,
+
+    #[serde(default = "default_fps_on_battery", rename = "fpsOnBattery")]
+    pub fps_on_battery: u32,
+}
+
+impl Default for NLockConfigAnimation {
+    fn default() -> Self {
+        Self {
+            success_delay_ms: default_success_delay_ms(),
+            sync_unlock: default_sync_unlock(),
+            highlight_ms: default_highlight_ms(),
+            shake_on_fail: default_shake_on_fail(),
+            fps: default_fps(),
+            fps_on_battery: default_fps_on_battery(),
+        }
+    }
+}
+
+impl LoadArgOverrides for NLockConfigAnimation {
+    fn load_arg_overrides(&mut self, args: &NLockArgs) {
+        set_if_some!(self.success_delay_ms, args.success_delay_ms);
+        set_if_some!(self.sync_unlock, args.sync_unlock);
+        set_if_some!(self.highlight_ms, args.highlight_ms);
+        set_if_some!(self.shake_on_fail, args.shake_on_fail);
+        set_if_some!(self.fps, args.fps);
+        set_if_some!(self.fps_on_battery, args.fps_on_battery);
+    }
+}
+
+fn default_success_delay_ms() -> u64 {
+    0
+}
+
+fn default_sync_unlock() -> bool {
+    true
+}
+
+fn default_highlight_ms() -> u64 {
+    0
+}
+
+fn default_shake_on_fail() -> bool {
+    false
+}
+
+fn default_fps() -> u32 {
+    30
+}
+
+fn default_fps_on_battery() -> u32 {
+    0
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NLockConfigXkb {
+    #[serde(default)]
+    pub layout: String,
+
+    #[serde(default)]
+    pub variant: String,
+
+    #[serde(default)]
+    pub options: String,
+
+    #[serde(default)]
+    pub model: String,
+}
+
+impl NLockConfigXkb {
+    /// Check whether a keymap is configured, instead of using the keymap
+    /// provided by the compositor.
+    pub fn is_set(&self) -> bool {
+        !(self.layout.is_empty()
+            && self.variant.is_empty()
+            && self.options.is_empty()
+            && self.model.is_empty())
+    }
+}
+
+impl LoadArgOverrides for NLockConfigXkb {
+    fn load_arg_overrides(&mut self, args: &NLockArgs) {
+        set_if_some_string!(self.layout, &args.xkb_layout);
+        set_if_some_string!(self.variant, &args.xkb_variant);
+        set_if_some_string!(self.options, &args.xkb_options);
+        set_if_some_string!(self.model, &args.xkb_model);
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NLockConfigKeys {
+    #[serde(default = "default_keys_submit")]
+    pub submit: SubmitModifiers,
+
+    #[serde(default = "default_keys_redraw")]
+    pub redraw: KeyBinding,
+}
+
+impl Default for NLockConfigKeys {
+    fn default() -> Self {
+        Self {
+            submit: default_keys_submit(),
+            redraw: default_keys_redraw(),
+        }
+    }
+}
+
+impl LoadArgOverrides for NLockConfigKeys {
+    fn load_arg_overrides(&mut self, args: &NLockArgs) {
+        set_if_some!(self.submit, args.submit_modifiers);
+        set_if_some!(self.redraw, args.redraw_key.clone());
+    }
+}
+
+fn default_keys_submit() -> SubmitModifiers {
+    SubmitModifiers::Any
+}
+
+fn default_keys_redraw() -> KeyBinding {
+    KeyBinding {
+        ctrl: true,
+        key: Some("l".to_string()),
+        ..Default::default()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NLockConfigOverlay {
+    #[serde(default = "default_overlay_opacity")]
+    pub opacity: f64,
+}
+
+impl Default for NLockConfigOverlay {
+    fn default() -> Self {
+        Self {
+            opacity: default_overlay_opacity(),
+        }
+    }
+}
+
+impl LoadArgOverrides for NLockConfigOverlay {
+    fn load_arg_overrides(&mut self, args: &NLockArgs) {
+        set_if_some!(self.opacity, args.overlay_opacity);
+    }
+}
+
+fn default_overlay_opacity() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NLockConfigSpinner {
+    #[serde(default = "default_spinner_enabled")]
+    pub enabled: bool,
+
+    #[serde(default = "default_spinner_size")]
+    pub size: f64,
+
+    #[serde(default = "default_spinner_thickness")]
+    pub thickness: f64,
+
+    #[serde(default = "default_spinner_x")]
+    pub x: f64,
+
+    #[serde(default = "default_spinner_y")]
+    pub y: f64,
+
+    #[serde(default = "default_spinner_period_ms", rename = "periodMs")]
+    pub period_ms: u64,
+}
+
+impl Default for NLockConfigSpinner {
+    fn default() -> Self {
+        Self {
+            enabled: default_spinner_enabled(),
+            size: default_spinner_size(),
+            thickness: default_spinner_thickness(),
+            x: default_spinner_x(),
+            y: default_spinner_y(),
+            period_ms: default_spinner_period_ms(),
+        }
+    }
+}
+
+impl LoadArgOverrides for NLockConfigSpinner {
+    fn load_arg_overrides(&mut self, args: &NLockArgs) {
+        set_if_some!(self.enabled, args.spinner);
+        set_if_some!(self.size, args.spinner_size);
+        set_if_some!(self.thickness, args.spinner_thickness);
+        set_if_some!(self.x, args.spinner_x);
+        set_if_some!(self.y, args.spinner_y);
+        set_if_some!(self.period_ms, args.spinner_period_ms);
+    }
+}
+
+fn default_spinner_enabled() -> bool {
+    false
+}
+
+fn default_spinner_size() -> f64 {
+    0.05
+}
+
+fn default_spinner_thickness() -> f64 {
+    4.0
+}
+
+fn default_spinner_x() -> f64 {
+    0.5
+}
+
+fn default_spinner_y() -> f64 {
+    0.6
+}
+
+fn default_spinner_period_ms() -> u64 {
+    1000
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NLockConfigPrompt {
+    #[serde(default = "default_prompt_text")]
+    pub text: String,
+
+    #[serde(default = "default_prompt_size")]
+    pub size: f64,
+
+    #[serde(default = "default_prompt_gap")]
+    pub gap: f64,
+
+    #[serde(default = "default_prompt_center_input", rename = "centerInput")]
+    pub center_input: bool,
+}
+
+impl Default for NLockConfigPrompt {
+    fn default() -> Self {
+        Self {
+            text: default_prompt_text(),
+            size: default_prompt_size(),
+            gap: default_prompt_gap(),
+            center_input: default_prompt_center_input(),
+        }
+    }
+}
+
+impl LoadArgOverrides for NLockConfigPrompt {
+    fn load_arg_overrides(&mut self, args: &NLockArgs) {
+        set_if_some_string!(self.text, &args.prompt);
+        set_if_some!(self.size, args.prompt_size);
+        set_if_some!(self.gap, args.prompt_gap);
+        set_if_some!(self.center_input, args.prompt_center_input);
+    }
+}
+
+fn default_prompt_text() -> String {
+    "".to_string()
+}
+
+fn default_prompt_size() -> f64 {
+    24.0f64
+}
+
+fn default_prompt_gap() -> f64 {
+    0.02
+}
+
+fn default_prompt_center_input() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NLockConfigText {
+    #[serde(default = "default_text_outline")]
+    pub outline: f64,
+}
+
+impl Default for NLockConfigText {
+    fn default() -> Self {
+        Self {
+            outline: default_text_outline(),
+        }
+    }
+}
+
+impl LoadArgOverrides for NLockConfigText {
+    fn load_arg_overrides(&mut self, args: &NLockArgs) {
+        set_if_some!(self.outline, args.text_outline);
+    }
+}
+
+fn default_text_outline() -> f64 {
+    0.0
+}
+
 impl NLockConfig {
     pub fn load(args: &NLockArgs) -> Result<Self> {
         let mut builder = Config::builder();
 
-        if let Some(config_file) = &args.config_file {
+        if args.config_file.as_deref() == Some("-") {
+            // Like any other config file given on the command line, this
+            // replaces discovering config files
+            let mut contents = String::new();
+            io::stdin().read_to_string(&mut contents)?;
+
+            if contents.trim().is_empty() {
+                bail!("No configuration read from stdin");
+            }
+
+            builder = builder.add_source(File::from_str(&contents, FileFormat::Toml));
+            debug!("Including config from stdin");
+        } else if let Some(config_file) = &args.config_file {
             let custom_config = PathBuf::from(config_file);
 
             if custom_config.is_file() {
@@ -464,10 +1487,76 @@ impl NLockConfig {
         }
 
         let config = builder.build()?;
+        if enabled!(Level::DEBUG) {
+            Self::log_sources("", &config.cache);
+        }
+
         let mut parsed_config = config.try_deserialize::<Self>()?;
 
         parsed_config.load_arg_overrides(args);
+        parsed_config.apply_low_memory();
 
         Ok(parsed_config)
     }
+
+    /// Log which source set each configured option. When several config
+    /// files are included, each option takes its value from the last file
+    /// setting it, which isn't obvious when layering system and user files.
+    fn log_sources(key: &str, value: &Value) {
+        if let ValueKind::Table(table) = &value.kind {
+            let mut entries: Vec<_> = table.iter().collect();
+            entries.sort_by_key(|&(name, _)| name);
+
+            for (name, value) in entries {
+                let key = if key.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{key}.{name}")
+                };
+                Self::log_sources(&key, value);
+            }
+        } else {
+            // Only sources read from a path have an origin
+            debug!(
+                "Config option {key} set by {}",
+                value.origin().unwrap_or("stdin")
+            );
+        }
+    }
+
+    /// Turn off whatever costs memory or constant redraws in low memory
+    /// mode, overriding the rest of the configuration. Surfaces limit their
+    /// own buffers.
+    fn apply_low_memory(&mut self) {
+        if !self.general.low_memory {
+            return;
+        }
+
+        if self.general.bg_type == BackgroundType::Image {
+            info!("Low memory mode: using the background color instead of an image");
+            self.general.bg_type = BackgroundType::Color;
+        }
+
+        if self.input.caret_blink_ms > 0 {
+            info!("Low memory mode: caret blinking disabled");
+            self.input.caret_blink_ms = 0;
+        }
+
+        if self.animation.highlight_ms > 0 {
+            info!("Low memory mode: key highlighting disabled");
+            self.animation.highlight_ms = 0;
+        }
+
+        if self.spinner.enabled {
+            info!("Low memory mode: spinner disabled");
+            self.spinner.enabled = false;
+        }
+
+        if self.animation.shake_on_fail {
+            info!("Low memory mode: shake on fail disabled");
+            self.animation.shake_on_fail = false;
+        }
+
+        info!("Low memory mode: limiting each surface to one buffer per layer");
+    }
 }
@@ -4,18 +4,56 @@
 use std::{
     os::fd::{AsFd, AsRawFd, BorrowedFd},
     sync::atomic::Ordering,
+    time::Duration,
 };
 
 use anyhow::{Result, anyhow};
-use mio::{Events, Interest, Poll, Token, unix::SourceFd};
+use mio::{Events, Interest, Poll, Registry, Token, unix::SourceFd};
 use nix::{
-    sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags},
+    sys::{
+        time::TimeSpec,
+        timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags},
+    },
+    time::clock_gettime,
     unistd::read,
 };
 use tracing::warn;
-use wayland_client::{EventQueue, QueueHandle, backend::ReadEventsGuard};
+use wayland_client::{
+    EventQueue, Proxy, QueueHandle,
+    backend::{ReadEventsGuard, WaylandError},
+};
+
+use crate::{
+    audit::{AuditOutcome, log_attempt},
+    auth::{AuthResponse, AuthState, attempts_exhausted},
+    state::NLockState,
+    util::{is_eintr, scrub_password},
+};
+
+/// Error from a single cycle of the event loop
+#[derive(Debug)]
+pub enum LoopError {
+    /// Only this cycle failed, the loop can carry on
+    Recoverable(anyhow::Error),
+    /// The Wayland connection is broken by a protocol error, the loop must
+    /// stop
+    Fatal(anyhow::Error),
+    /// The compositor went away, e.g. it crashed or is restarting, the loop
+    /// must stop
+    Disconnected(anyhow::Error),
+}
 
-use crate::{auth::AuthState, state::NLockState, util::is_eintr};
+// Minimum jump in suspended time treated as a resume from suspend
+const RESUME_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Total time spent suspended since boot, the difference between the boot
+/// time clock, which includes suspend, and the monotonic clock, which doesn't.
+fn suspended_time() -> Result<Duration> {
+    let boottime = Duration::from(clock_gettime(nix::time::ClockId::CLOCK_BOOTTIME)?);
+    let monotonic = Duration::from(clock_gettime(nix::time::ClockId::CLOCK_MONOTONIC)?);
+
+    Ok(boottime.saturating_sub(monotonic))
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(usize)]
@@ -23,6 +61,11 @@ pub enum EventType {
     Wayland = 0,
     KeyboardRepeat = 1,
     AuthStateChanged = 2,
+    SuccessDelay = 3,
+    CaretBlink = 4,
+    WakeExpired = 5,
+    HighlightExpired = 6,
+    Animation = 7,
 }
 
 impl EventType {
@@ -31,42 +74,68 @@ impl EventType {
             0 => Ok(Self::Wayland),
             1 => Ok(Self::KeyboardRepeat),
             2 => Ok(Self::AuthStateChanged),
+            3 => Ok(Self::SuccessDelay),
+            4 => Ok(Self::CaretBlink),
+            5 => Ok(Self::WakeExpired),
+            6 => Ok(Self::HighlightExpired),
+            7 => Ok(Self::Animation),
 
             _ => Err(anyhow!("Invalid EventType value")),
         }
     }
 }
 
-impl NLockState {
-    pub fn set_timer(&mut self, id: usize, expiration: Expiration) -> Result<()> {
-        let repeat_timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::empty())?;
-        repeat_timer.set(expiration, TimerSetTimeFlags::empty())?;
+/// A source of timers, which fire as events on the poll.
+///
+/// This abstracts timer creation and draining away from the event loop, so
+/// timer firings can be injected without real timers.
+pub trait TimerSource {
+    /// Arm a timer with the specified ID
+    fn set(&mut self, id: usize, expiration: Expiration) -> Result<()>;
 
-        let mut repeat_timer_src = SourceFd(&repeat_timer.as_fd().as_raw_fd());
+    /// Disarm and remove all timers with the specified ID
+    fn unset(&mut self, id: usize) -> Result<()>;
 
-        let poll = self
-            .poll
-            .as_mut()
-            .ok_or(anyhow!("Poll has not been created yet"))?;
+    /// Read the number of expirations of the timer with the specified ID since
+    /// it was last read, 0 if there is no such timer.
+    fn read_expirations(&mut self, id: usize) -> Result<u64>;
+}
 
-        poll.registry()
-            .register(&mut repeat_timer_src, Token(id), Interest::READABLE)?;
+/// Timer source backed by `timerfd`s registered on a `mio` poll
+pub struct TimerFdSource {
+    registry: Registry,
+    timers: Vec<(TimerFd, usize)>,
+}
 
-        self.timers.push((repeat_timer, id));
+impl TimerFdSource {
+    pub fn new(registry: Registry) -> Self {
+        Self {
+            registry,
+            timers: Vec::new(),
+        }
+    }
+}
+
+impl TimerSource for TimerFdSource {
+    fn set(&mut self, id: usize, expiration: Expiration) -> Result<()> {
+        let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::empty())?;
+        timer.set(expiration, TimerSetTimeFlags::empty())?;
+
+        let mut timer_src = SourceFd(&timer.as_fd().as_raw_fd());
+
+        self.registry
+            .register(&mut timer_src, Token(id), Interest::READABLE)?;
+
+        self.timers.push((timer, id));
 
         Ok(())
     }
 
-    pub fn unset_timer(&mut self, id: usize) -> Result<()> {
-        let poll = self
-            .poll
-            .as_mut()
-            .ok_or(anyhow!("Poll has not been created yet"))?;
-
+    fn unset(&mut self, id: usize) -> Result<()> {
         let mut i = 0;
         while i < self.timers.len() {
             if self.timers[i].1 == id {
-                poll.registry()
+                self.registry
                     .deregister(&mut SourceFd(&self.timers[i].0.as_fd().as_raw_fd()))?;
                 self.timers.swap_remove(i);
             } else {
@@ -77,6 +146,43 @@ impl NLockState {
         Ok(())
     }
 
+    fn read_expirations(&mut self, id: usize) -> Result<u64> {
+        let timer = match self.timers.iter().find(|timer| timer.1 == id) {
+            Some(timer) => timer,
+            None => return Ok(0),
+        };
+
+        let mut buf = [0u8; std::mem::size_of::<u64>()];
+        let res = read(&timer.0, &mut buf)?;
+
+        if res == std::mem::size_of::<u64>() {
+            Ok(u64::from_ne_bytes(buf))
+        } else {
+            Ok(0)
+        }
+    }
+}
+
+impl NLockState {
+    /// Replace the timer source, e.g. to drive timer events without timerfds
+    pub fn set_timer_source(&mut self, timers: Box<dyn TimerSource>) {
+        self.timers = Some(timers);
+    }
+
+    pub fn set_timer(&mut self, id: usize, expiration: Expiration) -> Result<()> {
+        self.timers
+            .as_mut()
+            .ok_or(anyhow!("Timers have not been created yet"))?
+            .set(id, expiration)
+    }
+
+    pub fn unset_timer(&mut self, id: usize) -> Result<()> {
+        self.timers
+            .as_mut()
+            .ok_or(anyhow!("Timers have not been created yet"))?
+            .unset(id)
+    }
+
     fn setup_poll(&mut self) -> Result<()> {
         let poll = Poll::new()?;
 
@@ -87,6 +193,19 @@ impl NLockState {
             Interest::READABLE,
         )?;
 
+        // Keep any timer source that was injected beforehand
+        if self.timers.is_none() {
+            self.timers = Some(Box::new(TimerFdSource::new(poll.registry().try_clone()?)));
+        }
+
+        let blink = self.config.input.caret_blink_ms;
+        if self.config.input.caret && blink > 0 {
+            self.set_timer(
+                EventType::CaretBlink as usize,
+                Expiration::Interval(TimeSpec::from_duration(Duration::from_millis(blink))),
+            )?;
+        }
+
         self.poll = Some(poll);
         Ok(())
     }
@@ -119,6 +238,171 @@ impl NLockState {
         Ok(())
     }
 
+    /// Handle a single event from the poll, other than Wayland socket events
+    pub fn handle_event(&mut self, event_type: EventType) -> Result<()> {
+        match event_type {
+            EventType::Wayland => {}
+            EventType::KeyboardRepeat => {
+                let intervals = self
+                    .timers
+                    .as_mut()
+                    .ok_or(anyhow!("Timers have not been created yet"))?
+                    .read_expirations(EventType::KeyboardRepeat as usize)?;
+
+                for _ in 0..intervals {
+                    self.handle_repeat_event();
+                }
+            }
+            EventType::SuccessDelay => {
+                self.unset_timer(EventType::SuccessDelay as usize)?;
+                self.request_unlock();
+            }
+            EventType::CaretBlink => {
+                let intervals = self
+                    .timers
+                    .as_mut()
+                    .ok_or(anyhow!("Timers have not been created yet"))?
+                    .read_expirations(EventType::CaretBlink as usize)?;
+
+                // Blinking pauses, with the caret shown, once input is over
+                let accepting_input = matches!(
+                    self.auth_state.load(Ordering::Relaxed),
+                    AuthState::Idle
+                        | AuthState::Verifying
+                        | AuthState::Fail
+                        | AuthState::TimedOut
+                        | AuthState::TooLong
+                        | AuthState::TooShort
+                        | AuthState::AccountUnusable
+                );
+
+                if !accepting_input {
+                    self.set_caret_visible(true);
+                } else if intervals % 2 == 1 {
+                    self.set_caret_visible(!self.caret_visible);
+                }
+            }
+            EventType::WakeExpired => self.handle_wake_expired()?,
+            EventType::HighlightExpired => {
+                self.unset_timer(EventType::HighlightExpired as usize)?;
+                self.set_key_highlight(None);
+            }
+            EventType::Animation => {
+                self.timers
+                    .as_mut()
+                    .ok_or(anyhow!("Timers have not been created yet"))?
+                    .read_expirations(EventType::Animation as usize)?;
+
+                self.update_spinner();
+                self.update_shake();
+            }
+            EventType::AuthStateChanged => match self.auth_comm.response.read() {
+                Ok(AuthResponse::Success) => {
+                    if self.config.general.audit_log {
+                        log_attempt(AuditOutcome::Success);
+                    }
+                    self.stop_spinner();
+                    scrub_password(&mut self.submitted_password);
+
+                    // auth was successful, show success, then exit after delay
+                    self.auth_state.store(AuthState::Success, Ordering::Relaxed);
+                    self.state_changed.store(true, Ordering::Relaxed);
+
+                    // Without a delay the success state is never seen, so
+                    // there's no point waiting for outputs to show it
+                    let delay = self.config.animation.success_delay_ms;
+                    if delay == 0 {
+                        self.running.store(false, Ordering::Relaxed);
+                    } else if let Err(e) = self.set_timer(
+                        EventType::SuccessDelay as usize,
+                        Expiration::OneShot(TimeSpec::from_duration(Duration::from_millis(delay))),
+                    ) {
+                        // Don't leave the user stuck on a correct password
+                        warn!("Failed to set success delay timer: {e}");
+                        self.running.store(false, Ordering::Relaxed);
+                    }
+                }
+                Ok(AuthResponse::TimedOut) => {
+                    if self.config.general.audit_log {
+                        log_attempt(AuditOutcome::TimedOut);
+                    }
+                    self.stop_spinner();
+                    scrub_password(&mut self.submitted_password);
+
+                    // not a failed attempt, the user can just try again
+                    self.auth_state
+                        .store(AuthState::TimedOut, Ordering::Relaxed);
+                    self.state_changed.store(true, Ordering::Relaxed);
+                }
+                Ok(AuthResponse::AccountUnusable) => {
+                    if self.config.general.audit_log {
+                        log_attempt(AuditOutcome::AccountUnusable);
+                    }
+                    self.stop_spinner();
+                    scrub_password(&mut self.submitted_password);
+
+                    // not a failed attempt either, the password was right
+                    self.auth_state
+                        .store(AuthState::AccountUnusable, Ordering::Relaxed);
+                    self.state_changed.store(true, Ordering::Relaxed);
+                }
+                Ok(AuthResponse::Fail) => {
+                    if self.config.general.audit_log {
+                        log_attempt(AuditOutcome::Failure);
+                    }
+                    self.stop_spinner();
+
+                    // auth failed, set fail state, or lock out if no attempts remain
+                    self.failed_attempts = self.failed_attempts.saturating_add(1);
+
+                    let auth_state = if attempts_exhausted(
+                        self.failed_attempts,
+                        self.config.general.max_attempts,
+                    ) {
+                        warn!("Maximum attempts exceeded, locking out");
+                        AuthState::LockedOut
+                    } else {
+                        AuthState::Fail
+                    };
+
+                    if matches!(auth_state, AuthState::Fail) {
+                        self.reset_password_after_fail();
+                        self.start_shake();
+                    } else {
+                        self.clear_password();
+                        scrub_password(&mut self.submitted_password);
+                    }
+
+                    self.auth_state.store(auth_state, Ordering::Relaxed);
+                    self.state_changed.store(true, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    warn!("Failed to receive auth response: {e}");
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the system was suspended since the last check
+    fn detect_resume(&mut self) -> bool {
+        let suspended = match suspended_time() {
+            Ok(t) => t,
+            Err(e) => {
+                warn!("Failed to read clocks: {e}");
+                return false;
+            }
+        };
+
+        let resumed = self
+            .suspended_time
+            .is_some_and(|last| suspended.saturating_sub(last) >= RESUME_THRESHOLD);
+        self.suspended_time = Some(suspended);
+
+        resumed
+    }
+
     fn process_events(
         &mut self,
         events: &Events,
@@ -131,39 +415,7 @@ impl NLockState {
                 EventType::Wayland => {
                     wayland_sock_ready = true;
                 }
-                EventType::KeyboardRepeat => {
-                    if let Some(idx) = self
-                        .timers
-                        .iter()
-                        .position(|timer| timer.1 == EventType::KeyboardRepeat as usize)
-                    {
-                        let timer = &self.timers[idx];
-                        let mut buf = [0u8; std::mem::size_of::<u64>()];
-                        let res = read(&timer.0, &mut buf)?;
-                        if res == std::mem::size_of::<u64>() {
-                            let intervals = u64::from_ne_bytes(buf);
-                            for _ in 0..intervals {
-                                self.handle_repeat_event();
-                            }
-                        }
-                    }
-                }
-                EventType::AuthStateChanged => match self.auth_comm.response.read() {
-                    Ok(true) => {
-                        // auth was successful, set flags for exit
-                        self.auth_state.store(AuthState::Success, Ordering::Relaxed);
-                        self.running.store(false, Ordering::Relaxed);
-                        self.state_changed.store(true, Ordering::Relaxed);
-                    }
-                    Ok(false) => {
-                        // auth failed, set fail state
-                        self.auth_state.store(AuthState::Fail, Ordering::Relaxed);
-                        self.state_changed.store(true, Ordering::Relaxed);
-                    }
-                    Err(e) => {
-                        warn!("Failed to receive auth response: {e}");
-                    }
-                },
+                event_type => self.handle_event(event_type)?,
             }
         }
 
@@ -188,8 +440,8 @@ impl NLockState {
                 self.surfaces[i].render(
                     &self.config,
                     auth_state,
-                    self.password.chars().count(),
-                    self.background_image.as_ref(),
+                    &self.password,
+                    &self.background_images,
                     shm,
                     qh,
                 );
@@ -197,9 +449,37 @@ impl NLockState {
 
             self.state_changed.store(false, Ordering::Relaxed);
         }
+
+        self.check_unlock_pending();
     }
 
-    pub fn event_loop_cycle(&mut self, event_queue: &mut EventQueue<NLockState>) -> Result<()> {
+    /// Run one cycle of the event loop. Errors are fatal once the Wayland
+    /// connection can no longer be used, since every later cycle would fail
+    /// in the same way.
+    pub fn event_loop_cycle(
+        &mut self,
+        event_queue: &mut EventQueue<NLockState>,
+    ) -> std::result::Result<(), LoopError> {
+        self.run_cycle(event_queue).map_err(|e| {
+            // The backend keeps protocol, and I/O, errors which broke the
+            // connection, anything else only affected this cycle
+            match self
+                .display
+                .backend()
+                .upgrade()
+                .and_then(|b| b.last_error())
+            {
+                // The socket failing means the compositor is gone
+                Some(conn_err @ WaylandError::Io(_)) => {
+                    LoopError::Disconnected(e.context(conn_err))
+                }
+                Some(conn_err) => LoopError::Fatal(e.context(conn_err)),
+                None => LoopError::Recoverable(e),
+            }
+        })
+    }
+
+    fn run_cycle(&mut self, event_queue: &mut EventQueue<NLockState>) -> Result<()> {
         if self.poll.is_none() {
             self.setup_poll()?;
         }
@@ -215,9 +495,171 @@ impl NLockState {
         let wayland_sock_fd = read_guard.connection_fd();
 
         self.poll_events(&mut events, wayland_sock_fd)?;
+
+        // Recover before handling events, so stale timers don't fire in bursts
+        if self.detect_resume() {
+            self.handle_resume();
+        }
+
         self.process_events(&events, read_guard, event_queue)?;
         self.re_render(&event_queue.handle());
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::net::UnixStream, sync::Arc};
+
+    use wayland_client::{
+        Connection, Dispatch,
+        protocol::{wl_output, wl_shm},
+    };
+    use wayland_protocols::ext::session_lock::v1::client::ext_session_lock_surface_v1::{
+        self, ExtSessionLockSurfaceV1,
+    };
+    use xkbcommon::xkb;
+
+    use super::*;
+    use crate::{auth::AuthChannel, config::NLockConfig, surface::NLockSurface};
+
+    /// Timer source that fires the expirations it was given, once each
+    struct FakeTimers {
+        expirations: Vec<(usize, u64)>,
+    }
+
+    impl TimerSource for FakeTimers {
+        fn set(&mut self, _: usize, _: Expiration) -> Result<()> {
+            Ok(())
+        }
+
+        fn unset(&mut self, id: usize) -> Result<()> {
+            self.expirations.retain(|&(timer, _)| timer != id);
+            Ok(())
+        }
+
+        fn read_expirations(&mut self, id: usize) -> Result<u64> {
+            let fired = self.expirations.iter().position(|&(timer, _)| timer == id);
+            Ok(fired.map_or(0, |i| self.expirations.swap_remove(i).1))
+        }
+    }
+
+    fn test_state() -> NLockState {
+        // Nothing is ever sent, so the other end needn't be a compositor
+        let (socket, _) = UnixStream::pair().unwrap();
+        let connection = Connection::from_socket(socket).unwrap();
+        let auth_comm = Arc::new(AuthChannel::new().unwrap());
+
+        NLockState::new(NLockConfig::default(), connection.display(), auth_comm).unwrap()
+    }
+
+    #[test]
+    fn keyboard_repeat_types_once_per_expiration() {
+        let mut state = test_state();
+        state.key_repeat.keysym = Some(xkb::Keysym::a);
+        state.key_repeat.codepoint = Some('a' as u32);
+        state.set_timer_source(Box::new(FakeTimers {
+            expirations: vec![(EventType::KeyboardRepeat as usize, 3)],
+        }));
+
+        state.handle_event(EventType::KeyboardRepeat).unwrap();
+        assert_eq!(state.password.as_str(), "aaa");
+
+        // Expirations are only counted once
+        state.handle_event(EventType::KeyboardRepeat).unwrap();
+        assert_eq!(state.password.as_str(), "aaa");
+    }
+
+    #[test]
+    fn keyboard_repeat_without_a_held_key_types_nothing() {
+        let mut state = test_state();
+        state.set_timer_source(Box::new(FakeTimers {
+            expirations: vec![(EventType::KeyboardRepeat as usize, 2)],
+        }));
+
+        state.handle_event(EventType::KeyboardRepeat).unwrap();
+        assert!(state.password.is_empty());
+    }
+
+    #[test]
+    fn configure_mid_typing_keeps_the_password() {
+        let mut state = test_state();
+        let (socket, _) = UnixStream::pair().unwrap();
+        let connection = Connection::from_socket(socket).unwrap();
+        let queue = connection.new_event_queue::<NLockState>();
+        let backend = connection.backend().downgrade();
+
+        state.shm = Some(wl_shm::WlShm::inert(backend.clone()));
+        state.surfaces.push(NLockSurface::new(
+            wl_output::WlOutput::inert(backend.clone()),
+            0,
+        ));
+        state.password.push_str("hunter");
+
+        // A first configure, then a resize, as the output mode changes
+        let lock_surface = ExtSessionLockSurfaceV1::inert(backend);
+        for (serial, width, height) in [(1, 1920, 1080), (2, 1280, 720)] {
+            <NLockState as Dispatch<ExtSessionLockSurfaceV1, usize>>::event(
+                &mut state,
+                &lock_surface,
+                ext_session_lock_surface_v1::Event::Configure {
+                    serial,
+                    width,
+                    height,
+                },
+                &0,
+                &connection,
+                &queue.handle(),
+            );
+        }
+
+        assert_eq!(state.password.as_str(), "hunter");
+    }
+
+    /// Deliver a failed auth response to `state`
+    fn fail_attempt(state: &mut NLockState) {
+        state.set_timer_source(Box::new(FakeTimers {
+            expirations: Vec::new(),
+        }));
+        state.auth_comm.response.write(AuthResponse::Fail).unwrap();
+        state.handle_event(EventType::AuthStateChanged).unwrap();
+
+        assert!(matches!(
+            state.auth_state.load(Ordering::Relaxed),
+            AuthState::Fail
+        ));
+    }
+
+    #[test]
+    fn clear_on_fail_clears_the_password() {
+        let mut state = test_state();
+        state.config.input.clear_on_fail = true;
+        state.password.push_str("typed while verifying");
+
+        fail_attempt(&mut state);
+        assert!(state.password.is_empty());
+    }
+
+    #[test]
+    fn keep_on_fail_restores_the_submitted_password() {
+        let mut state = test_state();
+        state.config.input.clear_on_fail = false;
+        state.submitted_password.push_str("hunter2");
+
+        fail_attempt(&mut state);
+        assert_eq!(state.password.as_str(), "hunter2");
+        assert!(state.submitted_password.is_empty());
+    }
+
+    #[test]
+    fn keep_on_fail_keeps_what_was_typed_since() {
+        let mut state = test_state();
+        state.config.input.clear_on_fail = false;
+        state.submitted_password.push_str("hunter2");
+        state.password.push_str("hunter3");
+
+        fail_attempt(&mut state);
+        assert_eq!(state.password.as_str(), "hunter3");
+    }
+}
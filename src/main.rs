@@ -2,9 +2,11 @@
 // Copyright (C) 2026, Nathan Gill
 
 pub mod args;
+pub mod audit;
 pub mod auth;
 pub mod buffer;
 pub mod cairo_ext;
+pub mod check;
 pub mod comm;
 pub mod config;
 pub mod event;
@@ -14,7 +16,11 @@ pub mod state;
 pub mod surface;
 pub mod util;
 
-use std::sync::{Arc, atomic::Ordering};
+use std::{
+    process::ExitCode,
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
 
 use anyhow::{Result, bail};
 
@@ -22,22 +28,49 @@ use anyhow::{Result, bail};
 use nix::sys::prctl;
 
 use tracing::{debug, error, warn};
+use tracing_subscriber::fmt::format::FmtSpan;
 use wayland_client::Connection;
 
 use crate::{
     args::run_cli,
-    auth::{AuthChannel, AuthConfig, run_auth_loop},
+    auth::{AuthChannel, AuthConfig, spawn_auth_thread, test_auth},
+    check::NLockCheck,
     config::NLockConfig,
+    event::LoopError,
     state::NLockState,
 };
 
+// Upper bound on roundtrips spent waiting for lock surfaces to render
+const MAX_SURFACE_WAIT_ROUNDTRIPS: usize = 10;
+
+/// Connect to the Wayland compositor, retrying with backoff in case nlock
+/// started before the compositor's socket was ready.
+fn connect(config: &NLockConfig) -> Result<Connection> {
+    let attempts = config.general.connect_attempts.max(1);
+    let mut delay = Duration::from_millis(config.general.connect_retry_ms);
+
+    let mut attempt = 1;
+    loop {
+        match Connection::connect_to_env() {
+            Ok(conn) => return Ok(conn),
+            Err(e) if attempt < attempts => {
+                debug!("Failed to connect to compositor (attempt {attempt}/{attempts}): {e}");
+                std::thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+                attempt += 1;
+            }
+            Err(e) => bail!("Failed to connect to compositor after {attempts} attempts: {e}"),
+        }
+    }
+}
+
 fn start(config: NLockConfig) -> Result<()> {
     // Prevent ptrace from attaching to nlock
     // Only do this in release config
     #[cfg(not(debug_assertions))]
     prctl::set_dumpable(false)?;
 
-    let conn = Connection::connect_to_env()?;
+    let conn = connect(&config)?;
     let display = conn.display();
 
     let auth_comm = Arc::new(AuthChannel::new()?);
@@ -63,7 +96,7 @@ fn start(config: NLockConfig) -> Result<()> {
         bail!("Missing WlShm");
     }
 
-    if state.r_seat.is_none() {
+    if state.seats.is_empty() {
         bail!("Missing WlSeat");
     }
 
@@ -71,22 +104,57 @@ fn start(config: NLockConfig) -> Result<()> {
         bail!("Missing ExtSessionLockManagerV1");
     }
 
+    // Format events are sent on bind, so these are known after the roundtrip
+    state.select_background_format();
+
     // spawn authenticator loop in another thread
-    std::thread::spawn({
-        let auth_comm = auth_comm.clone();
-        move || {
-            if let Err(e) = run_auth_loop(auth_config, auth_comm) {
-                warn!("Error in auth thread: {e}");
-            }
-            debug!("Auth thread exited");
-        }
-    });
+    state.auth_thread = Some(spawn_auth_thread(auth_config, auth_comm.clone(), 0));
+
+    if state.config.general.wait_for_surfaces {
+        // Receive output information before locking, so lock surfaces are
+        // created along with the lock itself
+        event_queue.roundtrip(&mut state)?;
+    }
 
     state.lock(&qh);
 
+    if state.config.general.wait_for_surfaces {
+        // Render backgrounds before doing anything else, so content is ready
+        // by the time the compositor shows the lock surfaces
+        let mut roundtrips = 0;
+        while !state.surfaces_ready() && roundtrips < MAX_SURFACE_WAIT_ROUNDTRIPS {
+            event_queue.roundtrip(&mut state)?;
+            roundtrips += 1;
+        }
+
+        debug!("Waited {roundtrips} roundtrips for lock surfaces");
+    }
+
     while state.running.load(Ordering::Relaxed) {
-        if let Err(e) = state.event_loop_cycle(&mut event_queue) {
-            warn!("Error while running event loop: {e}");
+        match state.event_loop_cycle(&mut event_queue) {
+            Ok(()) => {}
+            Err(LoopError::Recoverable(e)) => {
+                warn!("Error while running event loop: {e}");
+            }
+            Err(LoopError::Fatal(e)) => {
+                // Nothing more can be sent, the compositor keeps the session
+                // locked once nlock exits without unlocking
+                if let Err(e) = auth_comm.stop_ev.write(1) {
+                    warn!("Failed to stop auth loop: {e}");
+                }
+
+                return Err(e.context("Wayland connection failed, leaving the session locked"));
+            }
+            Err(LoopError::Disconnected(e)) => {
+                // Never unlock here. A restarted compositor starts without
+                // nlock's lock, so it's up to whatever started nlock, e.g.
+                // an idle daemon, to lock again, seeing the failure exit.
+                if let Err(e) = auth_comm.stop_ev.write(1) {
+                    warn!("Failed to stop auth loop: {e}");
+                }
+
+                return Err(e.context("Compositor disconnected, exiting without unlocking"));
+            }
         }
     }
 
@@ -97,32 +165,83 @@ fn start(config: NLockConfig) -> Result<()> {
         warn!("Failed to stop auth loop: {e}");
     }
 
+    if state.lock_lost {
+        bail!("Session lock was ended by the compositor");
+    }
+
+    Ok(())
+}
+
+fn check(config: NLockConfig, json: bool) -> Result<()> {
+    let conn = Connection::connect_to_env()?;
+    let display = conn.display();
+
+    let auth_comm = Arc::new(AuthChannel::new()?);
+    let mut state = NLockState::new(config, display, auth_comm)?;
+
+    let mut event_queue = conn.new_event_queue();
+    let qh = event_queue.handle();
+
+    state.get_registry(&qh);
+    event_queue.roundtrip(&mut state)?;
+
+    let check = NLockCheck::new(&state);
+    if json {
+        check.print_json();
+    } else {
+        check.print();
+    }
+
+    if !check.ok() {
+        bail!("Missing required globals");
+    }
+
     Ok(())
 }
 
-fn main() {
+fn main() -> ExitCode {
     let args = run_cli();
 
+    // Closing spans log their timings, render spans are only enabled at trace
     tracing_subscriber::fmt()
         .with_timer(tracing_subscriber::fmt::time::uptime())
         .with_max_level(args.log_level)
+        .with_span_events(FmtSpan::CLOSE)
         .init();
 
     let now = chrono::Local::now();
     debug!("nlock started at {}", now.to_rfc3339());
 
-    match NLockConfig::load(&args) {
-        Ok(cfg) => {
-            if let Err(e) = start(cfg) {
+    let code = match NLockConfig::load(&args) {
+        Ok(cfg) if args.check => match check(cfg, args.json) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
                 error!("{:#?}", e);
+                ExitCode::FAILURE
             }
-        }
+        },
+        Ok(cfg) if args.test_auth => match test_auth(&cfg) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("{:#?}", e);
+                ExitCode::FAILURE
+            }
+        },
+        Ok(cfg) => match start(cfg) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("{:#?}", e);
+                ExitCode::FAILURE
+            }
+        },
         Err(e) => {
             error!("Error loading configuration: {:#?}", e);
-            return;
+            return ExitCode::FAILURE;
         }
-    }
+    };
 
     let now = chrono::Local::now();
     debug!("nlock exited at {}", now.to_rfc3339());
+
+    code
 }
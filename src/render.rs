@@ -1,26 +1,188 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright (C) 2026, Nathan Gill
 
-use anyhow::{Result, anyhow, bail};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::OnceLock,
+};
+
+use anyhow::{Result, bail};
 use cairo::SurfacePattern;
-use pangocairo::functions::{create_layout, show_layout};
+use pango::prelude::FontExt;
+use pangocairo::functions::{create_context, create_layout, layout_path, show_layout};
 use tracing::{debug, warn};
+use wayland_client::protocol::wl_output;
+use zeroize::Zeroizing;
 
 use crate::{
     auth::AuthState,
-    cairo_ext::CairoExt,
+    cairo_ext::{CairoExt, ImageSurfaceExt, NEUTRAL_TEMPERATURE, SubpixelOrderExt},
     config::NLockConfig,
-    util::{BackgroundImageScale, BackgroundType, InputVisibility, PANGO_SCALE, pango_pixels},
+    util::{
+        BackgroundImageScale, BackgroundType, FontAntialias, ImageAnchor, InputEcho, InputOverflow,
+        InputVisibility, PANGO_SCALE, Rgba, StrokeAlign, TextDirection, pango_pixels,
+    },
 };
 
 pub const DEFAULT_DPI: f64 = 96.0;
 pub const DEFAULT_SCALE: f64 = 1.0;
 
+const AUTH_UNAVAILABLE_MESSAGE: &str = "Authentication unavailable";
+const AUTH_TIMED_OUT_MESSAGE: &str = "Authentication timed out";
+const PASSWORD_TOO_LONG_MESSAGE: &str = "Password too long";
+const ACCOUNT_UNUSABLE_MESSAGE: &str = "Account expired or password change required";
+
+// Ranges the background image color curve is clamped to
+const MIN_IMAGE_GAMMA: f64 = 0.1;
+const MAX_IMAGE_GAMMA: f64 = 10.0;
+const MIN_IMAGE_TEMPERATURE: f64 = 1000.0;
+const MAX_IMAGE_TEMPERATURE: f64 = 10000.0;
+
+// Debug overlay text size in points, and padding around it in logical pixels
+const DEBUG_OVERLAY_FONT_SIZE: f64 = 9.0;
+const DEBUG_OVERLAY_PADDING: f64 = 6.0;
+
+// Furthest the input box moves while shaking, in logical pixels
+const SHAKE_AMPLITUDE: f64 = 12.0;
+
+// Keys the hash behind block echo, random for each run, so a pattern seen on
+// screen can't be checked against guessed passwords later
+static BLOCK_HASH_STATE: OnceLock<RandomState> = OnceLock::new();
+
+// Families tried if the system default sans-serif font can't be resolved
+const FALLBACK_FONT_FAMILIES: &[&str] = &["DejaVu Sans", "Noto Sans", "Liberation Sans"];
+
+// Aliases fontconfig maps to an installed font, never to a family of the
+// same name
+const GENERIC_FONT_FAMILIES: &[&str] = &[
+    "sans",
+    "sans-serif",
+    "serif",
+    "monospace",
+    "mono",
+    "system-ui",
+    "cursive",
+    "fantasy",
+    "emoji",
+    "math",
+];
+
+/// Get the family of the font Pango actually loads for a font family
+fn resolve_font_family(context: &pango::Context, family: &str) -> Option<String> {
+    let mut fd = pango::FontDescription::new();
+    fd.set_family(family);
+
+    let font = context.load_font(&fd)?;
+    font.describe().family().map(|f| f.to_string())
+}
+
+/// Check whether a font family is installed, rather than substituted by
+/// fontconfig. Generic families always resolve to something suitable.
+fn font_family_installed(context: &pango::Context, family: &str) -> bool {
+    GENERIC_FONT_FAMILIES
+        .iter()
+        .any(|generic| generic.eq_ignore_ascii_case(family))
+        || resolve_font_family(context, family)
+            .is_some_and(|resolved| resolved.eq_ignore_ascii_case(family))
+}
+
+/// Create a Pango context for resolving fonts, outside of any surface
+fn font_resolution_context() -> Option<pango::Context> {
+    match cairo::ImageSurface::create(cairo::Format::ARgb32, 1, 1)
+        .and_then(|surface| cairo::Context::new(&surface))
+    {
+        Ok(context) => Some(create_context(&context)),
+        Err(e) => {
+            warn!("Failed to create context for font resolution: {e}");
+            None
+        }
+    }
+}
+
+/// Resolve the system default sans-serif font family through fontconfig, for
+/// use when no font family is configured.
+pub fn resolve_default_font_family() -> String {
+    let resolved = font_resolution_context().and_then(|p_ctx| {
+        resolve_font_family(&p_ctx, "sans-serif").or_else(|| {
+            FALLBACK_FONT_FAMILIES
+                .iter()
+                .find(|&&fallback| font_family_installed(&p_ctx, fallback))
+                .map(|family| family.to_string())
+        })
+    });
+
+    let family = resolved.unwrap_or_else(|| FALLBACK_FONT_FAMILIES[0].to_string());
+    debug!("Using default font family {family}");
+
+    family
+}
+
+/// Resolve a configured font family, which fontconfig would otherwise
+/// silently substitute if it isn't installed, to the first installed of
+/// `fallback` instead.
+pub fn resolve_configured_font_family(family: &str, fallback: &[String]) -> String {
+    let Some(p_ctx) = font_resolution_context() else {
+        return family.to_string();
+    };
+
+    if font_family_installed(&p_ctx, family) {
+        debug!("Using font family {family}");
+        return family.to_string();
+    }
+
+    if let Some(fallback) = fallback
+        .iter()
+        .find(|fallback| font_family_installed(&p_ctx, fallback))
+    {
+        warn!("Font family {family} is not installed, using fallback {fallback}");
+        return fallback.clone();
+    }
+
+    warn!(
+        "Font family {family} is not installed, using {} in its place",
+        resolve_font_family(&p_ctx, family)
+            .as_deref()
+            .unwrap_or("a substitute")
+    );
+
+    family.to_string()
+}
+
+/// The text shown in the input box for a password under an echo mode. Blocks
+/// are drawn as shapes, not text.
+fn echo_text(echo: InputEcho, mask_char: &str, password: &str) -> Zeroizing<String> {
+    Zeroizing::new(match echo {
+        InputEcho::None | InputEcho::Blocks => String::new(),
+        InputEcho::Mask => mask_char.repeat(password.chars().count()),
+        InputEcho::Clear => password.to_string(),
+    })
+}
+
+/// Images drawn in image background mode, loaded once, and shared by every
+/// surface
+#[derive(Default)]
+pub struct NLockBackgroundImages {
+    pub image: Option<cairo::ImageSurface>,
+    // Drawn over the image, in order
+    pub layers: Vec<NLockImageLayer>,
+}
+
+/// A loaded image layer, with its settings from `image.layers`
+pub struct NLockImageLayer {
+    pub image: cairo::ImageSurface,
+    pub scale: BackgroundImageScale,
+    pub anchor: ImageAnchor,
+    pub opacity: f64,
+}
+
 pub struct NLockRenderBackgroundArgs<'a> {
     pub buf_height: f64,
     pub buf_width: f64,
     pub context: &'a cairo::Context,
-    pub image: Option<&'a cairo::ImageSurface>,
+    pub images: &'a NLockBackgroundImages,
+    // Only paint the background color, regardless of background type
+    pub plain: bool,
 }
 
 impl<'a> NLockRenderBackgroundArgs<'a> {
@@ -42,7 +204,9 @@ pub struct NLockRenderOverlayArgs<'a> {
     pub buf_height: f64,
     pub buf_width: f64,
     pub context: &'a cairo::Context,
-    pub pwd_len: usize,
+    // Output details to show for troubleshooting, only with general.debugOverlay
+    pub debug_info: Option<&'a str>,
+    pub password: &'a str,
 }
 
 impl<'a> NLockRenderOverlayArgs<'a> {
@@ -59,11 +223,31 @@ impl<'a> NLockRenderOverlayArgs<'a> {
     }
 }
 
+/// Key action acknowledged by tinting the input border
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyHighlight {
+    Key,
+    Backspace,
+    // Backspace with nothing left to delete
+    Empty,
+}
+
 #[derive(Default)]
 pub struct NLockRenderer {
     dpi: Option<f64>,
     scale: Option<f64>,
+    // Shrinks fixed-size elements on small surfaces
+    size_factor: Option<f64>,
     subpixel_order: Option<cairo::SubpixelOrder>,
+    subpixel_none: bool,
+    transform: Option<wl_output::Transform>,
+    caret_hidden: bool,
+    input_woken: bool,
+    key_highlight: Option<KeyHighlight>,
+    // Rotation of the spinner in radians, if it's shown
+    spinner_angle: Option<f64>,
+    // Horizontal offset of the input box while shaking, from -1 to 1
+    shake_offset: f64,
 }
 
 impl NLockRenderer {
@@ -85,12 +269,51 @@ impl NLockRenderer {
         self.subpixel_order = Some(order);
     }
 
+    pub fn set_subpixel_none(&mut self, none: bool) {
+        self.subpixel_none = none;
+    }
+
+    pub fn set_transform(&mut self, transform: wl_output::Transform) {
+        self.transform = Some(transform);
+    }
+
+    pub fn set_caret_visible(&mut self, visible: bool) {
+        self.caret_hidden = !visible;
+    }
+
+    pub fn set_input_woken(&mut self, woken: bool) {
+        self.input_woken = woken;
+    }
+
+    pub fn set_key_highlight(&mut self, highlight: Option<KeyHighlight>) {
+        self.key_highlight = highlight;
+    }
+
+    pub fn set_spinner_angle(&mut self, angle: Option<f64>) {
+        self.spinner_angle = angle;
+    }
+
+    pub fn set_shake_offset(&mut self, offset: f64) {
+        self.shake_offset = offset;
+    }
+
     pub fn set_scale(&mut self, scale: f64) {
         if scale > 0.0 {
             self.scale = Some(scale);
         }
     }
 
+    pub fn set_size_factor(&mut self, factor: f64) {
+        if factor > 0.0 {
+            self.size_factor = Some(factor);
+        }
+    }
+
+    /// Scale applied to sizes given in absolute units, like borders and fonts
+    fn element_scale(&self) -> f64 {
+        self.scale.unwrap_or(DEFAULT_SCALE) * self.size_factor.unwrap_or(1.0)
+    }
+
     fn clear_background(&self, context: &cairo::Context) -> Result<()> {
         context.save()?;
         context.set_operator(cairo::Operator::Source);
@@ -109,23 +332,83 @@ impl NLockRenderer {
         Ok(())
     }
 
+    /// Create font options for the current output, only using subpixel
+    /// anti-aliasing if the subpixel order is known in buffer coordinates.
+    fn create_font_options(&self, config: &NLockConfig) -> Result<cairo::FontOptions> {
+        let mut options = cairo::FontOptions::new()?;
+
+        let subpixel_order = self
+            .subpixel_order
+            .filter(|order| !matches!(order, cairo::SubpixelOrder::Default))
+            .and_then(|order| {
+                order.transformed(self.transform.unwrap_or(wl_output::Transform::Normal))
+            });
+
+        let antialias = match config.font.antialias {
+            // Subpixel anti-aliasing only causes color fringing on outputs
+            // without subpixels
+            FontAntialias::Auto | FontAntialias::Subpixel if self.subpixel_none => {
+                cairo::Antialias::Gray
+            }
+            FontAntialias::Auto if subpixel_order.is_some() => cairo::Antialias::Subpixel,
+            FontAntialias::Auto => cairo::Antialias::Gray,
+            FontAntialias::Subpixel => cairo::Antialias::Subpixel,
+            FontAntialias::Grayscale => cairo::Antialias::Gray,
+            FontAntialias::None => cairo::Antialias::None,
+        };
+
+        options.set_antialias(antialias);
+        if let Some(order) = subpixel_order {
+            options.set_subpixel_order(order);
+        }
+
+        Ok(options)
+    }
+
     fn create_font(
         &self,
         config: &NLockConfig,
         context: &cairo::Context,
+    ) -> Result<(pango::Layout, pango::FontMetrics)> {
+        self.create_font_sized(config, context, config.font.size)
+    }
+
+    /// Create a layout in the configured font, at `size` points
+    fn create_font_sized(
+        &self,
+        config: &NLockConfig,
+        context: &cairo::Context,
+        size: f64,
     ) -> Result<(pango::Layout, pango::FontMetrics)> {
         let dpi = self.dpi.unwrap_or(DEFAULT_DPI);
-        let scale = self.scale.unwrap_or(DEFAULT_SCALE);
+        let scale = self.element_scale();
 
         let mut fd = pango::FontDescription::new();
         fd.set_family(&config.font.family);
         fd.set_style(config.font.slant.into());
         fd.set_weight(config.font.weight.into());
-        fd.set_absolute_size(((config.font.size / 72.0) * dpi * scale) * PANGO_SCALE as f64);
+        fd.set_absolute_size(((size / 72.0) * dpi * scale) * PANGO_SCALE as f64);
+
+        // Pango picks up font options from the Cairo context on layout creation
+        context.set_font_options(&self.create_font_options(config)?);
 
         let layout = create_layout(context);
         layout.set_font_description(Some(&fd));
 
+        // Auto leaves Pango to pick the direction from the text itself
+        if config.font.direction != TextDirection::Auto {
+            layout.set_auto_dir(false);
+            layout.context().set_base_dir(config.font.direction.into());
+            layout.context_changed();
+        }
+
+        // OpenType features use CSS syntax, e.g. "tnum, smcp"
+        if !config.font.features.is_empty() {
+            let attrs = pango::AttrList::new();
+            attrs.insert(pango::AttrFontFeatures::new(&config.font.features));
+            layout.set_attributes(Some(&attrs));
+        }
+
         let p_ctx = layout.context();
         let metrics = p_ctx.metrics(Some(&fd), None);
 
@@ -154,12 +437,28 @@ impl NLockRenderer {
         auth_state: AuthState,
     ) {
         match auth_state {
-            AuthState::Idle => context.ext_set_source_rgba(config.colors.frame_border_idle),
+            AuthState::Idle | AuthState::Verifying => {
+                context.ext_set_source_rgba(config.colors.frame_border_idle)
+            }
             AuthState::Success => context.ext_set_source_rgba(config.colors.frame_border_success),
-            AuthState::Fail => context.ext_set_source_rgba(config.colors.frame_border_fail),
+            AuthState::Fail
+            | AuthState::LockedOut
+            | AuthState::Unavailable
+            | AuthState::TimedOut
+            | AuthState::TooLong
+            | AuthState::TooShort
+            | AuthState::AccountUnusable => {
+                context.ext_set_source_rgba(config.colors.frame_border_fail)
+            }
         }
     }
 
+    /// Draw `image` over the whole buffer, scaled by `mode`.
+    ///
+    /// `buf_width` and `buf_height` are already after the output transform:
+    /// lock surfaces are configured in the output's rotated orientation, and
+    /// buffers are never given a transform of their own. A portrait monitor
+    /// gets a portrait buffer, so fitting and filling need no special case.
     fn draw_background_image(
         &self,
         context: &cairo::Context,
@@ -167,6 +466,7 @@ impl NLockRenderer {
         buf_width: f64,
         buf_height: f64,
         mode: BackgroundImageScale,
+        anchor: ImageAnchor,
     ) -> Result<()> {
         let width = image.width() as f64;
         let height = image.height() as f64;
@@ -197,7 +497,7 @@ impl NLockRenderer {
                     context.scale(scale, scale);
                     context.set_source_surface(
                         image,
-                        buf_width / 2.0 / scale - width / 2.0,
+                        (buf_width / scale - width) * anchor.factor(),
                         0.0,
                     )?;
                 } else {
@@ -206,7 +506,7 @@ impl NLockRenderer {
                     context.set_source_surface(
                         image,
                         0.0,
-                        buf_height / 2.0 / scale - height / 2.0,
+                        (buf_height / scale - height) * anchor.factor(),
                     )?;
                 }
             }
@@ -220,14 +520,14 @@ impl NLockRenderer {
                     context.set_source_surface(
                         image,
                         0.0,
-                        buf_height / 2.0 / scale - height / 2.0,
+                        (buf_height / scale - height) * anchor.factor(),
                     )?;
                 } else {
                     let scale = buf_height / height;
                     context.scale(scale, scale);
                     context.set_source_surface(
                         image,
-                        buf_width / 2.0 / scale - width / 2.0,
+                        (buf_width / scale - width) * anchor.factor(),
                         0.0,
                     )?;
                 }
@@ -237,26 +537,495 @@ impl NLockRenderer {
         Ok(())
     }
 
-    fn draw_overlay(
+    /// Draw the placeholder color, background image, layers and vignette,
+    /// everything that makes up an image background.
+    fn draw_image_background(
         &self,
         config: &NLockConfig,
         context: &cairo::Context,
-        auth_state: AuthState,
-        pwd_len: usize,
+        images: &NLockBackgroundImages,
         buf_width: f64,
         buf_height: f64,
     ) -> Result<()> {
+        // Paint the placeholder first, it shows through wherever the
+        // image doesn't cover, or in place of an image not loaded yet
+        context.ext_set_source_rgba(config.image.placeholder_color);
+        context.set_operator(cairo::Operator::Source);
+        context.paint()?;
+        context.set_operator(cairo::Operator::Over);
+
+        let Some(image) = &images.image else {
+            warn!("Surface in image mode, but no image set, using placeholder");
+            return Ok(());
+        };
+
+        // Each image sets its own scale, which mustn't carry over
+        context.save()?;
+        self.draw_background_image(
+            context,
+            image,
+            buf_width,
+            buf_height,
+            config.image.scale,
+            config.image.anchor,
+        )?;
+        context.paint()?;
+        context.restore()?;
+
+        // Layers are drawn over the image, and each other, in order
+        for layer in &images.layers {
+            context.save()?;
+            self.draw_background_image(
+                context,
+                &layer.image,
+                buf_width,
+                buf_height,
+                layer.scale,
+                layer.anchor,
+            )?;
+            context.paint_with_alpha(layer.opacity.clamp(0.0, 1.0))?;
+            context.restore()?;
+        }
+
+        let intensity = config.image.vignette.clamp(0.0, 1.0);
+        if intensity > 0.0 {
+            Self::draw_vignette(config, context, intensity, buf_width, buf_height)?;
+        }
+
+        Ok(())
+    }
+
+    /// Darken the edges of the buffer towards the vignette color, with a
+    /// radial gradient from the center. Clear inside `image.vignetteRadius`,
+    /// reaching `intensity` at the corners.
+    fn draw_vignette(
+        config: &NLockConfig,
+        context: &cairo::Context,
+        intensity: f64,
+        buf_width: f64,
+        buf_height: f64,
+    ) -> Result<()> {
+        let (cx, cy) = (buf_width / 2.0, buf_height / 2.0);
+        let corner = cx.hypot(cy);
+        let inner = corner * config.image.vignette_radius.clamp(0.0, 1.0);
+
+        let color = config.colors.vignette;
+        let gradient = cairo::RadialGradient::new(cx, cy, inner, cx, cy, corner);
+        gradient.add_color_stop_rgba(0.0, color.r, color.g, color.b, 0.0);
+        gradient.add_color_stop_rgba(1.0, color.r, color.g, color.b, color.a * intensity);
+
+        context.save()?;
+        context.set_source(gradient)?;
+        context.paint()?;
+        context.restore()?;
+
+        Ok(())
+    }
+
+    /// Round a position or size to whole buffer pixels, if pixel snapping is
+    /// enabled. Everything drawn is already in buffer pixels, after scaling.
+    fn snap(config: &NLockConfig, value: f64) -> f64 {
+        if config.general.pixel_snap {
+            value.round()
+        } else {
+            value
+        }
+    }
+
+    /// Convert floating point extents, as returned by Cairo, into an integer
+    /// rectangle, covering any partially touched pixels.
+    fn extents_to_rect((x1, y1, x2, y2): (f64, f64, f64, f64)) -> cairo::RectangleInt {
+        let x = x1.floor() as i32 - 1;
+        let y = y1.floor() as i32 - 1;
+
+        cairo::RectangleInt::new(x, y, x2.ceil() as i32 + 1 - x, y2.ceil() as i32 + 1 - y)
+    }
+
+    /// Check whether text is laid out right to left, putting its end on the
+    /// left. Text without a direction of its own, like a mask, is left to right
+    /// unless configured otherwise.
+    fn is_rtl(config: &NLockConfig, text: &str) -> bool {
+        match config.font.direction {
+            TextDirection::Auto => pango::find_base_dir(text) == pango::Direction::Rtl,
+            TextDirection::Ltr => false,
+            TextDirection::Rtl => true,
+        }
+    }
+
+    /// Draw a line of text, centered on the buffer
+    ///
+    /// Returns the area of the buffer drawn to.
+    fn draw_message(
+        &self,
+        config: &NLockConfig,
+        context: &cairo::Context,
+        message: &str,
+        buf_width: f64,
+        buf_height: f64,
+    ) -> Result<cairo::RectangleInt> {
+        let (layout, _) = self.create_font(config, context)?;
+        layout.set_text(message);
+        let text_ext = layout.pixel_extents().0;
+        let scale = self.element_scale();
+
+        let rect_x = (buf_width - text_ext.width() as f64) / 2.0;
+        let rect_y = (buf_height - text_ext.height() as f64) / 2.0;
+        let text_x = rect_x - text_ext.x() as f64;
+        let text_y = rect_y - text_ext.y() as f64;
+
+        context.move_to(text_x, text_y);
+        Self::show_text(config, context, &layout, config.colors.text, scale)?;
+
+        let outline = Self::text_outline_width(config, scale);
+        Ok(Self::extents_to_rect((
+            rect_x - outline,
+            rect_y - outline,
+            rect_x + text_ext.width() as f64 + outline,
+            rect_y + text_ext.height() as f64 + outline,
+        )))
+    }
+
+    /// Draw the prompt label with its ink extents' top left at `x`, `y`
+    ///
+    /// Returns the area of the buffer drawn to.
+    fn draw_prompt(
+        config: &NLockConfig,
+        context: &cairo::Context,
+        layout: &pango::Layout,
+        ext: &pango::Rectangle,
+        x: f64,
+        y: f64,
+        scale: f64,
+    ) -> Result<cairo::RectangleInt> {
+        context.move_to(x - ext.x() as f64, y - ext.y() as f64);
+        Self::show_text(config, context, layout, config.colors.prompt, scale)?;
+
+        let outline = Self::text_outline_width(config, scale);
+        Ok(Self::extents_to_rect((
+            x - outline,
+            y - outline,
+            x + ext.width() as f64 + outline,
+            y + ext.height() as f64 + outline,
+        )))
+    }
+
+    /// Width of the outline around text, in buffer pixels, 0 if disabled
+    fn text_outline_width(config: &NLockConfig, scale: f64) -> f64 {
+        config.text.outline.max(0.0) * scale
+    }
+
+    /// Draw a layout at the current point in `color`. If `text.outline` is
+    /// set, the text is drawn as a path, stroked with the outline color
+    /// underneath the fill, so it stays readable over busy backgrounds.
+    fn show_text(
+        config: &NLockConfig,
+        context: &cairo::Context,
+        layout: &pango::Layout,
+        color: Rgba,
+        scale: f64,
+    ) -> Result<()> {
+        let outline = Self::text_outline_width(config, scale);
+
+        context.save()?;
+        if outline > 0.0 {
+            layout_path(context, layout);
+
+            // The fill covers the inner half of the stroke
+            context.ext_set_source_rgba(config.colors.text_outline);
+            context.set_line_width(outline * 2.0);
+            context.set_line_join(cairo::LineJoin::Round);
+            context.stroke_preserve()?;
+
+            context.ext_set_source_rgba(color);
+            context.fill()?;
+        } else {
+            context.ext_set_source_rgba(color);
+            show_layout(context, layout);
+        }
+        context.restore()?;
+
+        Ok(())
+    }
+
+    /// Color of block `index` in block echo, a hue picked by hashing the
+    /// password, so any change to it changes every block.
+    fn block_color(password: &str, index: u32) -> (f64, f64, f64) {
+        let mut hasher = BLOCK_HASH_STATE
+            .get_or_init(RandomState::new)
+            .build_hasher();
+        hasher.write(password.as_bytes());
+        hasher.write_u32(index);
+
+        // Fixed saturation and value keep every block equally visible
+        let hue = (hasher.finish() % 360) as f64 / 60.0;
+        let (s, v) = (0.6, 0.9);
+        let c = v * s;
+        let x = c * (1.0 - (hue % 2.0 - 1.0).abs());
+        let m = v - c;
+
+        let (r, g, b) = match hue as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+
+        (r + m, g + m, b + m)
+    }
+
+    /// Draw a glow fading inwards from the edges of a rounded rectangle, the
+    /// current clip. Drawn as strokes of the outline, each narrower than the
+    /// last, so their overlap is most opaque at the edge. Only the inner half
+    /// of each stroke is inside the clip.
+    fn draw_inner_glow(
+        config: &NLockConfig,
+        context: &cairo::Context,
+        (x, y, w, h): (f64, f64, f64, f64),
+        radius: f64,
+        width: f64,
+    ) -> Result<()> {
+        const STEPS: u32 = 8;
+
+        let color = config.colors.input_glow;
+        context.save()?;
+        context.set_source_rgba(color.r, color.g, color.b, color.a / STEPS as f64);
+
+        for step in 0..STEPS {
+            Self::draw_rounded_rect(context, x, y, w, h, radius);
+            context.set_line_width(2.0 * width * (STEPS - step) as f64 / STEPS as f64);
+            context.stroke()?;
+        }
+
+        context.restore()?;
+
+        Ok(())
+    }
+
+    /// Draw a fixed number of colored blocks across the text area, in place
+    /// of the password. Colors come from a hash of the password, so typing
+    /// changes them, but the length isn't shown. Nothing is drawn while the
+    /// password is empty.
+    fn draw_blocks(
+        config: &NLockConfig,
+        context: &cairo::Context,
+        password: &str,
+        x: f64,
+        y: f64,
+        w: f64,
+        h: f64,
+    ) -> Result<()> {
+        let count = config.input.block_count.max(1);
+        if password.is_empty() {
+            return Ok(());
+        }
+
+        let gap = Self::snap(config, h * 0.25);
+        let block_w = Self::snap(
+            config,
+            ((w - gap * (count - 1) as f64) / count as f64).max(0.0),
+        );
+
+        for i in 0..count {
+            let (r, g, b) = Self::block_color(password, i);
+            context.set_source_rgb(r, g, b);
+            context.rectangle(x + i as f64 * (block_w + gap), y, block_w, h);
+            context.fill()?;
+        }
+
+        Ok(())
+    }
+
+    /// Draw an open arc, rotated by `angle`, shown while the password is
+    /// being verified
+    ///
+    /// Returns the area of the buffer drawn to.
+    fn draw_spinner(
+        &self,
+        config: &NLockConfig,
+        context: &cairo::Context,
+        angle: f64,
+        buf_width: f64,
+        buf_height: f64,
+    ) -> Result<cairo::RectangleInt> {
+        let radius = (config.spinner.size * buf_height) / 2.0;
+        let x = config.spinner.x * buf_width;
+        let y = config.spinner.y * buf_height;
+
+        context.save()?;
+        context.ext_set_source_rgba(config.colors.spinner);
+        context.set_line_width(config.spinner.thickness * self.element_scale());
+        context.set_line_cap(cairo::LineCap::Round);
+        context.new_path();
+        // The gap in the arc is what makes the rotation visible
+        context.arc(x, y, radius, angle, angle + 270f64.to_radians());
+        let rect = Self::extents_to_rect(context.stroke_extents()?);
+        context.stroke()?;
+        context.restore()?;
+
+        Ok(rect)
+    }
+
+    /// Replace a masked password which doesn't fit in `inner_w` with as many
+    /// mask characters as fit, followed by an ellipsis, and optionally the
+    /// length. Returns the new ink extents, or `None` if it's left to clip.
+    fn shorten_mask(
+        config: &NLockConfig,
+        layout: &pango::Layout,
+        pwd_len: usize,
+        inner_w: f64,
+    ) -> Option<pango::Rectangle> {
+        let suffix = match config.input.overflow {
+            InputOverflow::Clip => return None,
+            InputOverflow::Ellipsis => "\u{2026}".to_string(),
+            InputOverflow::Count => format!("\u{2026}({pwd_len})"),
+        };
+
+        // Logical extents include the advance, so repeated glyphs add up
+        layout.set_text(&config.input.mask_char);
+        let mask_w = layout.pixel_extents().1.width() as f64;
+        layout.set_text(&suffix);
+        let suffix_w = layout.pixel_extents().1.width() as f64;
+
+        let capacity = if mask_w > 0.0 {
+            ((inner_w - suffix_w) / mask_w).floor().max(0.0) as usize
+        } else {
+            0
+        };
+
+        let text = config.input.mask_char.repeat(capacity.min(pwd_len)) + &suffix;
+        layout.set_text(&text);
+
+        Some(layout.pixel_extents().0)
+    }
+
+    fn draw_overlay(
+        &self,
+        config: &NLockConfig,
+        args: &NLockRenderOverlayArgs,
+        buf_width: f64,
+        buf_height: f64,
+    ) -> Result<Vec<cairo::RectangleInt>> {
+        let context = args.context;
+
         // Reset the context for fresh rendering
         self.reset_cairo_context(context)?;
 
+        // Everything is placed within the area inside the margins, as if that
+        // was the whole buffer
+        let margins = &config.general.margins;
         let scale = self.scale.unwrap_or(DEFAULT_SCALE);
+        let (left, top) = (
+            Self::snap(config, margins.left.max(0.0) * scale),
+            Self::snap(config, margins.top.max(0.0) * scale),
+        );
+        let right = Self::snap(config, margins.right.max(0.0) * scale);
+        let bottom = Self::snap(config, margins.bottom.max(0.0) * scale);
+
+        let area_width = (buf_width - left - right).max(1.0);
+        let area_height = (buf_height - top - bottom).max(1.0);
+
+        context.translate(left, top);
+        let drawn = self.draw_overlay_content(
+            config,
+            context,
+            args.auth_state,
+            args.password,
+            area_width,
+            area_height,
+        )?;
+
+        let (dx, dy) = (left.floor() as i32, top.floor() as i32);
+        let mut drawn: Vec<_> = drawn
+            .into_iter()
+            .map(|rect| {
+                // Rounding down the offset may leave the rect a pixel short
+                cairo::RectangleInt::new(
+                    rect.x() + dx,
+                    rect.y() + dy,
+                    rect.width() + 1,
+                    rect.height() + 1,
+                )
+            })
+            .collect();
+
+        // Ignores the margins, so it stays in the same place whatever they are
+        if let Some(info) = args.debug_info {
+            context.identity_matrix();
+            drawn.push(self.draw_debug_overlay(context, info)?);
+        }
+
+        Ok(drawn)
+    }
+
+    /// Draw output details in the top left corner of the buffer, for
+    /// troubleshooting scaling. Fixed colors and a monospace font keep it
+    /// readable whatever the configuration is.
+    ///
+    /// Returns the area of the buffer drawn to.
+    fn draw_debug_overlay(
+        &self,
+        context: &cairo::Context,
+        info: &str,
+    ) -> Result<cairo::RectangleInt> {
+        let dpi = self.dpi.unwrap_or(DEFAULT_DPI);
+        let scale = self.scale.unwrap_or(DEFAULT_SCALE);
+
+        let mut fd = pango::FontDescription::from_string("monospace");
+        fd.set_absolute_size(((DEBUG_OVERLAY_FONT_SIZE / 72.0) * dpi * scale) * PANGO_SCALE as f64);
+
+        let layout = create_layout(context);
+        layout.set_font_description(Some(&fd));
+        layout.set_text(info);
+        let ext = layout.pixel_extents().1;
+
+        let padding = (DEBUG_OVERLAY_PADDING * scale).round();
+        let width = ext.width() as f64 + padding * 2.0;
+        let height = ext.height() as f64 + padding * 2.0;
+
+        context.set_source_rgba(0.0, 0.0, 0.0, 0.75);
+        context.rectangle(0.0, 0.0, width, height);
+        context.fill()?;
+
+        context.set_source_rgba(1.0, 1.0, 1.0, 1.0);
+        context.move_to(padding - ext.x() as f64, padding - ext.y() as f64);
+        show_layout(context, &layout);
+
+        Ok(Self::extents_to_rect((0.0, 0.0, width, height)))
+    }
+
+    fn draw_overlay_content(
+        &self,
+        config: &NLockConfig,
+        context: &cairo::Context,
+        auth_state: AuthState,
+        password: &str,
+        buf_width: f64,
+        buf_height: f64,
+    ) -> Result<Vec<cairo::RectangleInt>> {
+        // Areas of the buffer drawn to, used for damage tracking
+        let mut drawn = Vec::new();
+
+        let scale = self.element_scale();
 
         // Draw border colour
         context.save()?;
+        context.set_antialias(config.frame.antialias.into());
         self.set_frame_border_color(config, context, auth_state);
         context.set_line_width(config.frame.border * scale);
 
-        let frame_offset = (config.frame.border * scale) / 2.0;
+        // Gap left between the frame and the surface edges
+        let inset = config.frame.inset.max(0.0);
+
+        // Strokes are centered on the path, so an inset of half the border
+        // keeps all of it inside the surface, and an outset of half keeps it
+        // all outside the inset frame edge
+        let frame_offset = match config.frame.stroke_align {
+            StrokeAlign::Inside => (config.frame.border * scale) / 2.0,
+            StrokeAlign::Center => 0.0,
+            StrokeAlign::Outside => -(config.frame.border * scale) / 2.0,
+        } + inset * scale;
         let frame_w = buf_width - (frame_offset * 2.0);
         let frame_h = buf_height - (frame_offset * 2.0);
 
@@ -268,14 +1037,61 @@ impl NLockRenderer {
             frame_h,
             config.frame.radius * scale,
         );
+
+        // The frame hugs the buffer edges, so damage the strips along each edge
+        // instead of the (whole buffer) bounding box.
+        if config.frame.border > 0.0 {
+            let strip =
+                ((config.frame.border + config.frame.radius + inset) * scale).ceil() as i32 + 1;
+            let (w, h) = (buf_width.ceil() as i32, buf_height.ceil() as i32);
+
+            drawn.push(cairo::RectangleInt::new(0, 0, w, strip));
+            drawn.push(cairo::RectangleInt::new(0, h - strip, w, strip));
+            drawn.push(cairo::RectangleInt::new(0, 0, strip, h));
+            drawn.push(cairo::RectangleInt::new(w - strip, 0, strip, h));
+        }
+
         context.stroke()?;
         context.restore()?;
 
-        // Skip drawing input box if the password is empty and config flag set
+        if matches!(auth_state, AuthState::Verifying)
+            && let Some(angle) = self.spinner_angle
+        {
+            drawn.push(self.draw_spinner(config, context, angle, buf_width, buf_height)?);
+        }
+
+        // No more input is accepted, show the lockout message in place of the
+        // input box
+        let message = match auth_state {
+            AuthState::LockedOut => Some(config.general.lockout_message.as_str()),
+            AuthState::Unavailable => Some(AUTH_UNAVAILABLE_MESSAGE),
+            // Shown until the user starts typing again
+            AuthState::TimedOut if password.is_empty() => Some(AUTH_TIMED_OUT_MESSAGE),
+            AuthState::TooLong if password.is_empty() => Some(PASSWORD_TOO_LONG_MESSAGE),
+            AuthState::AccountUnusable if password.is_empty() => Some(ACCOUNT_UNUSABLE_MESSAGE),
+            AuthState::TooShort
+                if password.is_empty() && !config.input.too_short_message.is_empty() =>
+            {
+                Some(config.input.too_short_message.as_str())
+            }
+            _ => None,
+        };
+
+        if let Some(message) = message {
+            drawn.push(self.draw_message(config, context, message, buf_width, buf_height)?);
+            return Ok(drawn);
+        }
+
+        let pwd_len = password.chars().count();
+
+        // Skip drawing input box if the password is empty and config flag set,
+        // unless recent pointer activity woke it
         if config.input.visible == InputVisibility::Never
-            || (pwd_len == 0 && config.input.visible == InputVisibility::Content)
+            || (pwd_len == 0
+                && config.input.visible == InputVisibility::Content
+                && !self.input_woken)
         {
-            return Ok(());
+            return Ok(drawn);
         }
 
         let (layout, metrics) = self.create_font(config, context)?;
@@ -287,27 +1103,76 @@ impl NLockRenderer {
         let padding_y = config.input.padding_y * buf_height;
 
         // Calculate text extents here, so input box width can be determined
-        let text = config.input.mask_char.repeat(pwd_len);
+        let text = echo_text(config.input.echo, &config.input.mask_char, password);
         layout.set_text(&text);
-        let text_ext = layout.pixel_extents().0; // use ink extents for drawing
+        let mut text_ext = layout.pixel_extents().0; // use ink extents for drawing
 
         let mut inner_w = buf_width * config.input.width;
 
-        if config.input.fit_to_content {
+        // Without echo there is no content to fit, use the configured width,
+        // as do blocks, which never change width
+        if config.input.fit_to_content
+            && !matches!(config.input.echo, InputEcho::None | InputEcho::Blocks)
+        {
             // Cap computed width to specified width
             inner_w = (text_ext.width() as f64).min(inner_w);
         }
 
-        let inner_h = f_ascent + f_descent;
-        let inner_x = (buf_width - inner_w) / 2.0;
-        let inner_y = (buf_height - inner_h) / 2.0;
+        if config.input.echo == InputEcho::Mask && text_ext.width() as f64 > inner_w {
+            text_ext = Self::shorten_mask(config, &layout, pwd_len, inner_w).unwrap_or(text_ext);
+        }
 
+        // A configured height can only make the box taller, so the glyph ink
+        // always fits. Text is centered vertically either way.
+        let font_h = f_ascent + f_descent;
+        let inner_h = (config.input.height * buf_height).max(font_h);
         let outer_h = inner_h + (padding_y * 2.0) + (config.input.border * scale);
         let outer_w = inner_w + (padding_x * 2.0) + (config.input.border * scale);
-        let outer_x = (buf_width - outer_w) / 2.0;
-        let outer_y = (buf_height - outer_h) / 2.0;
+
+        // Measured before placing the box, which may move down to center
+        // the two together
+        let prompt = if config.prompt.text.is_empty() {
+            None
+        } else {
+            let (prompt_layout, _) = self.create_font_sized(config, context, config.prompt.size)?;
+            prompt_layout.set_text(&config.prompt.text);
+            let prompt_ext = prompt_layout.pixel_extents().0;
+            Some((prompt_layout, prompt_ext))
+        };
+        let prompt_gap = config.prompt.gap * buf_height;
+        let shift = match &prompt {
+            Some((_, ext)) if !config.prompt.center_input => {
+                (ext.height() as f64 + prompt_gap) / 2.0
+            }
+            _ => 0.0,
+        };
+
+        let snap = |v: f64| Self::snap(config, v);
+        let (inner_w, inner_h) = (snap(inner_w), snap(inner_h));
+        let (outer_w, outer_h) = (snap(outer_w), snap(outer_h));
+
+        // Shaking moves the box, and everything in it, but not the prompt
+        let shake = self.shake_offset * SHAKE_AMPLITUDE * scale;
+
+        let inner_x = snap((buf_width - inner_w) / 2.0 + shake);
+        let inner_y = snap((buf_height - inner_h) / 2.0 + shift);
+        let outer_x = snap((buf_width - outer_w) / 2.0 + shake);
+        let outer_y = snap((buf_height - outer_h) / 2.0 + shift);
+
+        if let Some((prompt_layout, prompt_ext)) = &prompt {
+            drawn.push(Self::draw_prompt(
+                config,
+                context,
+                prompt_layout,
+                prompt_ext,
+                snap((buf_width - prompt_ext.width() as f64) / 2.0),
+                snap(outer_y - prompt_gap - prompt_ext.height() as f64),
+                scale,
+            )?);
+        }
 
         context.save()?;
+        context.set_antialias(config.input.antialias.into());
 
         // Draw the outer rectangle, including padding
         // Outer rectangle should have rounded corners
@@ -321,26 +1186,79 @@ impl NLockRenderer {
         );
         context.ext_set_source_rgba(config.colors.input_bg);
         context.fill_preserve()?;
-        context.ext_set_source_rgba(config.colors.input_border);
+        context.ext_set_source_rgba(match self.key_highlight {
+            Some(KeyHighlight::Key) => config.colors.key_highlight,
+            Some(KeyHighlight::Backspace) => config.colors.backspace_highlight,
+            Some(KeyHighlight::Empty) => config.colors.frame_border_fail,
+            None => config.colors.input_border,
+        });
         context.set_line_width(config.input.border * scale);
+        drawn.push(Self::extents_to_rect(if config.input.border > 0.0 {
+            context.stroke_extents()?
+        } else {
+            context.fill_extents()?
+        }));
         context.stroke_preserve()?;
         context.clip();
 
+        if config.input.inner_glow > 0.0 {
+            Self::draw_inner_glow(
+                config,
+                context,
+                (outer_x, outer_y, outer_w, outer_h),
+                config.input.radius * outer_h,
+                config.input.inner_glow * scale,
+            )?;
+        }
+
         // Clip text to the inner rectangle
         context.rectangle(inner_x, inner_y, inner_w, inner_h);
         context.clip();
 
-        let text_x = inner_x + (inner_w - (text_ext.width() as f64)) / 2.0 - (text_ext.x() as f64);
-        let text_y = inner_y + (inner_h - text_ext.height() as f64) / 2.0 - (text_ext.y() as f64);
+        let text_x =
+            snap(inner_x + (inner_w - (text_ext.width() as f64)) / 2.0 - (text_ext.x() as f64));
+        let text_y =
+            snap(inner_y + (inner_h - text_ext.height() as f64) / 2.0 - (text_ext.y() as f64));
 
         // Actually draw the text
-        context.ext_set_source_rgba(config.colors.text);
         context.move_to(text_x, text_y);
-        show_layout(context, &layout);
+        Self::show_text(config, context, &layout, config.colors.text, scale)?;
+
+        if config.input.echo == InputEcho::Blocks {
+            // Blocks are a font ascent tall, centered like the text
+            let block_h = f_ascent.min(inner_h);
+            Self::draw_blocks(
+                config,
+                context,
+                password,
+                inner_x,
+                inner_y + (inner_h - block_h) / 2.0,
+                inner_w,
+                block_h,
+            )?;
+        }
+
+        // Caret follows the end of the text, or sits in the middle when empty.
+        // Blocks fill the box, leaving nowhere for it.
+        if config.input.caret && !self.caret_hidden && config.input.echo != InputEcho::Blocks {
+            let caret_x = snap(if Self::is_rtl(config, &text) {
+                inner_x + (inner_w - text_ext.width() as f64) / 2.0 - scale
+            } else {
+                inner_x + (inner_w + text_ext.width() as f64) / 2.0 + scale
+            });
+
+            // The caret spans the font height, not a taller box
+            let caret_y = snap(inner_y + (inner_h - font_h) / 2.0);
+
+            context.set_line_width(scale);
+            context.move_to(caret_x, caret_y);
+            context.line_to(caret_x, caret_y + font_h);
+            context.stroke()?;
+        }
 
         context.restore()?;
 
-        Ok(())
+        Ok(drawn)
     }
 
     pub fn render_background(
@@ -353,21 +1271,59 @@ impl NLockRenderer {
         self.reset_cairo_context(args.context)?;
 
         match config.general.bg_type {
+            _ if args.plain => {
+                args.context.ext_set_source_rgba(config.colors.bg);
+                args.context.set_operator(cairo::Operator::Source);
+            }
             BackgroundType::Color => {
                 args.context.ext_set_source_rgba(config.colors.bg);
                 args.context.set_operator(cairo::Operator::Source);
             }
+            // Matches what compositors show with no lock surface at all. The
+            // buffer can't be skipped, see `BackgroundType::None`.
+            BackgroundType::None => {
+                args.context.set_source_rgb(0.0, 0.0, 0.0);
+                args.context.set_operator(cairo::Operator::Source);
+            }
             BackgroundType::Image => {
-                let image = args
+                let gamma = config.image.gamma.clamp(MIN_IMAGE_GAMMA, MAX_IMAGE_GAMMA);
+                let temperature = config
                     .image
-                    .ok_or(anyhow!("Surface in image mode, but no image set!"))?;
-                self.draw_background_image(
-                    args.context,
-                    image,
-                    buf_width,
-                    buf_height,
-                    config.image.scale,
+                    .temperature
+                    .clamp(MIN_IMAGE_TEMPERATURE, MAX_IMAGE_TEMPERATURE);
+
+                if gamma == 1.0 && temperature == NEUTRAL_TEMPERATURE {
+                    return self.draw_image_background(
+                        config,
+                        args.context,
+                        args.images,
+                        buf_width,
+                        buf_height,
+                    );
+                }
+
+                // The curve needs the finished pixels, so composite into a
+                // separate surface, adjust that once, then copy it over
+                let mut surface = cairo::ImageSurface::create(
+                    cairo::Format::ARgb32,
+                    buf_width as i32,
+                    buf_height as i32,
                 )?;
+                {
+                    let context = cairo::Context::new(&surface)?;
+                    context.set_antialias(cairo::Antialias::Best);
+                    self.draw_image_background(
+                        config,
+                        &context,
+                        args.images,
+                        buf_width,
+                        buf_height,
+                    )?;
+                }
+                surface.apply_color_curve(gamma, temperature)?;
+
+                args.context.set_source_surface(&surface, 0.0, 0.0)?;
+                args.context.set_operator(cairo::Operator::Source);
             }
         }
         args.context.paint()?;
@@ -379,18 +1335,403 @@ impl NLockRenderer {
         &mut self,
         config: &NLockConfig,
         args: NLockRenderOverlayArgs,
-    ) -> Result<()> {
+    ) -> Result<Vec<cairo::RectangleInt>> {
         let (buf_width, buf_height) = args.get_buffer_dimensions()?;
 
-        self.draw_overlay(
+        let opacity = config.overlay.opacity.clamp(0.0, 1.0);
+        if opacity >= 1.0 {
+            return self.draw_overlay(config, &args, buf_width, buf_height);
+        }
+
+        // Draw into a group, so the whole overlay fades uniformly, instead of
+        // overlapping elements showing through each other. The buffer itself
+        // still needs clearing, as the group is only painted over it.
+        self.reset_cairo_context(args.context)?;
+        args.context.push_group();
+        let drawn = self.draw_overlay(config, &args, buf_width, buf_height);
+        args.context.pop_group_to_source()?;
+        args.context.paint_with_alpha(opacity)?;
+
+        drawn
+    }
+}
+
+#[cfg(test)]
+impl NLockRenderer {
+    /// Render the background into a new in-memory image, in place of a
+    /// buffer, for checking pixels without a compositor
+    pub fn render_background_to_image(
+        &mut self,
+        config: &NLockConfig,
+        images: &NLockBackgroundImages,
+        width: i32,
+        height: i32,
+    ) -> Result<cairo::ImageSurface> {
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height)?;
+        let context = cairo::Context::new(&surface)?;
+
+        self.render_background(
             config,
-            args.context,
-            args.auth_state,
-            args.pwd_len,
-            buf_width,
-            buf_height,
+            NLockRenderBackgroundArgs {
+                buf_height: height as f64,
+                buf_width: width as f64,
+                context: &context,
+                images,
+                plain: false,
+            },
         )?;
 
-        Ok(())
+        Ok(surface)
+    }
+
+    /// Render the overlay into a new in-memory image, like
+    /// `render_background_to_image`
+    pub fn render_overlay_to_image(
+        &mut self,
+        config: &NLockConfig,
+        password: &str,
+        width: i32,
+        height: i32,
+    ) -> Result<cairo::ImageSurface> {
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height)?;
+        let context = cairo::Context::new(&surface)?;
+
+        self.render_overlay(
+            config,
+            NLockRenderOverlayArgs {
+                auth_state: AuthState::Idle,
+                buf_height: height as f64,
+                buf_width: width as f64,
+                context: &context,
+                debug_info: None,
+                password,
+            },
+        )?;
+
+        Ok(surface)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_text_per_mode() {
+        let password = "pässwörd";
+
+        assert_eq!(echo_text(InputEcho::None, "*", password).as_str(), "");
+        assert_eq!(echo_text(InputEcho::Blocks, "*", password).as_str(), "");
+        assert_eq!(
+            echo_text(InputEcho::Clear, "*", password).as_str(),
+            password
+        );
+
+        // One mask per character, not per byte
+        assert_eq!(
+            echo_text(InputEcho::Mask, "*", password).as_str(),
+            "********"
+        );
+        assert_eq!(echo_text(InputEcho::Mask, "•", "abc").as_str(), "•••");
+    }
+
+    #[test]
+    fn echo_text_of_an_empty_password_is_empty() {
+        for echo in [
+            InputEcho::None,
+            InputEcho::Mask,
+            InputEcho::Clear,
+            InputEcho::Blocks,
+        ] {
+            assert!(echo_text(echo, "*", "").is_empty());
+        }
+    }
+
+    const RED: u32 = 0xffff0000;
+    const BLUE: u32 = 0xff0000ff;
+    const BLACK: u32 = 0xff000000;
+
+    /// Pixel of an ARGB32 surface, as a native-endian word
+    fn pixel(surface: &cairo::ImageSurface, x: usize, y: usize) -> u32 {
+        let stride = surface.stride() as usize;
+
+        let mut pixel = 0;
+        surface
+            .with_data(|data| {
+                let i = y * stride + x * 4;
+                pixel = u32::from_ne_bytes(data[i..i + 4].try_into().unwrap());
+            })
+            .unwrap();
+
+        pixel
+    }
+
+    /// A 40x20 image, red on the left half and blue on the right
+    fn two_tone_image() -> cairo::ImageSurface {
+        let image = cairo::ImageSurface::create(cairo::Format::ARgb32, 40, 20).unwrap();
+        let context = cairo::Context::new(&image).unwrap();
+
+        context.set_source_rgb(1.0, 0.0, 0.0);
+        context.rectangle(0.0, 0.0, 20.0, 20.0);
+        context.fill().unwrap();
+        context.set_source_rgb(0.0, 0.0, 1.0);
+        context.rectangle(20.0, 0.0, 20.0, 20.0);
+        context.fill().unwrap();
+
+        image
+    }
+
+    /// Draw `image` into a transparent buffer, as `draw_background_image` places it
+    fn draw_image(
+        image: &cairo::ImageSurface,
+        mode: BackgroundImageScale,
+        anchor: ImageAnchor,
+        width: i32,
+        height: i32,
+    ) -> cairo::ImageSurface {
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height).unwrap();
+        let context = cairo::Context::new(&surface).unwrap();
+
+        NLockRenderer::default()
+            .draw_background_image(&context, image, width as f64, height as f64, mode, anchor)
+            .unwrap();
+        context.paint().unwrap();
+
+        surface
+    }
+
+    #[test]
+    fn fill_anchor_picks_the_visible_part_of_the_image() {
+        // Filling a 20x40 buffer doubles the image to 80x40, 60 pixels too wide
+        let image = two_tone_image();
+
+        let start = draw_image(
+            &image,
+            BackgroundImageScale::Fill,
+            ImageAnchor::Start,
+            20,
+            40,
+        );
+        assert_eq!(pixel(&start, 10, 20), RED);
+
+        let end = draw_image(&image, BackgroundImageScale::Fill, ImageAnchor::End, 20, 40);
+        assert_eq!(pixel(&end, 10, 20), BLUE);
+
+        // The middle of the image, where the halves meet, is centered
+        let center = draw_image(
+            &image,
+            BackgroundImageScale::Fill,
+            ImageAnchor::Center,
+            20,
+            40,
+        );
+        assert_eq!(pixel(&center, 2, 20), RED);
+        assert_eq!(pixel(&center, 17, 20), BLUE);
+    }
+
+    #[test]
+    fn fit_anchor_places_the_letterboxed_image() {
+        // Fitting a 20x40 buffer halves the image to 20x10, leaving 30 pixels
+        let image = two_tone_image();
+
+        for (anchor, inside, outside) in [
+            (ImageAnchor::Start, 5, 20),
+            (ImageAnchor::Center, 20, 5),
+            (ImageAnchor::End, 35, 20),
+        ] {
+            let surface = draw_image(&image, BackgroundImageScale::Fit, anchor, 20, 40);
+            assert_eq!(pixel(&surface, 2, inside), RED, "{anchor:?}");
+            assert_eq!(pixel(&surface, 2, outside), 0, "{anchor:?}");
+        }
+    }
+
+    #[test]
+    fn default_config_renders_unchanged() {
+        let config = NLockConfig::default();
+        let mut renderer = NLockRenderer::default();
+
+        // A plain black background
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, 200, 100).unwrap();
+        let context = cairo::Context::new(&surface).unwrap();
+        renderer
+            .render_background(
+                &config,
+                NLockRenderBackgroundArgs {
+                    buf_height: 100.0,
+                    buf_width: 200.0,
+                    context: &context,
+                    images: &NLockBackgroundImages::default(),
+                    plain: false,
+                },
+            )
+            .unwrap();
+        for (x, y) in [(0, 0), (100, 50), (199, 99)] {
+            assert_eq!(pixel(&surface, x, y), BLACK);
+        }
+
+        // A black input box in the middle, with nothing around it, as the
+        // idle frame border is transparent
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, 200, 100).unwrap();
+        let context = cairo::Context::new(&surface).unwrap();
+        renderer
+            .render_overlay(
+                &config,
+                NLockRenderOverlayArgs {
+                    auth_state: AuthState::Idle,
+                    buf_height: 100.0,
+                    buf_width: 200.0,
+                    context: &context,
+                    debug_info: None,
+                    password: "",
+                },
+            )
+            .unwrap();
+        assert_eq!(pixel(&surface, 100, 50), BLACK);
+        for (x, y) in [(0, 0), (20, 50), (199, 99)] {
+            assert_eq!(pixel(&surface, x, y), 0);
+        }
+    }
+
+    /// An image background config, with a transparent placeholder
+    fn image_background(image: cairo::ImageSurface) -> (NLockConfig, NLockBackgroundImages) {
+        let mut config = NLockConfig::default();
+        config.general.bg_type = BackgroundType::Image;
+
+        let images = NLockBackgroundImages {
+            image: Some(image),
+            layers: Vec::new(),
+        };
+
+        (config, images)
+    }
+
+    /// Render the two tone image with `mode` into a `width`x`height` buffer
+    fn render_mode(mode: BackgroundImageScale, width: i32, height: i32) -> cairo::ImageSurface {
+        let (mut config, images) = image_background(two_tone_image());
+        config.image.scale = mode;
+
+        NLockRenderer::default()
+            .render_background_to_image(&config, &images, width, height)
+            .unwrap()
+    }
+
+    #[test]
+    fn stretch_covers_the_buffer() {
+        let surface = render_mode(BackgroundImageScale::Stretch, 20, 40);
+        for y in [2, 20, 37] {
+            assert_eq!(pixel(&surface, 5, y), RED);
+            assert_eq!(pixel(&surface, 15, y), BLUE);
+        }
+    }
+
+    #[test]
+    fn center_keeps_the_image_size() {
+        // Placed at 10,20 in a 60x60 buffer
+        let surface = render_mode(BackgroundImageScale::Center, 60, 60);
+        assert_eq!(pixel(&surface, 15, 30), RED);
+        assert_eq!(pixel(&surface, 45, 30), BLUE);
+        assert_eq!(pixel(&surface, 30, 10), 0);
+        assert_eq!(pixel(&surface, 5, 30), 0);
+    }
+
+    #[test]
+    fn tile_repeats_the_image() {
+        let surface = render_mode(BackgroundImageScale::Tile, 80, 40);
+        for y in [10, 30] {
+            assert_eq!(pixel(&surface, 5, y), RED);
+            assert_eq!(pixel(&surface, 25, y), BLUE);
+            assert_eq!(pixel(&surface, 45, y), RED);
+            assert_eq!(pixel(&surface, 65, y), BLUE);
+        }
+    }
+
+    #[test]
+    fn fit_letterboxes_the_image() {
+        // A buffer narrower than the image leaves space above and below
+        let surface = render_mode(BackgroundImageScale::Fit, 80, 80);
+        assert_eq!(pixel(&surface, 20, 40), RED);
+        assert_eq!(pixel(&surface, 60, 40), BLUE);
+        assert_eq!(pixel(&surface, 40, 5), 0);
+        assert_eq!(pixel(&surface, 40, 75), 0);
+
+        // A wider one leaves space to the sides
+        let surface = render_mode(BackgroundImageScale::Fit, 120, 20);
+        assert_eq!(pixel(&surface, 45, 10), RED);
+        assert_eq!(pixel(&surface, 75, 10), BLUE);
+        assert_eq!(pixel(&surface, 10, 10), 0);
+        assert_eq!(pixel(&surface, 110, 10), 0);
+    }
+
+    #[test]
+    fn fill_covers_the_buffer_cropping_the_image() {
+        // Scaled to 160x80, with the middle 80 pixels showing
+        let surface = render_mode(BackgroundImageScale::Fill, 80, 80);
+        for y in [10, 40, 70] {
+            assert_eq!(pixel(&surface, 20, y), RED);
+            assert_eq!(pixel(&surface, 60, y), BLUE);
+        }
+    }
+
+    #[test]
+    fn rotated_output_fits_and_fills_the_portrait_buffer() {
+        // A 1920x1080 output rotated by 90 degrees is configured as 1080x1920,
+        // so the landscape image is scaled against the portrait buffer
+        let surface = render_mode(BackgroundImageScale::Fit, 1080, 1920);
+
+        // Scaled by 27 to 1080x540, not stretched to the full height
+        assert_eq!(pixel(&surface, 270, 960), RED);
+        assert_eq!(pixel(&surface, 810, 960), BLUE);
+        assert_eq!(pixel(&surface, 540, 600), 0);
+        assert_eq!(pixel(&surface, 540, 1320), 0);
+
+        // Scaled by 96 to 3840x1920, the middle of the image is shown
+        let surface = render_mode(BackgroundImageScale::Fill, 1080, 1920);
+        for y in [100, 960, 1820] {
+            assert_eq!(pixel(&surface, 200, y), RED);
+            assert_eq!(pixel(&surface, 880, y), BLUE);
+        }
+    }
+
+    /// Whether every pixel is either fully covered or untouched, without
+    /// partially covered edges
+    fn is_crisp(surface: &cairo::ImageSurface) -> bool {
+        let width = surface.width() as usize;
+        let stride = surface.stride() as usize;
+
+        let mut crisp = true;
+        surface
+            .with_data(|data| {
+                crisp = data.chunks(stride).all(|row| {
+                    row[..width * 4]
+                        .chunks(4)
+                        .map(|px| u32::from_ne_bytes(px.try_into().unwrap()) >> 24)
+                        .all(|alpha| alpha == 0 || alpha == 0xff)
+                });
+            })
+            .unwrap();
+
+        crisp
+    }
+
+    #[test]
+    fn pixel_snap_keeps_edges_crisp_at_fractional_scale() {
+        let mut config = NLockConfig::default();
+        config.input.echo = InputEcho::None;
+        config.colors.input_bg = Rgba::new(1.0, 1.0, 1.0, 1.0);
+
+        let mut renderer = NLockRenderer::default();
+        renderer.set_scale(1.5);
+
+        // Odd sizes, so the centered input box lands between pixels
+        let surface = renderer
+            .render_overlay_to_image(&config, "", 301, 201)
+            .unwrap();
+        assert!(!is_crisp(&surface));
+
+        config.general.pixel_snap = true;
+        let surface = renderer
+            .render_overlay_to_image(&config, "", 301, 201)
+            .unwrap();
+        assert!(is_crisp(&surface));
     }
 }
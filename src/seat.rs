@@ -1,23 +1,43 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright (C) 2026, Nathan Gill
 
-use std::{os::fd::OwnedFd, sync::atomic::Ordering, time::Duration};
+use std::{
+    ffi::OsString,
+    os::fd::OwnedFd,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
 
 use anyhow::{Result, anyhow};
 use nix::sys::{time::TimeSpec, timerfd::Expiration};
-use tracing::{debug, warn};
+use tracing::{debug, trace, warn};
 use wayland_client::{
     Connection, Dispatch, QueueHandle, WEnum,
-    protocol::{wl_keyboard, wl_pointer, wl_seat},
+    protocol::{wl_keyboard, wl_pointer, wl_seat, wl_shm, wl_touch},
 };
 use xkbcommon::xkb;
 
-use crate::{event::EventType, state::NLockState};
+use crate::{
+    auth::AuthState,
+    buffer::{NLockBuffer, NLockShmPool},
+    event::EventType,
+    render::KeyHighlight,
+    state::NLockState,
+    util::{EmptyBackspaceFeedback, InputVisibility, SubmitModifiers},
+};
+
+// Key events held while waiting for a keymap, anything more is dropped
+const MAX_PENDING_KEYS: usize = 64;
+
+// Border flash for backspace on an empty password, unless key highlights
+// set their own duration
+const EMPTY_FLASH_DURATION: Duration = Duration::from_millis(150);
 
 pub struct NLockXkb {
     pub context: xkb::Context,
     pub keymap: Option<xkb::Keymap>,
     pub state: Option<xkb::State>,
+    pub compose_state: Option<xkb::compose::State>,
 }
 
 impl NLockXkb {
@@ -26,6 +46,68 @@ impl NLockXkb {
             context: xkb::Context::new(0),
             keymap: None,
             state: None,
+            compose_state: None,
+        }
+    }
+
+    /// Load the compose table for the current locale, as determined by the
+    /// usual environment variables.
+    pub fn load_compose_table(&mut self) -> Result<()> {
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .filter_map(std::env::var_os)
+            .find(|locale| !locale.is_empty())
+            .unwrap_or_else(|| OsString::from("C"));
+
+        let table = xkb::compose::Table::new_from_locale(
+            &self.context,
+            &locale,
+            xkb::compose::COMPILE_NO_FLAGS,
+        )
+        .map_err(|_| anyhow!("No compose table for locale {}", locale.display()))?;
+
+        self.compose_state = Some(xkb::compose::State::new(
+            &table,
+            xkb::compose::STATE_NO_FLAGS,
+        ));
+
+        debug!("Loaded compose table for locale {}", locale.display());
+
+        Ok(())
+    }
+
+    /// Feed a pressed key through the compose state, if there is one.
+    ///
+    /// Returns the resulting keysym and codepoint, or `None` if the key was
+    /// consumed as part of an incomplete, or cancelled, compose sequence.
+    pub fn compose(&mut self, keysym: xkb::Keysym, codepoint: u32) -> Option<(xkb::Keysym, u32)> {
+        let compose_state = match self.compose_state.as_mut() {
+            Some(compose_state) => compose_state,
+            None => return Some((keysym, codepoint)),
+        };
+
+        if compose_state.feed(keysym) == xkb::compose::FeedResult::Ignored {
+            return Some((keysym, codepoint));
+        }
+
+        match compose_state.status() {
+            xkb::compose::Status::Composing => None,
+            xkb::compose::Status::Cancelled => {
+                compose_state.reset();
+                None
+            }
+            xkb::compose::Status::Composed => {
+                let composed_keysym = compose_state.keysym().unwrap_or(keysym);
+                let composed_codepoint = compose_state
+                    .utf8()
+                    .and_then(|s| s.chars().next())
+                    .map(|ch| ch as u32)
+                    .unwrap_or(codepoint);
+
+                compose_state.reset();
+                Some((composed_keysym, composed_codepoint))
+            }
+            xkb::compose::Status::Nothing => Some((keysym, codepoint)),
         }
     }
 }
@@ -36,67 +118,323 @@ impl Default for NLockXkb {
     }
 }
 
+/// A seat advertised by the compositor, along with its input devices
 pub struct NLockSeat {
+    pub seat: wl_seat::WlSeat,
+    pub name: Option<String>,
     pub pointer: Option<wl_pointer::WlPointer>,
     pub keyboard: Option<wl_keyboard::WlKeyboard>,
+    pub touch: Option<wl_touch::WlTouch>,
     pub repeat_rate: i32,
     pub repeat_delay: i32,
-    pub repeat_keysym: Option<xkb::Keysym>,
-    pub repeat_codepoint: Option<u32>,
-    pub repeat_timer_set: bool,
+    // Each keyboard has its own keymap, and modifiers held on one don't
+    // apply to keys pressed on another
+    pub xkb: NLockXkb,
+    // Key events received before the keymap, replayed once it arrives
+    pub pending_keys: Vec<(u32, WEnum<wl_keyboard::KeyState>)>,
 }
 
 impl NLockSeat {
-    pub fn new() -> Self {
+    pub fn new(seat: wl_seat::WlSeat) -> Self {
         Self {
+            seat,
+            name: None,
             pointer: None,
             keyboard: None,
+            touch: None,
             repeat_rate: 0,
             repeat_delay: 0,
-            repeat_keysym: None,
-            repeat_codepoint: None,
-            repeat_timer_set: false,
+            xkb: NLockXkb::default(),
+            pending_keys: Vec::new(),
         }
     }
 }
 
-impl Default for NLockSeat {
+/// Key repeat in progress, shared between all seats, as only the most recent
+/// key press is ever repeated.
+pub struct NLockKeyRepeat {
+    pub keysym: Option<xkb::Keysym>,
+    pub codepoint: Option<u32>,
+    // Keyboard the key was pressed on, whose modifiers apply to each repeat
+    pub keyboard: Option<wl_keyboard::WlKeyboard>,
+    pub timer_set: bool,
+}
+
+impl NLockKeyRepeat {
+    pub fn new() -> Self {
+        Self {
+            keysym: None,
+            codepoint: None,
+            keyboard: None,
+            timer_set: false,
+        }
+    }
+}
+
+impl Default for NLockKeyRepeat {
     fn default() -> Self {
         Self::new()
     }
 }
 
 impl NLockState {
-    pub fn handle_keymap_event(&mut self, fd: OwnedFd, size: u32) -> Result<()> {
-        let keymap =
-            unsafe { xkb::Keymap::new_from_fd(&self.xkb.context, fd, size as usize, 1, 0) }?
-                .ok_or(anyhow!("Failed to get keymap"))?;
-        let state = xkb::State::new(&keymap);
+    /// Hide the cursor over a lock surface. Some compositors keep showing
+    /// the cursor without a cursor surface, so a transparent one can be set
+    /// instead, falling back to none if it can't be created.
+    fn hide_cursor(
+        &mut self,
+        pointer: &wl_pointer::WlPointer,
+        serial: u32,
+        qh: &QueueHandle<Self>,
+    ) {
+        if self.config.general.transparent_cursor
+            && self.cursor_surface.is_none()
+            && let (Some(compositor), Some(shm)) = (&self.compositor, &self.shm)
+        {
+            let buffer = NLockShmPool::new(shm, 4, self.config.general.shm_backend, qh).and_then(
+                |mut pool| {
+                    // The buffer outlives the pool
+                    let buffer = NLockBuffer::new(&mut pool, 1, 1, wl_shm::Format::Argb8888, qh);
+                    pool.destroy();
+                    buffer
+                },
+            );
+
+            match buffer {
+                Some(buffer) => {
+                    let surface = compositor.create_surface(qh, ());
+                    if let Some(mut guard) = buffer.lock_buffer() {
+                        guard.commit_to(&surface, 1, None);
+                    }
+
+                    self.cursor_surface = Some(surface);
+                    self.cursor_buffer = Some(buffer);
+                }
+                None => warn!("Failed to create transparent cursor, hiding it instead"),
+            }
+        }
 
-        self.xkb.state = Some(state);
-        self.xkb.keymap = Some(keymap);
+        match &self.cursor_surface {
+            Some(surface) => {
+                debug!("Received pointer enter event, cursor made transparent");
+                pointer.set_cursor(serial, Some(surface), 0, 0);
+            }
+            None => {
+                debug!("Received pointer enter event, cursor hidden");
+                pointer.set_cursor(serial, None, 0, 0);
+            }
+        }
+    }
+
+    /// Build the keymap configured by name, if any
+    fn configured_keymap(&self, context: &xkb::Context) -> Option<xkb::Keymap> {
+        let names = &self.config.xkb;
+        if !names.is_set() {
+            return None;
+        }
+
+        let options = (!names.options.is_empty()).then(|| names.options.clone());
+        let keymap = xkb::Keymap::new_from_names(
+            context,
+            "",
+            &names.model,
+            &names.layout,
+            &names.variant,
+            options,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        );
+
+        if keymap.is_none() {
+            warn!("Failed to build configured keymap, using compositor keymap");
+        }
+
+        keymap
+    }
+
+    /// Find the seat a keyboard belongs to
+    fn keyboard_seat(&self, keyboard: &wl_keyboard::WlKeyboard) -> Option<usize> {
+        self.seats
+            .iter()
+            .position(|s| s.keyboard.as_ref() == Some(keyboard))
+    }
+
+    pub fn handle_keymap_event(
+        &mut self,
+        keyboard: &wl_keyboard::WlKeyboard,
+        fd: OwnedFd,
+        size: u32,
+    ) -> Result<()> {
+        let index = self
+            .keyboard_seat(keyboard)
+            .ok_or(anyhow!("Keymap for an unknown keyboard"))?;
+
+        let context = self.seats[index].xkb.context.clone();
+        let keymap = match self.configured_keymap(&context) {
+            Some(keymap) => keymap,
+            None => unsafe { xkb::Keymap::new_from_fd(&context, fd, size as usize, 1, 0) }?
+                .ok_or(anyhow!("Failed to get keymap"))?,
+        };
+        let mut state = xkb::State::new(&keymap);
+
+        // A new keymap mid-session, e.g. after a layout switch, or keyboard
+        // hotplug. Anything derived from the old keymap is stale.
+        if let Some(old_state) = self.seats[index].xkb.state.take() {
+            debug!("Keymap changed, resetting key repeat and compose state");
+
+            if self.key_repeat.keyboard.as_ref() == Some(keyboard) {
+                self.cancel_key_repeat()?;
+                self.key_repeat.keysym = None;
+                self.key_repeat.codepoint = None;
+            }
+
+            if let Some(compose_state) = self.seats[index].xkb.compose_state.as_mut() {
+                compose_state.reset();
+            }
+
+            carry_over_modifiers(&old_state, &mut state);
+        }
+
+        let xkb = &mut self.seats[index].xkb;
+        xkb.state = Some(state);
+        xkb.keymap = Some(keymap);
 
         debug!("Created keymap and state");
 
+        // Missing compose tables fall back to plain keysyms
+        if self.config.input.compose
+            && xkb.compose_state.is_none()
+            && let Err(e) = xkb.load_compose_table()
+        {
+            debug!("Compose support unavailable: {e}");
+        }
+
+        self.replay_pending_keys(keyboard, index)
+    }
+
+    /// Process key events that arrived before there was a keymap to
+    /// interpret them with, in the order they were received
+    fn replay_pending_keys(
+        &mut self,
+        keyboard: &wl_keyboard::WlKeyboard,
+        index: usize,
+    ) -> Result<()> {
+        let keys = std::mem::take(&mut self.seats[index].pending_keys);
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        debug!(
+            "Replaying {} key events received before the keymap",
+            keys.len()
+        );
+
+        for (key, key_state) in keys {
+            self.handle_key_event(keyboard, key, key_state)?;
+        }
+
         Ok(())
     }
 
-    pub fn process_key(&mut self, keysym: xkb::Keysym, codepoint: u32) {
+    /// Get the Shift, Ctrl, Alt and Super modifiers held on a keyboard
+    fn held_modifiers(
+        &self,
+        keyboard: Option<&wl_keyboard::WlKeyboard>,
+    ) -> Option<(bool, bool, bool, bool)> {
+        let index = self.keyboard_seat(keyboard?)?;
+        let state = self.seats[index].xkb.state.as_ref()?;
+
+        let active = |name: &str| state.mod_name_is_active(name, xkb::STATE_MODS_EFFECTIVE);
+        Some((
+            active(xkb::MOD_NAME_SHIFT),
+            active(xkb::MOD_NAME_CTRL),
+            active(xkb::MOD_NAME_ALT),
+            active(xkb::MOD_NAME_LOGO),
+        ))
+    }
+
+    /// Check whether the held modifiers match those required to submit
+    fn submit_modifiers_held(&self, keyboard: Option<&wl_keyboard::WlKeyboard>) -> bool {
+        let Some((shift, ctrl, alt, logo)) = self.held_modifiers(keyboard) else {
+            return true;
+        };
+
+        match self.config.keys.submit {
+            SubmitModifiers::Any => true,
+            SubmitModifiers::None => !(shift || ctrl || alt || logo),
+            SubmitModifiers::Shift => shift,
+            SubmitModifiers::Ctrl => ctrl,
+            SubmitModifiers::Alt => alt,
+            SubmitModifiers::Super => logo,
+        }
+    }
+
+    /// Act on a key pressed on `keyboard`, which decides the modifiers held
+    pub fn process_key(
+        &mut self,
+        keyboard: Option<&wl_keyboard::WlKeyboard>,
+        keysym: xkb::Keysym,
+        codepoint: u32,
+    ) {
+        // Redrawing doesn't touch the password, so is allowed even when locked out
+        let (shift, ctrl, alt, logo) = self.held_modifiers(keyboard).unwrap_or_default();
+        if self
+            .config
+            .keys
+            .redraw
+            .matches(keysym, shift, ctrl, alt, logo)
+        {
+            self.force_redraw();
+            return;
+        }
+
+        if self.is_locked_out() {
+            return;
+        }
+
+        // Any input after a timeout, or a rejected password, starts a new attempt
+        if matches!(
+            self.auth_state.load(Ordering::Relaxed),
+            AuthState::TimedOut
+                | AuthState::TooLong
+                | AuthState::TooShort
+                | AuthState::AccountUnusable
+        ) {
+            self.auth_state.store(AuthState::Idle, Ordering::Relaxed);
+        }
+
+        // With NumLock off, numpad keys move the cursor, which means nothing
+        // here, and would otherwise be silently ignored
+        let codepoint = match numpad_digit(keysym) {
+            Some(digit) if self.config.input.numpad_always_digits => digit as u32,
+            _ => codepoint,
+        };
+
         match keysym {
             xkb::Keysym::KP_Enter | xkb::Keysym::Return => {
-                self.submit_password();
+                if self.submit_modifiers_held(keyboard) {
+                    self.submit_password();
+                }
             }
             xkb::Keysym::BackSpace | xkb::Keysym::Delete => {
                 if !self.password.is_empty() {
                     self.password.pop();
+                    self.highlight_key(KeyHighlight::Backspace);
+                } else if self.config.input.empty_backspace_feedback
+                    == EmptyBackspaceFeedback::Flash
+                {
+                    self.highlight_key(KeyHighlight::Empty);
                 }
             }
             xkb::Keysym::Escape => {
+                if !self.password.is_empty() {
+                    self.highlight_key(KeyHighlight::Backspace);
+                }
                 self.clear_password();
             }
             _ => match char::from_u32(codepoint) {
                 Some(ch) if !ch.is_control() => {
                     self.password.push(ch);
+                    self.highlight_key(KeyHighlight::Key);
                 }
                 _ => {}
             },
@@ -107,37 +445,69 @@ impl NLockState {
 
     pub fn handle_key_event(
         &mut self,
+        keyboard: &wl_keyboard::WlKeyboard,
         key: u32,
         key_state: WEnum<wl_keyboard::KeyState>,
     ) -> Result<()> {
-        if self.xkb.state.is_none() {
-            return Err(anyhow!("Xkb state not set"));
-        }
+        let index = self
+            .keyboard_seat(keyboard)
+            .ok_or(anyhow!("Key event from an unknown keyboard"))?;
+        let seat = &mut self.seats[index];
+
+        let Some(xkb_state) = seat.xkb.state.as_ref() else {
+            // Some compositors send keys before the keymap, hold on to them
+            // rather than losing the start of the password
+            if seat.pending_keys.len() >= MAX_PENDING_KEYS {
+                return Err(anyhow!("Xkb state not set, too many key events pending"));
+            }
+
+            seat.pending_keys.push((key, key_state));
+            return Ok(());
+        };
 
         let keycode = xkb::Keycode::new(key + 8);
-        let keysym = self.xkb.state.as_ref().unwrap().key_get_one_sym(keycode);
-        let codepoint = self.xkb.state.as_ref().unwrap().key_get_utf32(keycode);
+        let keysym = xkb_state.key_get_one_sym(keycode);
+        let codepoint = xkb_state.key_get_utf32(keycode);
 
-        if let WEnum::Value(wl_keyboard::KeyState::Pressed) = key_state {
-            self.process_key(keysym, codepoint);
-        }
+        self.cancel_key_repeat()?;
 
-        if self.seat.repeat_timer_set
-            && let Err(e) = self.unset_timer(EventType::KeyboardRepeat as usize)
-        {
-            return Err(e);
-        } else {
-            self.seat.repeat_timer_set = false;
+        let pressed = matches!(key_state, WEnum::Value(wl_keyboard::KeyState::Pressed));
+        if !pressed {
+            return Ok(());
         }
 
-        if let WEnum::Value(wl_keyboard::KeyState::Pressed) = key_state
-            && self.seat.repeat_rate > 0
-        {
-            self.seat.repeat_keysym = Some(keysym);
-            self.seat.repeat_codepoint = Some(codepoint);
-
-            let repeat_delay_duration = Duration::from_millis(self.seat.repeat_delay as u64);
-            let repeat_rate_duration = Duration::from_millis(self.seat.repeat_rate as u64);
+        // Keys that are part of a compose sequence are neither processed, nor
+        // repeated, until the sequence is complete.
+        let (keysym, codepoint) = match self.seats[index].xkb.compose(keysym, codepoint) {
+            Some(composed) => composed,
+            None => return Ok(()),
+        };
+
+        self.process_key(Some(keyboard), keysym, codepoint);
+
+        // Repeat follows the settings of the keyboard the key was pressed on,
+        // unless overridden in the config
+        let seat = &self.seats[index];
+        let repeat_rate = seat.repeat_rate.max(0) as u32;
+        let repeat_delay = seat.repeat_delay.max(0) as u32;
+
+        let repeat_rate = match self.config.input.force_repeat_rate {
+            0 => repeat_rate,
+            rate => rate,
+        };
+        let repeat_delay = match self.config.input.force_repeat_delay {
+            0 => repeat_delay,
+            delay => delay,
+        };
+
+        if repeat_rate > 0 {
+            self.key_repeat.keysym = Some(keysym);
+            self.key_repeat.codepoint = Some(codepoint);
+            self.key_repeat.keyboard = Some(keyboard.clone());
+
+            // The rate is in keys per second, the delay in milliseconds
+            let repeat_delay_duration = Duration::from_millis(repeat_delay as u64);
+            let repeat_rate_duration = Duration::from_secs(1) / repeat_rate;
 
             self.set_timer(
                 EventType::KeyboardRepeat as usize,
@@ -147,44 +517,193 @@ impl NLockState {
                 ),
             )?;
 
-            self.seat.repeat_timer_set = true;
+            self.key_repeat.timer_set = true;
+        }
+
+        Ok(())
+    }
+
+    /// Show an input box hidden for having an empty password, for the
+    /// configured wake duration after the latest pointer activity.
+    pub fn wake_input(&mut self) -> Result<()> {
+        let wake = Duration::from_millis(self.config.input.wake_ms);
+        if wake.is_zero() || self.config.input.visible != InputVisibility::Content {
+            return Ok(());
+        }
+
+        let was_woken = self.show_until.is_some();
+        self.show_until = Some(Instant::now() + wake);
+
+        // Motion events are frequent, so only the first arms the timer, which
+        // is armed again on expiry if there was activity since
+        if !was_woken {
+            self.set_timer(
+                EventType::WakeExpired as usize,
+                Expiration::OneShot(TimeSpec::from_duration(wake)),
+            )?;
+            self.set_input_woken(true);
+        }
+
+        Ok(())
+    }
+
+    pub fn handle_wake_expired(&mut self) -> Result<()> {
+        self.unset_timer(EventType::WakeExpired as usize)?;
+
+        let Some(show_until) = self.show_until else {
+            return Ok(());
+        };
+
+        let remaining = show_until.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            self.show_until = None;
+            self.set_input_woken(false);
+        } else {
+            self.set_timer(
+                EventType::WakeExpired as usize,
+                Expiration::OneShot(TimeSpec::from_duration(remaining)),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Briefly tint the input border, acknowledging a key without revealing
+    /// anything about the password
+    fn highlight_key(&mut self, highlight: KeyHighlight) {
+        let duration = match highlight {
+            // Asked for separately, so flashes even with highlighting off
+            KeyHighlight::Empty if self.config.animation.highlight_ms == 0 => EMPTY_FLASH_DURATION,
+            _ => Duration::from_millis(self.config.animation.highlight_ms),
+        };
+        if duration.is_zero() {
+            return;
+        }
+
+        // Each keypress restarts the highlight, replacing any pending timer
+        let rearmed = self
+            .unset_timer(EventType::HighlightExpired as usize)
+            .and_then(|_| {
+                self.set_timer(
+                    EventType::HighlightExpired as usize,
+                    Expiration::OneShot(TimeSpec::from_duration(duration)),
+                )
+            });
+
+        if let Err(e) = rearmed {
+            warn!("Failed to set key highlight timer: {e}");
+            return;
+        }
+
+        self.set_key_highlight(Some(highlight));
+    }
+
+    /// Stop any in-progress key repeat
+    pub fn cancel_key_repeat(&mut self) -> Result<()> {
+        if self.key_repeat.timer_set {
+            self.unset_timer(EventType::KeyboardRepeat as usize)?;
+        }
+
+        self.key_repeat.timer_set = false;
+
+        Ok(())
+    }
+
+    /// Handle keyboard focus leaving the lock surface
+    ///
+    /// Key repeat is cancelled, and held modifiers are treated as released,
+    /// since no release events will be received while focus is elsewhere.
+    pub fn handle_leave_event(&mut self, keyboard: &wl_keyboard::WlKeyboard) -> Result<()> {
+        self.cancel_key_repeat()?;
+        self.key_repeat.keysym = None;
+        self.key_repeat.codepoint = None;
+
+        if let Some(index) = self.keyboard_seat(keyboard)
+            && let Some(xkb_state) = self.seats[index].xkb.state.as_mut()
+        {
+            let locked = xkb_state.serialize_mods(xkb::STATE_MODS_LOCKED);
+            let group = xkb_state.serialize_layout(xkb::STATE_LAYOUT_EFFECTIVE);
+            xkb_state.update_mask(0, 0, locked, 0, 0, group);
         }
 
         Ok(())
     }
 
     pub fn handle_repeat_event(&mut self) {
-        if let (Some(keysym), Some(codepoint)) =
-            (self.seat.repeat_keysym, self.seat.repeat_codepoint)
+        if let (Some(keysym), Some(codepoint)) = (self.key_repeat.keysym, self.key_repeat.codepoint)
         {
-            self.process_key(keysym, codepoint);
+            let keyboard = self.key_repeat.keyboard.clone();
+            self.process_key(keyboard.as_ref(), keysym, codepoint);
         }
     }
 
     pub fn handle_modifiers_event(
         &mut self,
+        keyboard: &wl_keyboard::WlKeyboard,
         depressed: u32,
         latched: u32,
         locked: u32,
         group: u32,
     ) -> Result<()> {
-        if self.xkb.state.is_none() {
-            return Err(anyhow!("Xkb state not set"));
-        }
+        let xkb_state = self
+            .keyboard_seat(keyboard)
+            .and_then(|index| self.seats[index].xkb.state.as_mut())
+            .ok_or(anyhow!("Xkb state not set"))?;
 
-        self.xkb
-            .state
-            .as_mut()
-            .unwrap()
-            .update_mask(depressed, latched, locked, 0, 0, group);
+        xkb_state.update_mask(depressed, latched, locked, 0, 0, group);
         Ok(())
     }
 }
 
+/// Get the digit a numpad key types, whether NumLock is on or off
+fn numpad_digit(keysym: xkb::Keysym) -> Option<char> {
+    let digit = match keysym {
+        xkb::Keysym::KP_0 | xkb::Keysym::KP_Insert => '0',
+        xkb::Keysym::KP_1 | xkb::Keysym::KP_End => '1',
+        xkb::Keysym::KP_2 | xkb::Keysym::KP_Down => '2',
+        xkb::Keysym::KP_3 | xkb::Keysym::KP_Next => '3',
+        xkb::Keysym::KP_4 | xkb::Keysym::KP_Left => '4',
+        xkb::Keysym::KP_5 | xkb::Keysym::KP_Begin => '5',
+        xkb::Keysym::KP_6 | xkb::Keysym::KP_Right => '6',
+        xkb::Keysym::KP_7 | xkb::Keysym::KP_Home => '7',
+        xkb::Keysym::KP_8 | xkb::Keysym::KP_Up => '8',
+        xkb::Keysym::KP_9 | xkb::Keysym::KP_Prior => '9',
+        _ => return None,
+    };
+
+    Some(digit)
+}
+
+/// Apply the modifiers held in one state to a state for a different keymap.
+///
+/// Modifier indices differ between keymaps, so they are matched by name.
+/// Modifiers with no equivalent in the new keymap are dropped.
+fn carry_over_modifiers(old: &xkb::State, new: &mut xkb::State) {
+    let old_keymap = old.get_keymap();
+    let new_keymap = new.get_keymap();
+
+    let remap = |mask: xkb::ModMask| {
+        (0..old_keymap.num_mods())
+            .filter(|&idx| idx < 32 && mask & (1 << idx) != 0)
+            .map(|idx| new_keymap.mod_get_index(old_keymap.mod_get_name(idx)))
+            .filter(|&idx| idx != xkb::MOD_INVALID && idx < 32)
+            .fold(0, |mask, idx| mask | (1 << idx))
+    };
+
+    let depressed = remap(old.serialize_mods(xkb::STATE_MODS_DEPRESSED));
+    let latched = remap(old.serialize_mods(xkb::STATE_MODS_LATCHED));
+    let locked = remap(old.serialize_mods(xkb::STATE_MODS_LOCKED));
+    let group = old.serialize_layout(xkb::STATE_LAYOUT_EFFECTIVE);
+
+    // The compositor normally follows up with a Modifiers event, which
+    // replaces these anyway
+    new.update_mask(depressed, latched, locked, 0, 0, group);
+}
+
 impl Dispatch<wl_keyboard::WlKeyboard, ()> for NLockState {
     fn event(
         state: &mut Self,
-        _: &wl_keyboard::WlKeyboard,
+        keyboard: &wl_keyboard::WlKeyboard,
         event: <wl_keyboard::WlKeyboard as wayland_client::Proxy>::Event,
         _: &(),
         _: &Connection,
@@ -193,7 +712,7 @@ impl Dispatch<wl_keyboard::WlKeyboard, ()> for NLockState {
         match event {
             wl_keyboard::Event::Keymap { format, fd, size } => {
                 if let WEnum::Value(wl_keyboard::KeymapFormat::XkbV1) = format
-                    && let Err(e) = state.handle_keymap_event(fd, size)
+                    && let Err(e) = state.handle_keymap_event(keyboard, fd, size)
                 {
                     warn!("Error while handling keymap event: {e}");
                 }
@@ -204,13 +723,32 @@ impl Dispatch<wl_keyboard::WlKeyboard, ()> for NLockState {
                 key,
                 state: key_state,
             } => {
-                if let Err(e) = state.handle_key_event(key, key_state) {
+                if let Err(e) = state.handle_key_event(keyboard, key, key_state) {
                     warn!("Error while handling key event: {e}");
                 }
             }
+            wl_keyboard::Event::Enter { surface, .. } => {
+                // Modifier state is resynchronised by the following Modifiers event
+                debug!("Keyboard focus entered");
+
+                state.activate_output(&surface);
+            }
+            wl_keyboard::Event::Leave { .. } => {
+                debug!("Keyboard focus left");
+
+                if let Err(e) = state.handle_leave_event(keyboard) {
+                    warn!("Error while handling keyboard leave event: {e}");
+                }
+            }
             wl_keyboard::Event::RepeatInfo { rate, delay } => {
-                state.seat.repeat_rate = rate;
-                state.seat.repeat_delay = delay;
+                if let Some(seat) = state
+                    .seats
+                    .iter_mut()
+                    .find(|s| s.keyboard.as_ref() == Some(keyboard))
+                {
+                    seat.repeat_rate = rate;
+                    seat.repeat_delay = delay;
+                }
             }
             wl_keyboard::Event::Modifiers {
                 serial: _,
@@ -219,9 +757,13 @@ impl Dispatch<wl_keyboard::WlKeyboard, ()> for NLockState {
                 mods_locked,
                 group,
             } => {
-                if let Err(e) =
-                    state.handle_modifiers_event(mods_depressed, mods_latched, mods_locked, group)
-                {
+                if let Err(e) = state.handle_modifiers_event(
+                    keyboard,
+                    mods_depressed,
+                    mods_latched,
+                    mods_locked,
+                    group,
+                ) {
                     warn!("Error while handling modifiers event: {e}");
                 }
             }
@@ -237,18 +779,65 @@ impl Dispatch<wl_pointer::WlPointer, ()> for NLockState {
         event: <wl_pointer::WlPointer as wayland_client::Proxy>::Event,
         _: &(),
         _: &wayland_client::Connection,
-        _: &wayland_client::QueueHandle<Self>,
+        qh: &wayland_client::QueueHandle<Self>,
     ) {
-        if let wl_pointer::Event::Enter {
-            serial,
-            surface: _,
-            surface_x: _,
-            surface_y: _,
-        } = event
-            && state.config.general.hide_cursor
-        {
-            debug!("Received pointer enter event, cursor hidden");
-            pointer.set_cursor(serial, None, 0, 0);
+        match event {
+            wl_pointer::Event::Enter {
+                serial,
+                surface,
+                surface_x: _,
+                surface_y: _,
+            } => {
+                if state.config.general.hide_cursor {
+                    state.hide_cursor(pointer, serial, qh);
+                }
+
+                state.activate_output(&surface);
+
+                if let Err(e) = state.wake_input() {
+                    warn!("Failed to wake input box: {e}");
+                }
+            }
+            wl_pointer::Event::Motion { .. } => {
+                if let Err(e) = state.wake_input() {
+                    warn!("Failed to wake input box: {e}");
+                }
+            }
+            // Buttons and scrolling are consumed by the lock surface, and never
+            // acted upon. They are matched explicitly to make that obvious.
+            wl_pointer::Event::Button { .. }
+            | wl_pointer::Event::Axis { .. }
+            | wl_pointer::Event::AxisSource { .. }
+            | wl_pointer::Event::AxisStop { .. }
+            | wl_pointer::Event::AxisDiscrete { .. }
+            | wl_pointer::Event::AxisValue120 { .. }
+            | wl_pointer::Event::AxisRelativeDirection { .. } => {
+                trace!("Ignoring pointer button or axis event");
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_touch::WlTouch, ()> for NLockState {
+    fn event(
+        state: &mut Self,
+        _: &wl_touch::WlTouch,
+        event: <wl_touch::WlTouch as wayland_client::Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // Only touches going down matter, there is no PIN pad to track
+        // points against
+        if let wl_touch::Event::Down { surface, .. } = event {
+            // Without a keyboard, touching is the only way to find the
+            // input box
+            if let Err(e) = state.wake_input() {
+                warn!("Failed to wake input box: {e}");
+            }
+
+            state.activate_output(&surface);
         }
     }
 }
@@ -262,30 +851,90 @@ impl Dispatch<wl_seat::WlSeat, ()> for NLockState {
         _: &Connection,
         qh: &QueueHandle<Self>,
     ) {
-        if let wl_seat::Event::Capabilities {
-            capabilities: WEnum::Value(capabilities),
-        } = event
-        {
-            if capabilities.contains(wl_seat::Capability::Keyboard) {
-                if let Some(keyboard) = &state.seat.keyboard {
-                    keyboard.release();
+        let Some(nlock_seat) = state.seats.iter_mut().find(|s| &s.seat == seat) else {
+            return;
+        };
+
+        match event {
+            wl_seat::Event::Capabilities {
+                capabilities: WEnum::Value(capabilities),
+            } => {
+                if capabilities.contains(wl_seat::Capability::Keyboard) {
+                    if let Some(keyboard) = &nlock_seat.keyboard {
+                        keyboard.release();
+                    }
+
+                    let keyboard = seat.get_keyboard(qh, ());
+                    nlock_seat.keyboard = Some(keyboard);
+
+                    debug!("Found keyboard");
                 }
+                if capabilities.contains(wl_seat::Capability::Pointer) {
+                    if let Some(pointer) = &nlock_seat.pointer {
+                        pointer.release();
+                    }
 
-                let keyboard = seat.get_keyboard(qh, ());
-                state.seat.keyboard = Some(keyboard);
+                    let pointer = seat.get_pointer(qh, ());
+                    nlock_seat.pointer = Some(pointer);
 
-                debug!("Found keyboard");
-            }
-            if capabilities.contains(wl_seat::Capability::Pointer) {
-                if let Some(pointer) = &state.seat.pointer {
-                    pointer.release();
+                    debug!("Found pointer");
                 }
+                if capabilities.contains(wl_seat::Capability::Touch) {
+                    if let Some(touch) = &nlock_seat.touch {
+                        touch.release();
+                    }
 
-                let pointer = seat.get_pointer(qh, ());
-                state.seat.pointer = Some(pointer);
+                    let touch = seat.get_touch(qh, ());
+                    nlock_seat.touch = Some(touch);
 
-                debug!("Found pointer");
+                    debug!("Found touch device");
+                }
+            }
+            wl_seat::Event::Name { name } => {
+                debug!("Seat name: {name}");
+                nlock_seat.name = Some(name);
             }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numpad_keys_type_digits_with_numlock_on_or_off() {
+        let keys = [
+            (xkb::Keysym::KP_0, xkb::Keysym::KP_Insert, '0'),
+            (xkb::Keysym::KP_1, xkb::Keysym::KP_End, '1'),
+            (xkb::Keysym::KP_2, xkb::Keysym::KP_Down, '2'),
+            (xkb::Keysym::KP_3, xkb::Keysym::KP_Next, '3'),
+            (xkb::Keysym::KP_4, xkb::Keysym::KP_Left, '4'),
+            (xkb::Keysym::KP_5, xkb::Keysym::KP_Begin, '5'),
+            (xkb::Keysym::KP_6, xkb::Keysym::KP_Right, '6'),
+            (xkb::Keysym::KP_7, xkb::Keysym::KP_Home, '7'),
+            (xkb::Keysym::KP_8, xkb::Keysym::KP_Up, '8'),
+            (xkb::Keysym::KP_9, xkb::Keysym::KP_Prior, '9'),
+        ];
+
+        for (numlock_on, numlock_off, digit) in keys {
+            assert_eq!(numpad_digit(numlock_on), Some(digit));
+            assert_eq!(numpad_digit(numlock_off), Some(digit));
+        }
+    }
+
+    #[test]
+    fn other_keys_are_not_numpad_digits() {
+        for keysym in [
+            xkb::Keysym::KP_Enter,
+            xkb::Keysym::KP_Decimal,
+            xkb::Keysym::KP_Delete,
+            xkb::Keysym::KP_Add,
+            xkb::Keysym::_0,
+            xkb::Keysym::Home,
+        ] {
+            assert_eq!(numpad_digit(keysym), None);
         }
     }
 }
@@ -4,18 +4,24 @@
 use std::{
     fs::File,
     io::Seek,
-    sync::{Arc, atomic::AtomicBool},
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Result, anyhow, bail};
 use cairo::ImageSurface;
 use gdk_pixbuf::Pixbuf;
 use mio::Poll;
-use nix::sys::timerfd::TimerFd;
-use tracing::{debug, warn};
+use nix::sys::{time::TimeSpec, timerfd::Expiration};
+use tracing::{debug, error, warn};
 use wayland_client::protocol::{wl_region, wl_subcompositor, wl_subsurface};
 use wayland_client::{
-    Connection, Dispatch, QueueHandle, delegate_noop,
+    Connection, Dispatch, QueueHandle, WEnum, delegate_noop,
     protocol::{
         wl_callback, wl_compositor, wl_display, wl_output, wl_registry, wl_seat, wl_shm,
         wl_shm_pool, wl_surface,
@@ -27,43 +33,90 @@ use wayland_protocols::ext::session_lock::v1::client::{
 use zeroize::Zeroizing;
 
 use crate::config::NLockConfig;
-use crate::util::BackgroundType;
+use crate::event::{EventType, TimerSource};
+use crate::render::{
+    KeyHighlight, NLockBackgroundImages, NLockImageLayer, resolve_configured_font_family,
+    resolve_default_font_family,
+};
+use crate::util::{
+    BackgroundImageScale, BackgroundType, BufferFormat, SecondaryMode, on_battery, scrub_password,
+};
 use crate::{
     auth::AuthChannel,
+    buffer::NLockBuffer,
     cairo_ext::{ImageSurfaceExt, SubpixelOrderExt},
 };
 use crate::{
-    auth::{AtomicAuthState, AuthState},
+    auth::{AtomicAuthState, AuthConfig, AuthState, exceeds_pam_length, spawn_auth_thread},
     util::detect_png,
 };
 use crate::{
-    seat::{NLockSeat, NLockXkb},
+    seat::{NLockKeyRepeat, NLockSeat},
     surface::NLockSurface,
 };
 
+// Longest time to wait for every output to show the success state
+const SYNC_UNLOCK_GRACE: Duration = Duration::from_millis(250);
+
+// How long the power source is trusted for, before checking it again
+const POWER_SOURCE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// How long the input box shakes after a failed attempt, and how many times
+// it swings each way in that time
+const SHAKE_DURATION: Duration = Duration::from_millis(400);
+const SHAKE_SWINGS: f64 = 4.0;
+
 pub struct NLockState {
     pub config: NLockConfig,
     pub running: Arc<AtomicBool>,
     pub locked: bool,
     pub unlocked: bool,
+    pub lock_lost: bool,
+    pub relocks: u32,
     pub state_changed: Arc<AtomicBool>,
     pub display: wl_display::WlDisplay,
     pub registry: Option<wl_registry::WlRegistry>,
+    pub globals: Vec<(String, u32)>,
     pub compositor: Option<wl_compositor::WlCompositor>,
     pub subcompositor: Option<wl_subcompositor::WlSubcompositor>,
     pub shm: Option<wl_shm::WlShm>,
-    pub r_seat: Option<wl_seat::WlSeat>,
+    pub shm_formats: Vec<wl_shm::Format>,
+    pub bg_format: wl_shm::Format,
     pub session_lock_manager: Option<ext_session_lock_manager_v1::ExtSessionLockManagerV1>,
     pub session_lock: Option<ext_session_lock_v1::ExtSessionLockV1>,
     pub surfaces: Vec<NLockSurface>,
-    pub seat: NLockSeat,
-    pub xkb: NLockXkb,
+    pub seats: Vec<NLockSeat>,
+    pub key_repeat: NLockKeyRepeat,
+    // Only cleared on submit, Escape, or when the lock ends. Surface
+    // configures, output changes and keymap changes all leave it as typed.
     pub password: Zeroizing<String>,
+    // Last password sent to PAM, kept only to restore it after a failure
+    pub submitted_password: Zeroizing<String>,
     pub poll: Option<Poll>,
-    pub timers: Vec<(TimerFd, usize)>,
+    pub timers: Option<Box<dyn TimerSource>>,
     pub auth_comm: Arc<AuthChannel>,
+    pub auth_thread: Option<JoinHandle<()>>,
+    pub auth_restarts: u32,
     pub auth_state: Arc<AtomicAuthState>,
-    pub background_image: Option<cairo::ImageSurface>,
+    pub failed_attempts: u32,
+    pub suspended_time: Option<Duration>,
+    pub caret_visible: bool,
+    // Input box is shown despite an empty password until this time
+    pub show_until: Option<Instant>,
+    // Unlock is waiting for every output to show the success state
+    pub unlock_pending: bool,
+    // The spinner is animating, since the password was submitted at this time
+    pub verifying_since: Option<Instant>,
+    // The input box is shaking, since a failed attempt at this time
+    pub shaking_since: Option<Instant>,
+    // Whether running on battery, and when that was last checked
+    pub on_battery: Option<(bool, Instant)>,
+    // Output showing the lock screen, the others are blanked in power save mode
+    pub active_output: usize,
+    // Transparent cursor image, used to hide the cursor if configured
+    pub cursor_surface: Option<wl_surface::WlSurface>,
+    pub cursor_buffer: Option<NLockBuffer>,
+    pub background_images: NLockBackgroundImages,
 }
 
 impl NLockState {
@@ -77,26 +130,51 @@ impl NLockState {
             running: Arc::new(AtomicBool::new(true)),
             locked: false,
             unlocked: false,
+            lock_lost: false,
+            relocks: 0,
             state_changed: Arc::new(AtomicBool::new(false)),
             display,
             registry: None,
+            globals: Vec::new(),
             compositor: None,
             subcompositor: None,
             shm: None,
-            r_seat: None,
+            shm_formats: Vec::new(),
+            bg_format: wl_shm::Format::Argb8888,
             session_lock_manager: None,
             session_lock: None,
             surfaces: Vec::new(),
-            seat: NLockSeat::default(),
-            xkb: NLockXkb::default(),
+            seats: Vec::new(),
+            key_repeat: NLockKeyRepeat::default(),
             password: Zeroizing::new("".to_string()),
+            submitted_password: Zeroizing::new("".to_string()),
             poll: None,
-            timers: Vec::new(),
+            timers: None,
             auth_comm,
+            auth_thread: None,
+            auth_restarts: 0,
             auth_state: Arc::new(AtomicAuthState::new(AuthState::Idle)),
-            background_image: None,
+            failed_attempts: 0,
+            suspended_time: None,
+            caret_visible: true,
+            show_until: None,
+            unlock_pending: false,
+            verifying_since: None,
+            shaking_since: None,
+            on_battery: None,
+            active_output: 0,
+            cursor_surface: None,
+            cursor_buffer: None,
+            background_images: NLockBackgroundImages::default(),
         };
 
+        if s.config.font.family.is_empty() {
+            s.config.font.family = resolve_default_font_family();
+        } else {
+            s.config.font.family =
+                resolve_configured_font_family(&s.config.font.family, &s.config.font.fallback);
+        }
+
         if let Err(e) = s.try_load_background_image() {
             bail!(
                 "Failed to load background image: {}: {}",
@@ -118,6 +196,128 @@ impl NLockState {
             let session_lock = session_lock_manager.lock(qh, ());
             self.session_lock = Some(session_lock);
         }
+
+        // Outputs already described can have lock surfaces created right away
+        self.create_surfaces(qh);
+    }
+
+    /// Create lock surfaces for all outputs which have been fully described,
+    /// once the session lock exists.
+    pub fn create_surfaces(&mut self, qh: &QueueHandle<Self>) {
+        self.update_output_modes();
+
+        if let (Some(compositor), Some(subcompositor), Some(session_lock)) =
+            (&self.compositor, &self.subcompositor, &self.session_lock)
+        {
+            for surface in self.surfaces.iter_mut() {
+                if !surface.output_done {
+                    continue;
+                }
+
+                surface.create_surface(
+                    compositor,
+                    subcompositor,
+                    session_lock,
+                    self.bg_format,
+                    &self.config.general.outputs,
+                    qh,
+                );
+
+                // Ready to commit as soon as the first configure arrives
+                if self.config.general.prerender
+                    && let Some(shm) = &self.shm
+                {
+                    surface.prerender_background(&self.config, &self.background_images, shm, qh);
+                }
+            }
+        }
+    }
+
+    /// Set what each output shows. The first output is the primary one, or
+    /// in power save mode, the output last given input. The others show
+    /// whatever the secondary mode asks for, or nothing in power save mode.
+    fn update_output_modes(&mut self) {
+        let power_save = self.config.general.power_save;
+        let primary = if power_save { self.active_output } else { 0 };
+
+        for (i, surface) in self.surfaces.iter_mut().enumerate() {
+            surface.set_mode(if i == primary {
+                SecondaryMode::Full
+            } else if power_save {
+                SecondaryMode::Blank
+            } else {
+                self.config.general.secondary_mode
+            });
+        }
+    }
+
+    /// Move the lock screen to the output owning `surface`, in power save
+    /// mode, after pointer or keyboard input arrives there.
+    pub fn activate_output(&mut self, surface: &wl_surface::WlSurface) {
+        if !self.config.general.power_save {
+            return;
+        }
+
+        let Some(index) = self
+            .surfaces
+            .iter()
+            .position(|s| s.bg_surface.as_ref() == Some(surface))
+        else {
+            return;
+        };
+
+        if index != self.active_output {
+            debug!("Power save: moving the lock screen to output {index}");
+            self.active_output = index;
+            self.update_output_modes();
+            self.state_changed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Check whether every lock surface has rendered its background
+    pub fn surfaces_ready(&self) -> bool {
+        self.surfaces.iter().all(|s| s.bg_rendered)
+    }
+
+    /// Check whether every lock surface has committed the success state.
+    /// Surfaces never created, e.g. for unconfigured outputs, have nothing to
+    /// wait for.
+    fn success_rendered(&self) -> bool {
+        self.surfaces
+            .iter()
+            .filter(|s| s.created)
+            .all(|s| s.success_rendered)
+    }
+
+    /// Stop running, to unlock, once every output shows the success state,
+    /// so all outputs unlock together, instead of staggered.
+    ///
+    /// If some output still hasn't caught up after a grace period, unlock
+    /// anyway, rather than leave the user stuck on a correct password.
+    pub fn request_unlock(&mut self) {
+        if !self.config.animation.sync_unlock || self.unlock_pending || self.success_rendered() {
+            self.running.store(false, Ordering::Relaxed);
+            return;
+        }
+
+        debug!("Waiting for all outputs to show the success state before unlocking");
+        self.unlock_pending = true;
+
+        if let Err(e) = self.set_timer(
+            EventType::SuccessDelay as usize,
+            Expiration::OneShot(TimeSpec::from_duration(SYNC_UNLOCK_GRACE)),
+        ) {
+            warn!("Failed to set unlock grace timer: {e}");
+            self.running.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Unlock if waiting on the success state, and every output shows it
+    pub fn check_unlock_pending(&mut self) {
+        if self.unlock_pending && self.success_rendered() {
+            debug!("All outputs show the success state, unlocking");
+            self.running.store(false, Ordering::Relaxed);
+        }
     }
 
     pub fn unlock(&mut self, qh: &QueueHandle<Self>) {
@@ -130,6 +330,13 @@ impl NLockState {
 
             self.surfaces.iter_mut().for_each(|s| s.destroy());
 
+            if let Some(surface) = self.cursor_surface.take() {
+                surface.destroy();
+            }
+            if let Some(mut buffer) = self.cursor_buffer.take() {
+                buffer.destroy();
+            }
+
             self.display.sync(qh, ());
             self.session_lock = None;
             self.locked = false;
@@ -141,49 +348,463 @@ impl NLockState {
         }
     }
 
+    /// Handle the compositor ending the session lock without it being unlocked
+    /// by nlock, either by denying the lock or by ending it later on.
+    pub fn lock_finished(&mut self, qh: &QueueHandle<Self>) {
+        // A finished lock must be destroyed, not unlocked
+        if let Some(session_lock) = self.session_lock.take() {
+            session_lock.destroy();
+        }
+
+        self.locked = false;
+        self.surfaces.iter_mut().for_each(|s| s.destroy_surface());
+
+        if self.relocks < self.config.general.relock_limit {
+            self.relocks += 1;
+            warn!(
+                "Session lock finished by compositor, locking again ({}/{})",
+                self.relocks, self.config.general.relock_limit
+            );
+
+            self.lock(qh);
+            return;
+        }
+
+        error!("Session lock finished by compositor, the session may not be locked");
+
+        self.lock_lost = true;
+        self.clear_password();
+        self.running.store(false, Ordering::Relaxed);
+    }
+
+    /// Recover after the system resumes from suspend. Committed buffers may be
+    /// stale by now, so all surfaces are rendered again in full.
+    pub fn handle_resume(&mut self) {
+        debug!("Resumed from suspend, rendering surfaces again");
+
+        // A held key was most likely released while suspended
+        if let Err(e) = self.cancel_key_repeat() {
+            warn!("Failed to cancel key repeat: {e}");
+        }
+
+        self.surfaces.iter_mut().for_each(|s| s.invalidate());
+        self.state_changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Render the background and overlay of every surface again from scratch
+    pub fn force_redraw(&mut self) {
+        debug!("Forcing redraw of all surfaces");
+
+        self.surfaces.iter_mut().for_each(|s| s.invalidate());
+        self.state_changed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn set_input_woken(&mut self, woken: bool) {
+        self.surfaces
+            .iter_mut()
+            .for_each(|s| s.set_input_woken(woken));
+        self.state_changed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn set_key_highlight(&mut self, highlight: Option<KeyHighlight>) {
+        self.surfaces
+            .iter_mut()
+            .for_each(|s| s.set_key_highlight(highlight));
+        self.state_changed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn set_caret_visible(&mut self, visible: bool) {
+        if self.caret_visible != visible {
+            self.caret_visible = visible;
+            self.surfaces
+                .iter_mut()
+                .for_each(|s| s.set_caret_visible(visible));
+            self.state_changed.store(true, Ordering::Relaxed);
+        }
+    }
+
     pub fn clear_password(&mut self) {
-        self.password.clear();
+        scrub_password(&mut self.password);
+    }
+
+    /// Handle the input after a failed attempt, either clearing it, or
+    /// restoring the submitted password for editing if nothing was typed since
+    pub fn reset_password_after_fail(&mut self) {
+        if self.config.input.clear_on_fail {
+            self.clear_password();
+        } else if self.password.is_empty() {
+            self.password.push_str(&self.submitted_password);
+        }
+
+        scrub_password(&mut self.submitted_password);
+    }
+
+    /// Check whether input should no longer be accepted, either because too
+    /// many failed attempts have been made, the authenticator is unavailable,
+    /// or authentication succeeded, and the session is about to be unlocked.
+    pub fn is_locked_out(&self) -> bool {
+        matches!(
+            self.auth_state.load(Ordering::Relaxed),
+            AuthState::Success | AuthState::LockedOut | AuthState::Unavailable
+        )
+    }
+
+    /// Check the authenticator thread is still running, restarting it if it
+    /// has exited, up to the configured restart limit.
+    ///
+    /// Returns `false` if the authenticator is unavailable.
+    fn ensure_auth_thread(&mut self) -> bool {
+        let finished = self
+            .auth_thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished());
+
+        if !finished {
+            return true;
+        }
+
+        if self.auth_restarts >= self.config.general.auth_restart_limit {
+            error!("Auth thread is not running, authentication unavailable");
+            return false;
+        }
+
+        self.auth_restarts += 1;
+        warn!(
+            "Auth thread is not running, restarting ({}/{})",
+            self.auth_restarts, self.config.general.auth_restart_limit
+        );
+
+        self.auth_thread = Some(spawn_auth_thread(
+            AuthConfig::new(&self.config),
+            self.auth_comm.clone(),
+            self.failed_attempts,
+        ));
+
+        true
     }
 
     /// Write the current password into the auth channel and clear it
     pub fn submit_password(&mut self) {
+        // PAM modules may truncate, or reject, long passwords in ways that
+        // look like an ordinary failure, so refuse them here instead
+        let max_length = self.config.input.pam_max_length;
+        if exceeds_pam_length(&self.password, max_length) {
+            warn!("Password longer than {max_length} bytes, not submitting");
+            self.auth_state.store(AuthState::TooLong, Ordering::Relaxed);
+            self.state_changed.store(true, Ordering::Relaxed);
+            self.clear_password();
+            return;
+        }
+
+        // Not a security check, a password known to be too short would only
+        // fail, so save the round trip, and the failed attempt
+        let min_length = self.config.input.min_submit_length;
+        if min_length > 0 && self.password.chars().count() < min_length {
+            debug!("Password shorter than {min_length} characters, not submitting");
+            self.auth_state
+                .store(AuthState::TooShort, Ordering::Relaxed);
+            self.state_changed.store(true, Ordering::Relaxed);
+            self.clear_password();
+            return;
+        }
+
+        if !self.ensure_auth_thread() {
+            // Keep the session locked, but make the failure visible
+            self.auth_state
+                .store(AuthState::Unavailable, Ordering::Relaxed);
+            self.state_changed.store(true, Ordering::Relaxed);
+            self.clear_password();
+            return;
+        }
+
         if let Err(e) = self.auth_comm.request.write(self.password.to_string()) {
             warn!("Failed to write auth request: {e}");
+        } else {
+            scrub_password(&mut self.submitted_password);
+            if !self.config.input.clear_on_fail {
+                self.submitted_password.push_str(&self.password);
+            }
+
+            self.auth_state
+                .store(AuthState::Verifying, Ordering::Relaxed);
+            self.state_changed.store(true, Ordering::Relaxed);
+            self.stop_shake();
+            self.start_spinner();
         }
 
         self.clear_password();
     }
 
-    fn try_load_background_image(&mut self) -> Result<()> {
-        if self.config.general.bg_type == BackgroundType::Color {
-            self.config.general.bg_type = BackgroundType::Color;
-            return Ok(());
+    /// Time between animation frames, at the battery frame rate if set, and
+    /// running on battery. The power source is only checked again once the
+    /// last check is a while old, not for every animation.
+    fn animation_frame_interval(&mut self) -> Duration {
+        let animation = &self.config.animation;
+        let mut fps = animation.fps;
+
+        if animation.fps_on_battery > 0 {
+            let battery = match self.on_battery {
+                Some((battery, checked)) if checked.elapsed() < POWER_SOURCE_CHECK_INTERVAL => {
+                    battery
+                }
+                _ => {
+                    let battery = on_battery();
+                    debug!("Power source checked, on battery: {battery}");
+                    self.on_battery = Some((battery, Instant::now()));
+                    battery
+                }
+            };
+
+            if battery {
+                fps = animation.fps_on_battery;
+            }
         }
 
-        let mut image_file = File::open(&self.config.image.path)?;
-        let is_png = detect_png(&mut image_file)?;
-        image_file.rewind()?;
+        Duration::from_secs(1) / fps.max(1)
+    }
 
-        // if it's a PNG, Cairo can handle it directly, pixbuf conversion is expensive
-        if is_png {
-            let image_surface = ImageSurface::create_from_png(&mut image_file)?;
-            self.background_image = Some(image_surface);
-        } else {
-            let pixbuf = Pixbuf::from_read(image_file)?;
-            let pixbuf = pixbuf
-                .apply_embedded_orientation()
-                .ok_or(anyhow!("Failed to apply embedded image orientation"))?;
+    /// Start animating the spinner, if enabled, until a response arrives
+    fn start_spinner(&mut self) {
+        if !self.config.spinner.enabled || self.verifying_since.is_some() {
+            return;
+        }
+
+        let interval = self.animation_frame_interval();
+        if let Err(e) = self.set_timer(
+            EventType::Animation as usize,
+            Expiration::Interval(TimeSpec::from_duration(interval)),
+        ) {
+            warn!("Failed to set spinner timer: {e}");
+            return;
+        }
+
+        self.verifying_since = Some(Instant::now());
+        self.update_spinner();
+    }
+
+    /// Stop the spinner, disarming its timer so nothing wakes up needlessly
+    pub fn stop_spinner(&mut self) {
+        if self.verifying_since.take().is_none() {
+            return;
+        }
+
+        if let Err(e) = self.unset_timer(EventType::Animation as usize) {
+            warn!("Failed to unset spinner timer: {e}");
+        }
+
+        self.update_spinner();
+    }
+
+    /// Rotation of the spinner, based on how long verifying has taken
+    fn spinner_angle(&self) -> Option<f64> {
+        let since = self.verifying_since?;
+        let period = self.config.spinner.period_ms.max(1) as f64;
+        let elapsed = since.elapsed().as_millis() as f64;
+
+        Some((elapsed % period) / period * std::f64::consts::TAU)
+    }
+
+    /// Show the spinner at its current rotation, or hide it once stopped
+    pub fn update_spinner(&mut self) {
+        let angle = self.spinner_angle();
+        self.surfaces
+            .iter_mut()
+            .for_each(|s| s.set_spinner_angle(angle));
+        self.state_changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Start shaking the input box, if enabled, after a failed attempt. The
+    /// spinner must already be stopped, as both use the animation timer.
+    pub fn start_shake(&mut self) {
+        if !self.config.animation.shake_on_fail || self.shaking_since.is_some() {
+            return;
+        }
+
+        let interval = self.animation_frame_interval();
+        if let Err(e) = self.set_timer(
+            EventType::Animation as usize,
+            Expiration::Interval(TimeSpec::from_duration(interval)),
+        ) {
+            warn!("Failed to set shake timer: {e}");
+            return;
+        }
+
+        self.shaking_since = Some(Instant::now());
+        self.update_shake();
+    }
+
+    /// Stop shaking, and put the input box back in place
+    pub fn stop_shake(&mut self) {
+        if self.shaking_since.take().is_none() {
+            return;
+        }
+
+        if let Err(e) = self.unset_timer(EventType::Animation as usize) {
+            warn!("Failed to unset shake timer: {e}");
+        }
+
+        self.update_shake();
+    }
+
+    /// Offset of the input box, a sine wave that decays to nothing over the
+    /// shake duration
+    fn shake_offset(&self) -> f64 {
+        let Some(since) = self.shaking_since else {
+            return 0.0;
+        };
+
+        let progress = since.elapsed().as_secs_f64() / SHAKE_DURATION.as_secs_f64();
+        if progress >= 1.0 {
+            return 0.0;
+        }
+
+        (progress * SHAKE_SWINGS * std::f64::consts::TAU).sin() * (1.0 - progress)
+    }
+
+    /// Move the input box to its current shake offset, stopping once the
+    /// shake is over
+    pub fn update_shake(&mut self) {
+        if let Some(since) = self.shaking_since
+            && since.elapsed() >= SHAKE_DURATION
+        {
+            self.stop_shake();
+            return;
+        }
+
+        let offset = self.shake_offset();
+        self.surfaces
+            .iter_mut()
+            .for_each(|s| s.set_shake_offset(offset));
+        self.state_changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Check whether the rendered background covers every pixel with full
+    /// opacity, making it safe to use a format without an alpha channel.
+    fn background_is_opaque(&self) -> bool {
+        match self.config.general.bg_type {
+            BackgroundType::Color => self.config.colors.bg.a >= 1.0,
+            BackgroundType::None => true,
+            BackgroundType::Image => {
+                let covers_surface = matches!(
+                    self.config.image.scale,
+                    BackgroundImageScale::Stretch
+                        | BackgroundImageScale::Fill
+                        | BackgroundImageScale::Tile
+                );
+
+                // An opaque placeholder is painted beneath the whole image
+                let placeholder_opaque = self.config.image.placeholder_color.a >= 1.0;
+
+                placeholder_opaque
+                    || (covers_surface
+                        && self
+                            .background_images
+                            .image
+                            .as_ref()
+                            .is_some_and(|image| image.format() == cairo::Format::Rgb24))
+            }
+        }
+    }
+
+    /// Choose the buffer format for background surfaces, based on the
+    /// configured preference and the formats advertised by the compositor.
+    pub fn select_background_format(&mut self) {
+        debug!("Compositor supports buffer formats {:?}", self.shm_formats);
 
-            let image_surface = ImageSurface::create_from_pixbuf(&pixbuf)?;
-            self.background_image = Some(image_surface);
+        self.bg_format = Self::choose_background_format(
+            self.config.general.bg_format,
+            self.background_is_opaque(),
+            &self.shm_formats,
+        );
+
+        debug!("Using background buffer format {:?}", self.bg_format);
+    }
+
+    /// Pick the buffer format for a background, falling back to Argb8888,
+    /// which every compositor must support, whenever the preferred format
+    /// can't be used.
+    ///
+    /// Deeper formats, like Xrgb2101010, aren't picked even if supported,
+    /// backgrounds are drawn from 8-bit colors and images, so they would
+    /// only cost more memory.
+    fn choose_background_format(
+        preferred: BufferFormat,
+        opaque: bool,
+        supported: &[wl_shm::Format],
+    ) -> wl_shm::Format {
+        let xrgb_supported = supported.contains(&wl_shm::Format::Xrgb8888);
+
+        match preferred {
+            BufferFormat::Argb => wl_shm::Format::Argb8888,
+            BufferFormat::Xrgb if !opaque => {
+                debug!(
+                    "Background is not opaque, using {:?}",
+                    wl_shm::Format::Argb8888
+                );
+                wl_shm::Format::Argb8888
+            }
+            BufferFormat::Xrgb if !xrgb_supported => {
+                warn!(
+                    "Buffer format {:?} not supported by compositor, using {:?}",
+                    wl_shm::Format::Xrgb8888,
+                    wl_shm::Format::Argb8888
+                );
+                wl_shm::Format::Argb8888
+            }
+            BufferFormat::Xrgb => wl_shm::Format::Xrgb8888,
+            // Without an alpha channel, the compositor can skip blending
+            BufferFormat::Auto if opaque && xrgb_supported => wl_shm::Format::Xrgb8888,
+            BufferFormat::Auto => wl_shm::Format::Argb8888,
+        }
+    }
+
+    fn try_load_background_image(&mut self) -> Result<()> {
+        if self.config.general.bg_type != BackgroundType::Image {
+            return Ok(());
         }
 
+        self.background_images.image = Some(load_image(&self.config.image.path)?);
         self.config.general.bg_type = BackgroundType::Image;
 
+        // Layers are decoration, the background still works without them
+        for layer in &self.config.image.layers {
+            match load_image(&layer.path) {
+                Ok(image) => self.background_images.layers.push(NLockImageLayer {
+                    image,
+                    scale: layer.scale,
+                    anchor: layer.anchor,
+                    opacity: layer.opacity,
+                }),
+                Err(e) => warn!(
+                    "Failed to load background image layer: {}: {e}",
+                    layer.path.display()
+                ),
+            }
+        }
+
         Ok(())
     }
 }
 
+/// Load an image file into a Cairo surface, applying any orientation it has
+fn load_image(path: &Path) -> Result<ImageSurface> {
+    let mut image_file = File::open(path)?;
+    let is_png = detect_png(&mut image_file)?;
+    image_file.rewind()?;
+
+    // if it's a PNG, Cairo can handle it directly, pixbuf conversion is expensive
+    if is_png {
+        Ok(ImageSurface::create_from_png(&mut image_file)?)
+    } else {
+        let pixbuf = Pixbuf::from_read(image_file)?;
+        let pixbuf = pixbuf
+            .apply_embedded_orientation()
+            .ok_or(anyhow!("Failed to apply embedded image orientation"))?;
+
+        ImageSurface::create_from_pixbuf(&pixbuf)
+    }
+}
+
 impl Dispatch<wl_registry::WlRegistry, ()> for NLockState {
     fn event(
         state: &mut Self,
@@ -199,6 +820,8 @@ impl Dispatch<wl_registry::WlRegistry, ()> for NLockState {
             version,
         } = event
         {
+            state.globals.push((interface.clone(), version));
+
             match &interface[..] {
                 "wl_compositor" => {
                     let compositor =
@@ -220,14 +843,21 @@ impl Dispatch<wl_registry::WlRegistry, ()> for NLockState {
                 }
                 "wl_seat" => {
                     let seat = registry.bind::<wl_seat::WlSeat, _, _>(name, version, qh, ());
-                    state.r_seat = Some(seat);
+                    state.seats.push(NLockSeat::new(seat));
                 }
                 "wl_output" => {
                     let index = state.surfaces.len();
                     let output =
                         registry.bind::<wl_output::WlOutput, _, _>(name, version, qh, index);
 
-                    let surface = NLockSurface::new(output, index);
+                    let mut surface = NLockSurface::new(output, index);
+                    surface.set_max_dimension(state.config.general.max_buffer_dimension);
+                    surface.set_shm_backend(state.config.general.shm_backend);
+                    surface.set_low_memory(state.config.general.low_memory);
+                    surface.set_caret_visible(state.caret_visible);
+                    surface.set_input_woken(state.show_until.is_some());
+                    surface.set_spinner_angle(state.spinner_angle());
+                    surface.set_shake_offset(state.shake_offset());
                     state.surfaces.push(surface);
                 }
                 "ext_session_lock_manager_v1" => {
@@ -248,7 +878,6 @@ impl Dispatch<wl_registry::WlRegistry, ()> for NLockState {
 
 delegate_noop!(NLockState: ignore wl_compositor::WlCompositor);
 delegate_noop!(NLockState: ignore wl_subcompositor::WlSubcompositor);
-delegate_noop!(NLockState: ignore wl_shm::WlShm);
 delegate_noop!(NLockState: ignore wl_surface::WlSurface);
 delegate_noop!(NLockState: ignore wl_subsurface::WlSubsurface);
 delegate_noop!(NLockState: ignore ext_session_lock_manager_v1::ExtSessionLockManagerV1);
@@ -256,6 +885,24 @@ delegate_noop!(NLockState: ignore wl_callback::WlCallback);
 delegate_noop!(NLockState: ignore wl_shm_pool::WlShmPool);
 delegate_noop!(NLockState: ignore wl_region::WlRegion);
 
+impl Dispatch<wl_shm::WlShm, ()> for NLockState {
+    fn event(
+        state: &mut Self,
+        _: &wl_shm::WlShm,
+        event: <wl_shm::WlShm as wayland_client::Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_shm::Event::Format {
+            format: WEnum::Value(format),
+        } = event
+        {
+            state.shm_formats.push(format);
+        }
+    }
+}
+
 impl Dispatch<ext_session_lock_v1::ExtSessionLockV1, ()> for NLockState {
     fn event(
         state: &mut Self,
@@ -272,7 +919,9 @@ impl Dispatch<ext_session_lock_v1::ExtSessionLockV1, ()> for NLockState {
                 debug!("Session is locked");
             }
             ext_session_lock_v1::Event::Finished => {
-                state.unlock(qh);
+                // A lock which was unlocked is destroyed at the same time, so
+                // this is never received after a successful authentication.
+                state.lock_finished(qh);
             }
             _ => {}
         }
@@ -295,12 +944,23 @@ impl Dispatch<wl_output::WlOutput, usize> for NLockState {
                 physical_width,
                 physical_height,
                 subpixel,
-                make: _,
-                model: _,
-                transform: _,
+                make,
+                model,
+                transform,
             } => {
+                let make_model = format!("{make} {model}").trim().to_string();
+                if !make_model.is_empty() {
+                    state.surfaces[*data].output_make_model = Some(make_model);
+                }
+
                 state.surfaces[*data]
                     .set_subpixel_order(cairo::SubpixelOrder::from_wl_subpixel(subpixel));
+                state.surfaces[*data]
+                    .set_subpixel_none(matches!(subpixel, WEnum::Value(wl_output::Subpixel::None)));
+
+                if let WEnum::Value(transform) = transform {
+                    state.surfaces[*data].set_transform(transform);
+                }
 
                 if let Err(e) =
                     state.surfaces[*data].set_physical_dimensions(physical_width, physical_height)
@@ -308,14 +968,25 @@ impl Dispatch<wl_output::WlOutput, usize> for NLockState {
                     warn!("Failed to set output physical dimensions: {e}");
                 }
             }
+            wl_output::Event::Mode {
+                flags: WEnum::Value(flags),
+                width,
+                height,
+                refresh: _,
+            } if flags.contains(wl_output::Mode::Current) => {
+                if let Err(e) = state.surfaces[*data].set_current_mode(width, height) {
+                    warn!("Failed to set output mode: {e}");
+                }
+            }
             wl_output::Event::Name { name } => {
                 debug!("Found output '{name}'");
                 state.surfaces[*data].output_name = Some(name);
             }
-            wl_output::Event::Scale { factor } => {
-                if let Err(e) = state.surfaces[*data].set_scale(factor) {
-                    warn!("Failed to set output scale: {e}");
-                } else {
+            wl_output::Event::Description { description } => {
+                state.surfaces[*data].output_description = Some(description);
+            }
+            wl_output::Event::Scale { factor } => match state.surfaces[*data].set_scale(factor) {
+                Ok(changed) => {
                     debug!(
                         "Set output scale for '{}' to {factor}",
                         state.surfaces[*data]
@@ -323,21 +994,62 @@ impl Dispatch<wl_output::WlOutput, usize> for NLockState {
                             .as_ref()
                             .unwrap_or(&"".to_string())
                     );
+
+                    // Scale changed while locked, take effect now rather than
+                    // on the next configure
+                    if changed && state.surfaces[*data].created {
+                        state.state_changed.store(true, Ordering::Relaxed);
+                    }
                 }
-            }
+                Err(e) => warn!("Failed to set output scale: {e}"),
+            },
             wl_output::Event::Done => {
-                if let (Some(compositor), Some(subcompositor), Some(session_lock)) =
-                    (&state.compositor, &state.subcompositor, &state.session_lock)
-                {
-                    state.surfaces[*data].create_surface(
-                        compositor,
-                        subcompositor,
-                        session_lock,
-                        qh,
-                    );
-                }
+                state.surfaces[*data].output_done = true;
+                state.create_surfaces(qh);
             }
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOTH: &[wl_shm::Format] = &[wl_shm::Format::Argb8888, wl_shm::Format::Xrgb8888];
+    const ARGB_ONLY: &[wl_shm::Format] = &[wl_shm::Format::Argb8888];
+    const NONE: &[wl_shm::Format] = &[];
+
+    #[test]
+    fn choose_background_format_table() {
+        let cases = [
+            // preferred, opaque, supported, expected
+            (BufferFormat::Auto, true, BOTH, wl_shm::Format::Xrgb8888),
+            (BufferFormat::Auto, false, BOTH, wl_shm::Format::Argb8888),
+            (
+                BufferFormat::Auto,
+                true,
+                ARGB_ONLY,
+                wl_shm::Format::Argb8888,
+            ),
+            (BufferFormat::Xrgb, true, BOTH, wl_shm::Format::Xrgb8888),
+            (BufferFormat::Xrgb, false, BOTH, wl_shm::Format::Argb8888),
+            (
+                BufferFormat::Xrgb,
+                true,
+                ARGB_ONLY,
+                wl_shm::Format::Argb8888,
+            ),
+            (BufferFormat::Xrgb, true, NONE, wl_shm::Format::Argb8888),
+            (BufferFormat::Argb, true, BOTH, wl_shm::Format::Argb8888),
+        ];
+
+        for (preferred, opaque, supported, expected) in cases {
+            assert_eq!(
+                NLockState::choose_background_format(preferred, opaque, supported),
+                expected,
+                "{preferred:?}, opaque {opaque}, supported {supported:?}"
+            );
+        }
+    }
+}
@@ -4,7 +4,7 @@
 use std::sync::atomic::Ordering;
 
 use anyhow::{Result, anyhow, bail};
-use tracing::{debug, trace, warn};
+use tracing::{debug, error, trace, trace_span, warn};
 use wayland_client::{
     Dispatch, QueueHandle,
     protocol::{wl_compositor, wl_output, wl_shm, wl_subcompositor, wl_subsurface, wl_surface},
@@ -15,31 +15,72 @@ use wayland_protocols::ext::session_lock::v1::client::{
 
 use crate::{
     auth::AuthState,
-    buffer::NLockBuffer,
-    config::NLockConfig,
-    render::{DEFAULT_DPI, NLockRenderBackgroundArgs, NLockRenderOverlayArgs, NLockRenderer},
+    buffer::{NLockBuffer, NLockShmPool},
+    config::{NLockConfig, NLockConfigOutputs},
+    render::{
+        DEFAULT_DPI, KeyHighlight, NLockBackgroundImages, NLockRenderBackgroundArgs,
+        NLockRenderOverlayArgs, NLockRenderer,
+    },
     state::NLockState,
+    util::{SecondaryMode, ShmBackend},
 };
 
+/// Shortest side, in buffer pixels, below which fixed-size elements shrink
+const SMALL_SURFACE_SIZE: f64 = 720.0;
+
+/// Buffers a surface may hold at its current size in low memory mode, besides
+/// the background. The compositor may hold on to an attached buffer until
+/// another is attached, so the overlay needs two to always make progress.
+const LOW_MEMORY_MAX_BUFFERS: usize = 2;
+
+/// Overlay buffers committed and never released, after which the overlay
+/// subsurface is assumed not to be presented at all
+const OVERLAY_STUCK_COMMITS: usize = 4;
+
 pub struct NLockSurface {
     pub created: bool,
+    // All output information has been received
+    pub output_done: bool,
     // Background rendering is expensive, only do it once.
     pub bg_rendered: bool,
+    // The success state has been committed, used to unlock all outputs together
+    pub success_rendered: bool,
+    // A render was skipped to wait for a buffer release, in low memory mode
+    pub render_deferred: bool,
     pub index: usize,
     pub output_name: Option<String>,
+    pub output_description: Option<String>,
+    // Make and model, space separated, stable where names may not be
+    pub output_make_model: Option<String>,
+    // Output only shows a plain color, not the full lock screen
+    pub excluded: bool,
+    // What this output shows, secondary outputs may show less
+    mode: SecondaryMode,
 
     output_scale: i32,
+    // Largest buffer width or height allowed, in buffer pixels
+    max_dimension: u32,
+    shm_backend: ShmBackend,
+    // Wait for buffers to be released, rather than allocating more
+    low_memory: bool,
     width: Option<u32>,
     height: Option<u32>,
     last_width: Option<u32>,
     last_height: Option<u32>,
+    last_overlay_damage: Option<Vec<cairo::RectangleInt>>,
     physical_width: Option<i32>,
     physical_height: Option<i32>,
+    // Current output mode, in native pixels, before the output transform
+    mode_width: Option<i32>,
+    mode_height: Option<i32>,
+    // Output is rotated by 90 or 270 degrees
+    transposed: bool,
 
     dpi: Option<f64>,
     subpixel: Option<cairo::SubpixelOrder>,
 
     renderer: NLockRenderer,
+    bg_format: wl_shm::Format,
 
     pub ov_surface: Option<wl_surface::WlSurface>,
     pub bg_surface: Option<wl_surface::WlSurface>,
@@ -47,24 +88,53 @@ pub struct NLockSurface {
     pub output: wl_output::WlOutput,
     pub lock_surface: Option<ext_session_lock_surface_v1::ExtSessionLockSurfaceV1>,
     pub buffers: Vec<NLockBuffer>,
+    // Backs every buffer in `buffers`, created with the first one
+    shm_pool: Option<NLockShmPool>,
+    // Buffer holding a background rendered before the first configure
+    prerendered_bg: Option<usize>,
+    // Buffer last committed to the background surface
+    bg_buffer: Option<usize>,
+    // Overlay buffers committed, and not yet released by the compositor
+    overlay_held: Vec<usize>,
+    // The overlay is drawn onto the background surface, as the compositor
+    // doesn't present the subsurface
+    composited: bool,
+    // Buffer holding the plain background, drawn under the overlay when
+    // composited
+    composite_bg: Option<usize>,
 }
 
 impl NLockSurface {
     pub fn new(output: wl_output::WlOutput, index: usize) -> Self {
         Self {
             created: false,
+            output_done: false,
             bg_rendered: false,
+            success_rendered: false,
+            render_deferred: false,
             index,
             output_name: None,
+            output_description: None,
+            output_make_model: None,
+            excluded: false,
+            mode: SecondaryMode::Full,
             output_scale: 1,
+            max_dimension: u32::MAX,
+            shm_backend: ShmBackend::Memfd,
+            low_memory: false,
             width: None,
             height: None,
             last_width: None,
             last_height: None,
+            last_overlay_damage: None,
             physical_width: None,
             physical_height: None,
+            mode_width: None,
+            mode_height: None,
+            transposed: false,
             dpi: None,
             renderer: NLockRenderer::default(),
+            bg_format: wl_shm::Format::Argb8888,
             subpixel: None,
             ov_surface: None,
             bg_surface: None,
@@ -72,6 +142,12 @@ impl NLockSurface {
             output,
             lock_surface: None,
             buffers: Vec::new(),
+            shm_pool: None,
+            prerendered_bg: None,
+            bg_buffer: None,
+            overlay_held: Vec::new(),
+            composited: false,
+            composite_bg: None,
         }
     }
 
@@ -87,23 +163,96 @@ impl NLockSurface {
             bail!("Surface dimensions invalid: {}x{}", width, height);
         }
 
-        if self.output_scale <= 0 {
-            bail!("Output scale {} is invalid", self.output_scale);
+        let scale = self.buffer_scale();
+        if scale <= 0 {
+            bail!("Buffer scale {} is invalid", scale);
         }
 
-        let width = width * self.output_scale as u32;
-        let height = height * self.output_scale as u32;
+        let (Some(width), Some(height)) = (
+            width.checked_mul(scale as u32),
+            height.checked_mul(scale as u32),
+        ) else {
+            bail!("Scaled surface dimensions overflow");
+        };
 
         Ok((width.into(), height.into()))
     }
 
+    /// Scale buffers are rendered at. This is the output scale, unless that
+    /// makes buffers larger than the maximum dimension, in which case the
+    /// largest scale that fits is used, and the compositor scales it up.
+    fn buffer_scale(&self) -> i32 {
+        let (Some(width), Some(height)) = (self.width, self.height) else {
+            return self.output_scale;
+        };
+
+        fitting_scale(width, height, self.output_scale, self.max_dimension).unwrap_or(1)
+    }
+
+    pub fn set_max_dimension(&mut self, max: u32) {
+        self.max_dimension = max;
+    }
+
+    pub fn set_shm_backend(&mut self, backend: ShmBackend) {
+        self.shm_backend = backend;
+    }
+
+    pub fn set_low_memory(&mut self, low_memory: bool) {
+        self.low_memory = low_memory;
+    }
+
+    /// Set what this output shows, rendering the background again if it
+    /// changed, as the primary output may have gone away.
+    pub fn set_mode(&mut self, mode: SecondaryMode) {
+        if self.mode == mode {
+            return;
+        }
+
+        // The overlay isn't rendered again outside full mode, so its last
+        // buffer, input box and all, would stay on screen. The subsurface is
+        // synchronized, so this shows along with the next background.
+        if self.mode == SecondaryMode::Full
+            && let Some(surface) = &self.ov_surface
+        {
+            surface.attach(None, 0, 0);
+            surface.commit();
+            self.overlay_held.clear();
+        }
+
+        self.mode = mode;
+        self.invalidate();
+    }
+
     pub fn set_raw_dimensions(&mut self, width: u32, height: u32) -> Result<()> {
         if width == 0 || height == 0 {
             bail!("Surface dimensions invalid: {}x{}", width, height);
         }
 
+        // A buffer must match the configured size at its scale, so one too
+        // large even at scale 1 can't be clamped. The surface is left without
+        // a buffer instead, the session stays locked either way.
+        let Some(scale) = fitting_scale(width, height, self.output_scale, self.max_dimension)
+        else {
+            self.width = None;
+            self.height = None;
+            bail!(
+                "Surface dimensions {}x{} exceed the maximum of {}",
+                width,
+                height,
+                self.max_dimension
+            );
+        };
+
+        if scale != self.output_scale {
+            warn!(
+                "Surface dimensions {}x{} at scale {} exceed the maximum of {}, rendering at scale {}",
+                width, height, self.output_scale, self.max_dimension, scale
+            );
+        }
+
         self.width = Some(width);
         self.height = Some(height);
+        self.renderer.set_scale(scale as f64);
 
         Ok(())
     }
@@ -156,15 +305,130 @@ impl NLockSurface {
         self.renderer.set_subpixel_order(order);
     }
 
-    pub fn set_scale(&mut self, scale: i32) -> Result<()> {
+    /// Set whether the output declares it has no subpixels, e.g. it isn't an
+    /// LCD, in which case subpixel anti-aliasing is never used.
+    pub fn set_subpixel_none(&mut self, none: bool) {
+        self.renderer.set_subpixel_none(none);
+    }
+
+    pub fn set_caret_visible(&mut self, visible: bool) {
+        self.renderer.set_caret_visible(visible);
+    }
+
+    pub fn set_input_woken(&mut self, woken: bool) {
+        self.renderer.set_input_woken(woken);
+    }
+
+    pub fn set_key_highlight(&mut self, highlight: Option<KeyHighlight>) {
+        self.renderer.set_key_highlight(highlight);
+    }
+
+    pub fn set_spinner_angle(&mut self, angle: Option<f64>) {
+        self.renderer.set_spinner_angle(angle);
+    }
+
+    pub fn set_shake_offset(&mut self, offset: f64) {
+        self.renderer.set_shake_offset(offset);
+    }
+
+    pub fn set_transform(&mut self, transform: wl_output::Transform) {
+        self.transposed = matches!(
+            transform,
+            wl_output::Transform::_90
+                | wl_output::Transform::_270
+                | wl_output::Transform::Flipped90
+                | wl_output::Transform::Flipped270
+        );
+        self.renderer.set_transform(transform);
+    }
+
+    pub fn set_current_mode(&mut self, width: i32, height: i32) -> Result<()> {
+        if width <= 0 || height <= 0 {
+            bail!("Output mode invalid: {}x{}", width, height);
+        }
+
+        self.mode_width = Some(width);
+        self.mode_height = Some(height);
+
+        Ok(())
+    }
+
+    /// Describe what was worked out for this output, for the debug overlay.
+    /// Only output details go in here, never anything typed.
+    fn debug_info(&self) -> String {
+        let size = |width: Option<u32>, height: Option<u32>| match (width, height) {
+            (Some(width), Some(height)) => format!("{width}x{height}"),
+            _ => "unknown".to_string(),
+        };
+        let (buf_width, buf_height) = self
+            .get_dimensions::<u32>()
+            .map_or((None, None), |(width, height)| (Some(width), Some(height)));
+
+        format!(
+            "output   {}\nlogical  {}\nbuffer   {}\nscale    {}\ndpi      {}\nsubpixel {}",
+            self.output_name.as_deref().unwrap_or("unknown"),
+            size(self.width, self.height),
+            size(buf_width, buf_height),
+            self.output_scale,
+            self.dpi
+                .map_or("unknown".to_string(), |dpi| format!("{dpi:.1}")),
+            self.subpixel
+                .map_or("unknown".to_string(), |order| format!("{order:?}")),
+        )
+    }
+
+    /// Check that the configured size, at the output scale, matches the
+    /// native resolution of the current mode. A mismatch usually means the
+    /// compositor is scaling the lock surface, so text won't be sharp.
+    fn check_mode_dimensions(&self) {
+        let (Ok((width, height)), Some(mode_width), Some(mode_height)) = (
+            self.get_raw_dimensions::<u32>(),
+            self.mode_width,
+            self.mode_height,
+        ) else {
+            return;
+        };
+
+        // Modes are given before the output transform, surfaces after it
+        let (mode_width, mode_height) = if self.transposed {
+            (mode_height, mode_width)
+        } else {
+            (mode_width, mode_height)
+        };
+
+        let scale = self.output_scale as i64;
+        if width as i64 * scale != mode_width as i64 || height as i64 * scale != mode_height as i64
+        {
+            warn!(
+                "Output '{}' configured as {}x{} at scale {}, but its mode is {}x{}",
+                self.output_name.as_deref().unwrap_or(""),
+                width,
+                height,
+                scale,
+                mode_width,
+                mode_height
+            );
+        }
+    }
+
+    /// Set the output scale, returning whether it changed
+    pub fn set_scale(&mut self, scale: i32) -> Result<bool> {
         if scale <= 0 {
             bail!("Invalid scale {}", scale);
         }
 
+        let changed = self.output_scale != scale;
         self.output_scale = scale;
-        self.renderer.set_scale(scale as f64);
+        self.renderer.set_scale(self.buffer_scale() as f64);
 
-        Ok(())
+        if changed {
+            // Worked out again on the next render, along with new buffers
+            // at the new size
+            self.dpi = None;
+            self.invalidate();
+        }
+
+        Ok(changed)
     }
 
     fn update_last_dimensions(&mut self) -> Result<()> {
@@ -180,59 +444,190 @@ impl NLockSurface {
         &mut self,
         width: u32,
         height: u32,
+        format: wl_shm::Format,
         shm: &wl_shm::WlShm,
         qh: &QueueHandle<NLockState>,
     ) -> Option<usize> {
-        let buf = NLockBuffer::new(
-            shm,
-            width as i32,
-            height as i32,
-            wl_shm::Format::Argb8888,
-            qh,
-        )?;
+        // Don't let bogus dimensions allocate an enormous buffer
+        if width > self.max_dimension || height > self.max_dimension {
+            error!(
+                "Refusing to allocate buffer dim. {}x{}, exceeds the maximum of {}",
+                width, height, self.max_dimension
+            );
+            return None;
+        }
+
+        if self.shm_pool.is_none() {
+            // Room for double buffering at this size, grows on demand
+            let size = (width as usize * 4) * height as usize * 2;
+            self.shm_pool = Some(NLockShmPool::new(shm, size, self.shm_backend, qh)?);
+        }
+        let pool = self.shm_pool.as_mut()?;
+
+        let buf = trace_span!("new_buffer", output = self.index, width, height)
+            .in_scope(|| NLockBuffer::new(pool, width as i32, height as i32, format, qh))?;
 
         self.buffers.push(buf);
 
         debug!(
-            "Allocated buffer {} dim. {}x{}",
+            "Allocated buffer {} dim. {}x{} format {:?}",
             self.buffers.len() - 1,
             width,
-            height
+            height,
+            format
         );
 
         Some(self.buffers.len() - 1)
     }
 
+    /// Check whether the surface dimensions changed since the last render
+    fn dimensions_changed(&self) -> bool {
+        let Ok((width, height)) = self.get_dimensions::<u32>() else {
+            return false;
+        };
+
+        matches!(
+            (self.last_width, self.last_height),
+            (Some(last_width), Some(last_height)) if last_width != width || last_height != height
+        )
+    }
+
     fn get_buffer_idx(
         &mut self,
+        format: wl_shm::Format,
         shm: &wl_shm::WlShm,
         qh: &QueueHandle<NLockState>,
     ) -> Option<usize> {
         let (width, height) = self.get_dimensions::<u32>().ok()?;
 
         // The surface size changed, new buffers needed
-        if let Some(last_width) = self.last_width
-            && let Some(last_height) = self.last_height
-            && (last_width != width || last_height != height)
-        {
-            return self.new_buffer(width, height, shm, qh);
+        if self.dimensions_changed() {
+            return self.new_buffer(width, height, format, shm, qh);
         }
 
-        let index = self
-            .buffers
-            .iter()
-            .position(|buf| !buf.state.in_use.load(Ordering::Acquire));
+        // Buffers from before a scale change are the wrong size
+        // A prerendered background is kept for the background alone, as is
+        // the background the overlay is composited onto
+        let index = self.buffers.iter().enumerate().position(|(i, buf)| {
+            Some(i) != self.prerendered_bg
+                && Some(i) != self.composite_bg
+                && buf.format == format
+                && buf.width as u32 == width
+                && buf.height as u32 == height
+                && !buf.state.in_use.load(Ordering::Acquire)
+        });
+
+        // Rather than allocate another buffer, wait for the compositor to
+        // release one, and render again then. Backgrounds aren't counted,
+        // they're held for as long as they're shown.
+        if index.is_none()
+            && self.low_memory
+            && self
+                .buffers
+                .iter()
+                .enumerate()
+                .filter(|&(i, buf)| {
+                    Some(i) != self.bg_buffer
+                        && Some(i) != self.prerendered_bg
+                        && Some(i) != self.composite_bg
+                        && buf.width as u32 == width
+                        && buf.height as u32 == height
+                })
+                .count()
+                >= LOW_MEMORY_MAX_BUFFERS
+        {
+            trace!("No buffer free in low memory mode, deferring render");
+            self.render_deferred = true;
+            return None;
+        }
 
         let idx = match index {
             Some(i) => i,
-            None => self.new_buffer(width, height, shm, qh)?,
+            None => self.new_buffer(width, height, format, shm, qh)?,
         };
 
         Some(idx)
     }
 
-    pub fn calculate_dpi(&mut self) {
-        let dpi = (|| {
+    /// Free buffers left at an old size, or scale, once the compositor has
+    /// released them, so their pool space is reused rather than the pool
+    /// growing with every resize. The pool goes too once nothing uses it.
+    ///
+    /// This shifts buffer indices, so is only done before a render, when
+    /// no indices are held other than those remapped here.
+    fn release_stale_buffers(&mut self) {
+        let Ok((width, height)) = self.get_dimensions::<u32>() else {
+            return;
+        };
+
+        let stale = |i: usize, buf: &NLockBuffer| {
+            (buf.width as u32 != width || buf.height as u32 != height)
+                && Some(i) != self.prerendered_bg
+                && Some(i) != self.composite_bg
+                && !buf.state.in_use.load(Ordering::Acquire)
+        };
+        if !self
+            .buffers
+            .iter()
+            .enumerate()
+            .any(|(i, buf)| stale(i, buf))
+        {
+            return;
+        }
+
+        // New index of each buffer, if kept
+        let mut kept = 0;
+        let remap: Vec<Option<usize>> = self
+            .buffers
+            .iter()
+            .enumerate()
+            .map(|(i, buf)| {
+                (!stale(i, buf)).then(|| {
+                    kept += 1;
+                    kept - 1
+                })
+            })
+            .collect();
+
+        let buffers = std::mem::take(&mut self.buffers);
+        for (mut buf, new_index) in buffers.into_iter().zip(&remap) {
+            if new_index.is_some() {
+                self.buffers.push(buf);
+            } else if let Some(pool) = &mut self.shm_pool {
+                pool.free(&mut buf);
+            }
+        }
+
+        self.prerendered_bg = self.prerendered_bg.and_then(|i| remap[i]);
+        self.bg_buffer = self.bg_buffer.and_then(|i| remap[i]);
+        self.composite_bg = self.composite_bg.and_then(|i| remap[i]);
+        self.overlay_held = self.overlay_held.iter().filter_map(|&i| remap[i]).collect();
+
+        debug!(
+            "Released {} stale buffers for output {}, {} left",
+            remap.len() - self.buffers.len(),
+            self.index,
+            self.buffers.len()
+        );
+
+        // Start over with a pool sized for the current buffers
+        if self.buffers.is_empty()
+            && let Some(mut pool) = self.shm_pool.take()
+        {
+            pool.destroy();
+        }
+    }
+
+    /// Calculate the output DPI from its physical dimensions, falling back to
+    /// `assumed_dpi` if positive, then the default DPI. Virtual and headless
+    /// outputs commonly report 0x0 physical dimensions.
+    ///
+    /// The output scale is applied separately when rendering, so isn't taken
+    /// into account here.
+    pub fn calculate_dpi(&mut self, assumed_dpi: f64) {
+        self.check_mode_dimensions();
+
+        let physical_dpi = (|| {
             let (width, height) = self.get_raw_dimensions::<f64>().ok()?;
             let (phys_width, phys_height) = self.get_physical_dimensions::<f64>().ok()?;
 
@@ -246,29 +641,84 @@ impl NLockSurface {
             );
 
             if dpi.is_finite() { Some(dpi) } else { None }
-        })()
-        .unwrap_or(DEFAULT_DPI);
+        })();
+
+        let (dpi, source) = match physical_dpi {
+            Some(dpi) => (dpi, "physical dimensions"),
+            None if assumed_dpi > 0.0 => (assumed_dpi, "assumed DPI"),
+            None => (DEFAULT_DPI, "default DPI"),
+        };
+
+        debug!(
+            "Using DPI {dpi} from {source} for output '{}'",
+            self.output_name.as_deref().unwrap_or("")
+        );
 
         self.dpi = Some(dpi);
         self.renderer.set_dpi(dpi);
     }
 
+    /// Route all input on this output to the lock surface.
+    ///
+    /// The session lock already gives lock surfaces exclusive input, the
+    /// compositor sends nothing to other clients while locked, whatever the
+    /// input regions. These regions only decide which of nlock's own surfaces
+    /// gets it: the background, which is the lock surface, takes input over
+    /// its whole area, and the overlay takes none, so input under the overlay
+    /// falls through to the background instead of leaving a hole.
+    fn set_input_regions(
+        compositor: &wl_compositor::WlCompositor,
+        bg_surface: &wl_surface::WlSurface,
+        ov_surface: &wl_surface::WlSurface,
+        qh: &QueueHandle<NLockState>,
+    ) {
+        // A `None` input region is infinite, set explicitly rather than
+        // relying on the default
+        bg_surface.set_input_region(None);
+
+        // An empty region, unlike `None`, takes no input at all
+        let region = compositor.create_region(qh, ());
+        ov_surface.set_input_region(Some(&region));
+        region.destroy();
+    }
+
     pub fn create_surface(
         &mut self,
         compositor: &wl_compositor::WlCompositor,
         subcompositor: &wl_subcompositor::WlSubcompositor,
         session_lock: &ext_session_lock_v1::ExtSessionLockV1,
+        bg_format: wl_shm::Format,
+        outputs: &NLockConfigOutputs,
         qh: &QueueHandle<NLockState>,
     ) {
         if !self.created {
+            self.excluded = !outputs.allows(
+                self.output_name.as_deref(),
+                self.output_description.as_deref(),
+                self.output_make_model.as_deref(),
+            );
+            if self.excluded {
+                debug!(
+                    "Output '{}' excluded, showing a plain color",
+                    self.output_name.as_deref().unwrap_or("")
+                );
+            }
+
             let bg_surface = compositor.create_surface(qh, ());
+
+            // An opaque background lets the compositor skip blending
+            self.bg_format = bg_format;
+            if bg_format == wl_shm::Format::Xrgb8888 {
+                let opaque_region = compositor.create_region(qh, ());
+                opaque_region.add(0, 0, i32::MAX, i32::MAX);
+                bg_surface.set_opaque_region(Some(&opaque_region));
+                opaque_region.destroy();
+            }
+
             let ov_surface = compositor.create_surface(qh, ());
             let subsurface = subcompositor.get_subsurface(&ov_surface, &bg_surface, qh, ());
 
-            // Pass all input to the main surface, this feels a bit hacky
-            let region = compositor.create_region(qh, ());
-            region.add(0, 0, 0, 0);
-            ov_surface.set_input_region(Some(&region));
+            Self::set_input_regions(compositor, &bg_surface, &ov_surface, qh);
 
             self.bg_surface = Some(bg_surface);
             self.ov_surface = Some(ov_surface);
@@ -293,22 +743,44 @@ impl NLockSurface {
         &mut self,
         config: &NLockConfig,
         auth_state: AuthState,
-        pwd_len: usize,
-        bg_image: Option<&cairo::ImageSurface>,
+        password: &str,
+        bg_images: &NLockBackgroundImages,
         shm: &wl_shm::WlShm,
         qh: &QueueHandle<NLockState>,
     ) {
         // DPI used in font scaling, uses default if not set, effectively
         // disabling scaling.
         if self.dpi.is_none() && config.font.use_dpi_scaling {
-            self.calculate_dpi();
+            self.calculate_dpi(config.font.assumed_dpi);
         }
 
-        if let Err(e) = self.render_overlay(config, auth_state, pwd_len, shm, qh) {
+        self.renderer
+            .set_size_factor(self.size_factor(config.general.min_surface_scale));
+
+        self.render_deferred = false;
+        self.release_stale_buffers();
+
+        // Excluded outputs, and secondary outputs not showing everything,
+        // have nothing on the overlay
+        let show_overlay = !self.excluded && self.mode == SecondaryMode::Full;
+        let overlay = if show_overlay {
+            self.render_overlay(config, auth_state, password, bg_images, shm, qh)
+        } else {
+            Ok(())
+        };
+
+        if let Err(e) = &overlay {
             warn!("Error while rendering overlay: {e}");
         }
 
-        if let Err(e) = self.render_background(config, bg_image, shm, qh) {
+        // Outputs without an overlay have nothing to show, so count as done
+        self.success_rendered =
+            overlay.is_ok() && !self.render_deferred && matches!(auth_state, AuthState::Success);
+
+        // A composited overlay commits the background surface itself
+        if !(self.composited && show_overlay)
+            && let Err(e) = self.render_background(config, bg_images, shm, qh)
+        {
             warn!("Error while rendering background: {e}");
         }
 
@@ -318,10 +790,106 @@ impl NLockSurface {
         }
     }
 
+    /// Factor to shrink fixed-size elements by, so they don't overwhelm
+    /// small surfaces, like nested compositor windows. Surfaces at least
+    /// `SMALL_SURFACE_SIZE` in both directions are drawn at full size.
+    ///
+    /// This goes by buffer pixels, not logical pixels, so HiDPI outputs,
+    /// e.g. 1920x1080 at scale 2, which is only 960x540 logical, keep full
+    /// sizing like any other monitor.
+    fn size_factor(&self, min_scale: f64) -> f64 {
+        let Ok((width, height)) = self.get_dimensions::<u32>() else {
+            return 1.0;
+        };
+
+        let shortest = width.min(height) as f64;
+        (shortest / SMALL_SURFACE_SIZE).clamp(min_scale.clamp(0.0, 1.0), 1.0)
+    }
+
+    /// Draw the background into buffer `idx`, without committing it
+    fn draw_background(
+        &mut self,
+        idx: usize,
+        config: &NLockConfig,
+        bg_images: &NLockBackgroundImages,
+        buf_width: f64,
+        buf_height: f64,
+    ) -> Result<()> {
+        let context = &self.buffers[idx].context;
+
+        context.save()?;
+        trace_span!(
+            "render_background",
+            output = self.index,
+            buf_width,
+            buf_height
+        )
+        .in_scope(|| {
+            self.renderer.render_background(
+                config,
+                NLockRenderBackgroundArgs {
+                    buf_height,
+                    buf_width,
+                    context,
+                    images: bg_images,
+                    plain: self.excluded || self.mode == SecondaryMode::Blank,
+                },
+            )
+        })?;
+        context.restore()?;
+
+        Ok(())
+    }
+
+    /// Render the background before the first configure, at the size the
+    /// output mode suggests, so it can be committed as soon as the configure
+    /// arrives. Rendered again as usual if the configured size differs.
+    pub fn prerender_background(
+        &mut self,
+        config: &NLockConfig,
+        bg_images: &NLockBackgroundImages,
+        shm: &wl_shm::WlShm,
+        qh: &QueueHandle<NLockState>,
+    ) {
+        if !self.created
+            || self.width.is_some()
+            || self.bg_rendered
+            || self.prerendered_bg.is_some()
+        {
+            return;
+        }
+
+        let (Some(mode_width), Some(mode_height)) = (self.mode_width, self.mode_height) else {
+            return;
+        };
+
+        // Modes are given before the output transform, surfaces after it
+        let (width, height) = if self.transposed {
+            (mode_height as u32, mode_width as u32)
+        } else {
+            (mode_width as u32, mode_height as u32)
+        };
+
+        let Some(idx) = self.new_buffer(width, height, self.bg_format, shm, qh) else {
+            return;
+        };
+
+        if let Err(e) = self.draw_background(idx, config, bg_images, width as f64, height as f64) {
+            warn!("Failed to prerender background: {e}");
+            return;
+        }
+
+        debug!(
+            "Prerendered background for output {} at {}x{}",
+            self.index, width, height
+        );
+        self.prerendered_bg = Some(idx);
+    }
+
     fn render_background(
         &mut self,
         config: &NLockConfig,
-        bg_image: Option<&cairo::ImageSurface>,
+        bg_images: &NLockBackgroundImages,
         shm: &wl_shm::WlShm,
         qh: &QueueHandle<NLockState>,
     ) -> Result<()> {
@@ -336,15 +904,37 @@ impl NLockSurface {
 
         let (buf_width, buf_height) = self.get_dimensions::<f64>()?;
 
-        let idx = match self.get_buffer_idx(shm, qh) {
-            Some(i) => i,
-            None => {
-                bail!("Failed to obtain buffer for rendering background");
+        let idx = match self.prerendered_bg.take() {
+            Some(idx)
+                if self.buffers[idx].width as f64 == buf_width
+                    && self.buffers[idx].height as f64 == buf_height =>
+            {
+                debug!("Using prerendered background for output {}", self.index);
+                idx
+            }
+            prerendered => {
+                if prerendered.is_some() {
+                    debug!(
+                        "Prerendered background for output {} is the wrong size, rendering again",
+                        self.index
+                    );
+                }
+
+                let idx = match self.get_buffer_idx(self.bg_format, shm, qh) {
+                    Some(i) => i,
+                    None if self.render_deferred => return Ok(()),
+                    None => {
+                        bail!("Failed to obtain buffer for rendering background");
+                    }
+                };
+
+                trace!("got buffer index {} for background", idx);
+
+                self.draw_background(idx, config, bg_images, buf_width, buf_height)?;
+                idx
             }
         };
 
-        trace!("got buffer index {} for background", idx);
-
         let surface = match &self.bg_surface {
             Some(s) => s,
             None => {
@@ -353,27 +943,14 @@ impl NLockSurface {
         };
 
         let buffer = &self.buffers[idx];
-        let context = &buffer.context;
-
-        context.save()?;
-        self.renderer.render_background(
-            config,
-            NLockRenderBackgroundArgs {
-                buf_height,
-                buf_width,
-                context,
-                image: bg_image,
-            },
-        )?;
-        context.restore()?;
-
         let mut buf_guard = buffer
             .lock_buffer()
             .ok_or(anyhow!("Failed to lock buffer {}", idx))?;
-        buf_guard.commit_to(surface, self.output_scale);
+        buf_guard.commit_to(surface, self.buffer_scale(), None);
 
         // Avoid rendering the background again
         self.bg_rendered = true;
+        self.bg_buffer = Some(idx);
 
         Ok(())
     }
@@ -382,14 +959,24 @@ impl NLockSurface {
         &mut self,
         config: &NLockConfig,
         auth_state: AuthState,
-        pwd_len: usize,
+        password: &str,
+        bg_images: &NLockBackgroundImages,
         shm: &wl_shm::WlShm,
         qh: &QueueHandle<NLockState>,
     ) -> Result<()> {
+        if self.composited {
+            return self.render_composited(config, auth_state, password, bg_images, shm, qh);
+        }
+
         let (buf_width, buf_height) = self.get_dimensions::<f64>()?;
+        let debug_info = config.general.debug_overlay.then(|| self.debug_info());
 
-        let idx = match self.get_buffer_idx(shm, qh) {
+        // After a resize, previous damage no longer applies
+        let resized = self.dimensions_changed();
+
+        let idx = match self.get_buffer_idx(wl_shm::Format::Argb8888, shm, qh) {
             Some(i) => i,
+            None if self.render_deferred => return Ok(()),
             None => {
                 bail!("Failed to obtain buffer for rendering overlay");
             }
@@ -416,39 +1003,259 @@ impl NLockSurface {
 
         // Save context to ensure transformations don't leak
         context.save()?;
-        self.renderer.render_overlay(
-            config,
-            NLockRenderOverlayArgs {
-                auth_state,
-                buf_height,
-                buf_width,
-                context,
-                pwd_len,
-            },
-        )?;
+        // Spans must never carry password-derived data
+        let drawn = trace_span!("render_overlay", output = self.index, buf_width, buf_height)
+            .in_scope(|| {
+                self.renderer.render_overlay(
+                    config,
+                    NLockRenderOverlayArgs {
+                        auth_state,
+                        buf_height,
+                        buf_width,
+                        context,
+                        debug_info: debug_info.as_deref(),
+                        password,
+                    },
+                )
+            })?;
         context.restore()?;
 
+        let damage = Self::overlay_damage(self.last_overlay_damage.take(), &drawn, resized);
+
         // Ensure subsurface position is always set to 0,0
         subsurface.set_position(0, 0);
 
-        let mut buf_guard = buffer
+        buffer
             .lock_buffer()
-            .ok_or(anyhow!("Failed to lock buffer {}", idx))?;
-        buf_guard.commit_to(surface, self.output_scale);
+            .ok_or(anyhow!("Failed to lock buffer {}", idx))?
+            .commit_to(surface, self.buffer_scale(), damage.as_deref());
+
+        self.last_overlay_damage = Some(drawn);
+
+        self.overlay_held
+            .retain(|&i| self.buffers[i].state.in_use.load(Ordering::Acquire));
+        self.overlay_held.push(idx);
+
+        if config.general.overlay_fallback && self.overlay_held.len() > OVERLAY_STUCK_COMMITS {
+            warn!(
+                "Overlay buffers for output '{}' are never released, drawing the overlay onto the background instead",
+                self.output_name.as_deref().unwrap_or("")
+            );
+            self.start_compositing();
+            return self.render_composited(config, auth_state, password, bg_images, shm, qh);
+        }
+
+        Ok(())
+    }
+
+    /// Damage for an overlay frame. Anything outside of what was drawn this
+    /// frame, and the last frame, is unchanged, so only that needs to be
+    /// damaged.
+    fn overlay_damage(
+        last: Option<Vec<cairo::RectangleInt>>,
+        drawn: &[cairo::RectangleInt],
+        resized: bool,
+    ) -> Option<Vec<cairo::RectangleInt>> {
+        let damage = match last {
+            Some(mut last) if !resized => {
+                last.extend(drawn.iter().cloned());
+                Some(last)
+            }
+            _ => None,
+        };
+
+        if let Some(rects) = &damage {
+            let area: i64 = rects
+                .iter()
+                .map(|rect| rect.width() as i64 * rect.height() as i64)
+                .sum();
+            debug!(
+                "Overlay damage: {} rectangles, {} pixels",
+                rects.len(),
+                area
+            );
+        }
+
+        damage
+    }
+
+    /// Stop using the overlay subsurface, and draw the overlay onto the
+    /// background surface from the next render on
+    fn start_compositing(&mut self) {
+        if let Some(surface) = &self.ov_surface {
+            surface.attach(None, 0, 0);
+            surface.commit();
+        }
+
+        self.composited = true;
+        self.overlay_held.clear();
+        self.invalidate();
+    }
+
+    /// Render the overlay over a copy of the background, and commit both to
+    /// the background surface, for compositors that don't present the overlay
+    /// subsurface.
+    fn render_composited(
+        &mut self,
+        config: &NLockConfig,
+        auth_state: AuthState,
+        password: &str,
+        bg_images: &NLockBackgroundImages,
+        shm: &wl_shm::WlShm,
+        qh: &QueueHandle<NLockState>,
+    ) -> Result<()> {
+        let (buf_width, buf_height) = self.get_dimensions::<f64>()?;
+        let debug_info = config.general.debug_overlay.then(|| self.debug_info());
+        let resized = self.dimensions_changed();
+
+        // The plain background is only drawn once, then copied each frame
+        let bg_idx = match self.composite_bg {
+            Some(i)
+                if self.buffers[i].width as f64 == buf_width
+                    && self.buffers[i].height as f64 == buf_height =>
+            {
+                i
+            }
+            _ => {
+                self.composite_bg = None;
+                let i = match self.get_buffer_idx(self.bg_format, shm, qh) {
+                    Some(i) => i,
+                    None if self.render_deferred => return Ok(()),
+                    None => bail!("Failed to obtain buffer for composited background"),
+                };
+
+                self.draw_background(i, config, bg_images, buf_width, buf_height)?;
+                self.composite_bg = Some(i);
+                i
+            }
+        };
+
+        let idx = match self.get_buffer_idx(self.bg_format, shm, qh) {
+            Some(i) => i,
+            None if self.render_deferred => return Ok(()),
+            None => bail!("Failed to obtain buffer for rendering composited overlay"),
+        };
+
+        trace!("got buffer index {} for composited overlay", idx);
+
+        let buffer = &self.buffers[idx];
+        let context = &buffer.context;
+
+        context.save()?;
+        context.set_operator(cairo::Operator::Source);
+        context.set_source_surface(&self.buffers[bg_idx].surface, 0.0, 0.0)?;
+        context.paint()?;
+        context.set_operator(cairo::Operator::Over);
+
+        // The overlay clears what it draws into, so draw it into a group
+        context.push_group();
+        let drawn = trace_span!("render_overlay", output = self.index, buf_width, buf_height)
+            .in_scope(|| {
+                self.renderer.render_overlay(
+                    config,
+                    NLockRenderOverlayArgs {
+                        auth_state,
+                        buf_height,
+                        buf_width,
+                        context,
+                        debug_info: debug_info.as_deref(),
+                        password,
+                    },
+                )
+            });
+        context.pop_group_to_source()?;
+        context.paint()?;
+        context.restore()?;
+        let drawn = drawn?;
+
+        let damage = Self::overlay_damage(self.last_overlay_damage.take(), &drawn, resized);
+
+        let surface = match &self.bg_surface {
+            Some(s) => s,
+            None => {
+                bail!("wl_surface not set when attempting composited overlay render");
+            }
+        };
+
+        self.buffers[idx]
+            .lock_buffer()
+            .ok_or(anyhow!("Failed to lock buffer {}", idx))?
+            .commit_to(surface, self.buffer_scale(), damage.as_deref());
+
+        self.last_overlay_damage = Some(drawn);
+        self.bg_rendered = true;
 
         Ok(())
     }
 
+    /// Render the background and overlay again in full on the next render
+    pub fn invalidate(&mut self) {
+        self.bg_rendered = false;
+        self.prerendered_bg = None;
+        self.composite_bg = None;
+        self.last_overlay_damage = None;
+    }
+
+    /// Destroy the lock surface and its Wayland surfaces, so they can be
+    /// created again for a new session lock.
+    pub fn destroy_surface(&mut self) {
+        if let Some(lock_surface) = self.lock_surface.take() {
+            lock_surface.destroy();
+        }
+
+        if let Some(subsurface) = self.subsurface.take() {
+            subsurface.destroy();
+        }
+
+        if let Some(ov_surface) = self.ov_surface.take() {
+            ov_surface.destroy();
+        }
+
+        if let Some(bg_surface) = self.bg_surface.take() {
+            bg_surface.destroy();
+        }
+
+        // Buffers may still be held by the destroyed surfaces
+        self.buffers.iter_mut().for_each(|buf| buf.destroy());
+        self.buffers.clear();
+
+        if let Some(mut pool) = self.shm_pool.take() {
+            pool.destroy();
+        }
+        self.prerendered_bg = None;
+        self.bg_buffer = None;
+        self.overlay_held.clear();
+        self.composited = false;
+        self.composite_bg = None;
+
+        self.last_width = None;
+        self.last_height = None;
+        self.last_overlay_damage = None;
+        self.created = false;
+        self.bg_rendered = false;
+    }
+
     pub fn destroy(&mut self) {
         if let Some(lock_surface) = &self.lock_surface {
             lock_surface.destroy();
         }
 
         self.buffers.iter_mut().for_each(|buf| buf.destroy());
+        if let Some(mut pool) = self.shm_pool.take() {
+            pool.destroy();
+        }
         self.output.release();
     }
 }
 
+/// Find the largest scale, up to `scale`, at which a `width`x`height` surface
+/// fits within `max` buffer pixels each way, if there is one
+fn fitting_scale(width: u32, height: u32, scale: i32, max: u32) -> Option<i32> {
+    (1..=scale.max(1))
+        .rev()
+        .find(|&s| width.saturating_mul(s as u32) <= max && height.saturating_mul(s as u32) <= max)
+}
+
 impl Dispatch<ext_session_lock_surface_v1::ExtSessionLockSurfaceV1, usize> for NLockState {
     fn event(
         state: &mut Self,
@@ -467,22 +1274,78 @@ impl Dispatch<ext_session_lock_surface_v1::ExtSessionLockSurfaceV1, usize> for N
         {
             let surface = &mut state.surfaces[*data];
 
+            // Always ack, a configure left unacked stalls the lock
+            lock_surface.ack_configure(serial);
+
             if let Err(e) = surface.set_raw_dimensions(width, height) {
-                warn!("Failed to set surface dimensions: {e}");
+                error!("Failed to set surface dimensions: {e}");
                 return;
             }
 
-            lock_surface.ack_configure(serial);
-
+            // A configure only changes the surface, anything typed so far is
+            // kept and drawn again at the new size
             let auth_state = state.auth_state.clone().load(Ordering::Relaxed);
             surface.render(
                 &state.config,
                 auth_state,
-                state.password.chars().count(),
-                state.background_image.as_ref(),
+                &state.password,
+                &state.background_images,
                 shm,
                 qh,
             );
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use wayland_client::{Connection, Proxy};
+
+    use super::*;
+
+    fn test_surface(scale: i32, max_dimension: u32) -> NLockSurface {
+        // Nothing is ever sent, so the other end needn't be a compositor
+        let (socket, _) = UnixStream::pair().unwrap();
+        let connection = Connection::from_socket(socket).unwrap();
+        let output = wl_output::WlOutput::inert(connection.backend().downgrade());
+
+        let mut surface = NLockSurface::new(output, 0);
+        surface.set_max_dimension(max_dimension);
+        surface.set_scale(scale).unwrap();
+        surface
+    }
+
+    #[test]
+    fn fitting_scale_boundaries() {
+        assert_eq!(fitting_scale(1920, 1080, 2, 16384), Some(2));
+        assert_eq!(fitting_scale(8192, 1080, 2, 16384), Some(2));
+        assert_eq!(fitting_scale(8193, 1080, 2, 16384), Some(1));
+        assert_eq!(fitting_scale(16384, 16384, 1, 16384), Some(1));
+        assert_eq!(fitting_scale(16385, 1080, 2, 16384), None);
+        assert_eq!(fitting_scale(1920, u32::MAX, 1, 16384), None);
+    }
+
+    #[test]
+    fn oversized_configure_renders_at_a_lower_scale() {
+        let mut surface = test_surface(2, 4096);
+        surface.set_raw_dimensions(3000, 2000).unwrap();
+
+        // A buffer of the configured size at scale 1, never past the maximum
+        assert_eq!(surface.get_dimensions::<u32>().unwrap(), (3000, 2000));
+        assert_eq!(surface.buffer_scale(), 1);
+    }
+
+    #[test]
+    fn oversized_configure_allocates_nothing() {
+        let mut surface = test_surface(1, 4096);
+        surface.set_raw_dimensions(1920, 1080).unwrap();
+
+        assert!(surface.set_raw_dimensions(4097, 1080).is_err());
+
+        // The previous size is gone too, so no buffer is allocated at all
+        assert!(surface.get_dimensions::<u32>().is_err());
+        assert!(surface.buffers.is_empty());
+    }
+}
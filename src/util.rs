@@ -2,8 +2,10 @@
 // Copyright (C) 2026, Nathan Gill
 
 use std::{
+    fs,
     io::{self, Read},
     os::fd::OwnedFd,
+    path::Path,
     str::FromStr,
 };
 
@@ -12,6 +14,7 @@ use nix::{
     fcntl::OFlag,
     libc,
     sys::{
+        memfd::{MFdFlags, memfd_create},
         mman::{shm_open, shm_unlink},
         stat::Mode,
     },
@@ -19,12 +22,21 @@ use nix::{
 };
 use serde::{Deserialize, de};
 use tracing::debug;
+use xkbcommon::xkb;
+use zeroize::Zeroize;
 
 #[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
 #[serde(rename_all = "lowercase")]
 pub enum BackgroundType {
     Color,
     Image,
+    /// Plain black, as compositors show with no lock surface at all
+    ///
+    /// This still renders a full size background buffer. A lock surface must
+    /// commit a buffer of its configured size before the compositor shows
+    /// it, and the overlay subsurface is only shown over a parent with
+    /// content, so the black can't be left out.
+    None,
 }
 
 #[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
@@ -37,6 +49,131 @@ pub enum BackgroundImageScale {
     Tile,
 }
 
+/// Placement of a scaled image along the axis it overflows, or is letterboxed
+/// on. Start is the top or left edge, end is the bottom or right edge.
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageAnchor {
+    Start,
+    Center,
+    End,
+}
+
+impl ImageAnchor {
+    /// Fraction of the leftover space placed before the image
+    pub fn factor(self) -> f64 {
+        match self {
+            Self::Start => 0.0,
+            Self::Center => 0.5,
+            Self::End => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BufferFormat {
+    Argb,
+    Xrgb,
+    /// Xrgb if the background is opaque and the compositor supports it,
+    /// otherwise Argb
+    Auto,
+}
+
+/// Where the frame border is drawn relative to the surface edge
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StrokeAlign {
+    /// Whole border is inside the surface
+    Inside,
+    /// Border is centered on the surface edge, so half of it is cut off
+    Center,
+    /// Whole border is outside the frame edge, only visible with an inset
+    Outside,
+}
+
+/// How shared memory for buffers is created
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ShmBackend {
+    /// Anonymous file from `memfd_create`, falling back to POSIX shm
+    Memfd,
+    /// Named file from `shm_open`, usually under `/dev/shm`
+    Posix,
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FontAntialias {
+    Auto,
+    Subpixel,
+    Grayscale,
+    None,
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ShapeAntialias {
+    Best,
+    Good,
+    Fast,
+    None,
+}
+
+impl From<ShapeAntialias> for cairo::Antialias {
+    fn from(value: ShapeAntialias) -> Self {
+        match value {
+            ShapeAntialias::Best => Self::Best,
+            ShapeAntialias::Good => Self::Good,
+            ShapeAntialias::Fast => Self::Fast,
+            ShapeAntialias::None => Self::None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SecondaryMode {
+    Full,
+    Background,
+    Blank,
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SubmitModifiers {
+    Any,
+    None,
+    Shift,
+    Ctrl,
+    Alt,
+    Super,
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EmptyBackspaceFeedback {
+    None,
+    Flash,
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum InputEcho {
+    None,
+    Mask,
+    Clear,
+    Blocks,
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum InputOverflow {
+    Clip,
+    Ellipsis,
+    Count,
+}
+
 #[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
 #[serde(rename_all = "lowercase")]
 pub enum InputVisibility {
@@ -108,6 +245,80 @@ impl<'de> Deserialize<'de> for Rgba {
     }
 }
 
+/// A key combined with modifiers, e.g. "Ctrl+L". "none" disables the binding.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyBinding {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub logo: bool,
+    /// Canonical keysym name, or `None` if the binding is disabled
+    pub key: Option<String>,
+}
+
+impl KeyBinding {
+    /// Check whether a keysym, with the given modifiers held, triggers this
+    /// binding. Keys are compared ignoring case, as Shift changes the keysym.
+    pub fn matches(
+        &self,
+        keysym: xkb::Keysym,
+        shift: bool,
+        ctrl: bool,
+        alt: bool,
+        logo: bool,
+    ) -> bool {
+        let Some(key) = &self.key else {
+            return false;
+        };
+
+        (self.shift, self.ctrl, self.alt, self.logo) == (shift, ctrl, alt, logo)
+            && xkb::keysym_get_name(keysym).eq_ignore_ascii_case(key)
+    }
+}
+
+impl FromStr for KeyBinding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut binding = Self::default();
+        if s.is_empty() || s.eq_ignore_ascii_case("none") {
+            return Ok(binding);
+        }
+
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or_default();
+
+        for part in parts {
+            match part.to_ascii_lowercase().as_str() {
+                "shift" => binding.shift = true,
+                "ctrl" | "control" => binding.ctrl = true,
+                "alt" => binding.alt = true,
+                "super" | "logo" => binding.logo = true,
+                _ => return Err(format!("unknown modifier \"{part}\"")),
+            }
+        }
+
+        let keysym = xkb::keysym_from_name(key, xkb::KEYSYM_CASE_INSENSITIVE);
+        if keysym == xkb::Keysym::NoSymbol {
+            return Err(format!("unknown key \"{key}\""));
+        }
+
+        binding.key = Some(xkb::keysym_get_name(keysym));
+        Ok(binding)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyBinding {
+    fn deserialize<D>(d: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        let binding = Self::from_str(&s).map_err(de::Error::custom)?;
+        Ok(binding)
+    }
+}
+
 #[derive(Debug, Deserialize, Copy, Clone, ValueEnum)]
 #[serde(rename_all = "lowercase")]
 pub enum FontSlant {
@@ -126,6 +337,24 @@ impl From<FontSlant> for pango::Style {
     }
 }
 
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TextDirection {
+    Auto,
+    Ltr,
+    Rtl,
+}
+
+impl From<TextDirection> for pango::Direction {
+    fn from(value: TextDirection) -> Self {
+        match value {
+            TextDirection::Auto => Self::Neutral,
+            TextDirection::Ltr => Self::Ltr,
+            TextDirection::Rtl => Self::Rtl,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Copy, Clone, ValueEnum)]
 #[serde(rename_all = "lowercase")]
 pub enum FontWeight {
@@ -183,7 +412,16 @@ impl From<LogLevel> for tracing::level_filters::LevelFilter {
     }
 }
 
-pub fn open_shm() -> Option<OwnedFd> {
+pub fn open_shm(backend: ShmBackend) -> Option<OwnedFd> {
+    // memfd needs no name in the shm namespace, so can't collide, or be
+    // limited by the size of /dev/shm
+    if backend == ShmBackend::Memfd {
+        match memfd_create(c"nlock", MFdFlags::MFD_CLOEXEC) {
+            Ok(fd) => return Some(fd),
+            Err(e) => debug!("memfd_create failed, falling back to shm_open: {e}"),
+        }
+    }
+
     let mut retries = 100;
 
     loop {
@@ -223,6 +461,52 @@ pub fn pango_pixels(d: i32) -> i32 {
 // Pango scale factor
 pub const PANGO_SCALE: i32 = 1024;
 
+/// Where the kernel lists power supplies, such as AC adapters and batteries
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// Check whether the system is running on battery, meaning it has a battery,
+/// and no AC adapter is online. Systems without batteries, or where the
+/// power supplies can't be read, count as on AC power.
+pub fn on_battery() -> bool {
+    let Ok(entries) = fs::read_dir(POWER_SUPPLY_DIR) else {
+        return false;
+    };
+
+    let read = |path: &Path, name: &str| {
+        fs::read_to_string(path.join(name))
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default()
+    };
+
+    let mut has_battery = false;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match read(&path, "type").as_str() {
+            "Mains" | "USB" if read(&path, "online") == "1" => return false,
+            "Battery" => has_battery = true,
+            _ => {}
+        }
+    }
+
+    has_battery
+}
+
+/// Empty a password, zeroing its whole allocation. `String::clear` only sets
+/// the length to 0, leaving the old bytes, and any removed with `pop`, in the
+/// spare capacity until the allocation is dropped.
+pub fn scrub_password(password: &mut String) {
+    password.zeroize();
+
+    // Safety: the spare capacity was initialised by zeroize
+    debug_assert!(
+        unsafe { password.as_mut_vec() }
+            .spare_capacity_mut()
+            .iter()
+            .all(|b| unsafe { b.assume_init() } == 0),
+        "password not zeroed after clearing"
+    );
+}
+
 // This helper function just checks if an `std::io::Error` was an EINTR
 pub fn is_eintr(err: &std::io::Error) -> bool {
     match err.raw_os_error() {
@@ -248,3 +532,64 @@ where
 
     Ok(true)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_binding_parses_modifiers_and_key() {
+        let binding = KeyBinding::from_str("Ctrl+L").unwrap();
+        assert!(binding.ctrl);
+        assert!(!binding.shift && !binding.alt && !binding.logo);
+        assert!(binding.matches(xkb::Keysym::l, false, true, false, false));
+        assert!(!binding.matches(xkb::Keysym::l, false, false, false, false));
+    }
+
+    #[test]
+    fn key_binding_ignores_case_and_spaces() {
+        let binding = KeyBinding::from_str(" control + SHIFT + escape ").unwrap();
+        assert!(binding.ctrl && binding.shift);
+        assert_eq!(binding.key.as_deref(), Some("Escape"));
+
+        let binding = KeyBinding::from_str("super+Return").unwrap();
+        assert!(binding.logo);
+        assert_eq!(binding.key.as_deref(), Some("Return"));
+    }
+
+    #[test]
+    fn key_binding_none_is_disabled() {
+        for s in ["", "none", "None"] {
+            let binding = KeyBinding::from_str(s).unwrap();
+            assert_eq!(binding, KeyBinding::default());
+            assert!(!binding.matches(xkb::Keysym::l, false, false, false, false));
+        }
+    }
+
+    #[test]
+    fn key_binding_rejects_unknown_names() {
+        assert!(KeyBinding::from_str("Hyper+L").is_err());
+        assert!(KeyBinding::from_str("Ctrl+NotAKey").is_err());
+        assert!(KeyBinding::from_str("Ctrl+").is_err());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn scrub_password_zeroes_the_whole_allocation() {
+        let mut password = String::with_capacity(32);
+        password.push_str("hunter2hunter2");
+        // Popped characters stay in the spare capacity
+        password.pop();
+        password.pop();
+        let capacity = password.capacity();
+
+        // Panics through the debug_assert if any spare byte is left
+        scrub_password(&mut password);
+
+        assert!(password.is_empty());
+        assert_eq!(password.capacity(), capacity);
+        // Safety: every byte up to the capacity was written by zeroize
+        let bytes = unsafe { std::slice::from_raw_parts(password.as_ptr(), capacity) };
+        assert!(bytes.iter().all(|&b| b == 0));
+    }
+}